@@ -0,0 +1,165 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const PRG: &str = env!("CARGO_BIN_EXE_header");
+
+// --------------------------------------------------
+// 测试数据放在 target 下的临时目录中，已经存在时直接复用
+fn corpus_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bench-corpus");
+    fs::create_dir_all(&dir).expect("failed to create corpus directory");
+    dir
+}
+
+// 生成 num_lines 行长短不一的文本
+fn gen_lines(path: &Path, num_lines: usize) {
+    if path.exists() {
+        return;
+    }
+    let mut out = BufWriter::new(File::create(path).expect("failed to create corpus file"));
+    for i in 0..num_lines {
+        let width = 20 + i * 7919 % 100;
+        writeln!(out, "{:>8} {}", i, "x".repeat(width)).expect("failed to write corpus");
+    }
+    out.flush().expect("failed to write corpus");
+}
+
+// 生成 num_lines 行以 \r\n 结尾的文本，按 \n 和按 \r\n 分行得到的行数相同
+fn gen_crlf_lines(path: &Path, num_lines: usize) {
+    if path.exists() {
+        return;
+    }
+    let mut out = BufWriter::new(File::create(path).expect("failed to create corpus file"));
+    for i in 0..num_lines {
+        let width = 20 + i * 7919 % 100;
+        write!(out, "{:>8} {}\r\n", i, "x".repeat(width)).expect("failed to write corpus");
+    }
+    out.flush().expect("failed to write corpus");
+}
+
+// 生成 num_files 个各有 num_lines 行的小文件
+fn gen_small_files(dir: &Path, num_files: usize, num_lines: usize) -> Vec<String> {
+    fs::create_dir_all(dir).expect("failed to create corpus directory");
+    (0..num_files)
+        .map(|i| {
+            let path = dir.join(format!("{:04}.txt", i));
+            gen_lines(&path, num_lines);
+            path.to_string_lossy().into_owned()
+        })
+        .collect()
+}
+
+// 运行一次，输出丢弃
+fn header<S: AsRef<std::ffi::OsStr>>(args: &[S]) {
+    let status = Command::new(PRG)
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .expect("failed to run header");
+    assert!(status.success());
+}
+
+// --------------------------------------------------
+fn line_mode(c: &mut Criterion) {
+    let file = corpus_dir().join("lines.txt");
+    gen_lines(&file, 200_000);
+    let file = file.to_string_lossy().into_owned();
+    let mut group = c.benchmark_group("line_mode");
+    for num_lines in [10, 10_000, 100_000] {
+        group.throughput(Throughput::Elements(num_lines as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_lines),
+            &num_lines,
+            |b, n| b.iter(|| header(&["-n", &n.to_string(), &file])),
+        );
+    }
+    group.bench_function("all_but_last", |b| {
+        b.iter(|| header(&["-n", "-1000", &file]))
+    });
+    group.finish();
+}
+
+fn byte_mode(c: &mut Criterion) {
+    let file = corpus_dir().join("lines.txt");
+    gen_lines(&file, 200_000);
+    let file = file.to_string_lossy().into_owned();
+    let mut group = c.benchmark_group("byte_mode");
+    for num_bytes in [1024, 1024 * 1024, 8 * 1024 * 1024] {
+        group.throughput(Throughput::Bytes(num_bytes as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_bytes),
+            &num_bytes,
+            |b, n| b.iter(|| header(&["-c", &n.to_string(), &file])),
+        );
+    }
+    group.bench_function("mmap", |b| {
+        b.iter(|| header(&["--mmap", "-c", "8M", &file]))
+    });
+    group.bench_function("all_but_last", |b| {
+        b.iter(|| header(&["-c", "-1024", &file]))
+    });
+    group.finish();
+}
+
+// 单字节分隔符走 memchr 整块扫描，两字节分隔符逐行读取，两者输出相同的内容
+fn line_scan(c: &mut Criterion) {
+    let file = corpus_dir().join("crlf.txt");
+    gen_crlf_lines(&file, 1_000_000);
+    let bytes = fs::metadata(&file).expect("failed to stat corpus").len();
+    let file = file.to_string_lossy().into_owned();
+    let mut group = c.benchmark_group("line_scan");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes));
+    group.bench_function("memchr", |b| b.iter(|| header(&["-n", "1000000", &file])));
+    group.bench_function("per_line", |b| {
+        b.iter(|| header(&["--delimiter", "\\r\\n", "-n", "1000000", &file]))
+    });
+    group.bench_function("skip_memchr", |b| {
+        b.iter(|| header(&["-n", "+999990", &file]))
+    });
+    group.finish();
+}
+
+fn many_small_files(c: &mut Criterion) {
+    let files = gen_small_files(&corpus_dir().join("small"), 500, 50);
+    let mut group = c.benchmark_group("many_small_files");
+    group.throughput(Throughput::Elements(files.len() as u64));
+    group.bench_function("sequential", |b| b.iter(|| header(&files)));
+    group.bench_function("jobs_4", |b| {
+        b.iter(|| {
+            let mut args = vec!["-j".to_string(), "4".to_string()];
+            args.extend(files.iter().cloned());
+            header(&args)
+        })
+    });
+    group.finish();
+}
+
+fn huge_single_file(c: &mut Criterion) {
+    let file = corpus_dir().join("huge.txt");
+    gen_lines(&file, 3_000_000);
+    let file = file.to_string_lossy().into_owned();
+    let mut group = c.benchmark_group("huge_single_file");
+    group.sample_size(10);
+    group.bench_function("lines_1m", |b| b.iter(|| header(&["-n", "1000000", &file])));
+    group.bench_function("bytes_range", |b| {
+        b.iter(|| header(&["-c", "1000-100000000", &file]))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    line_mode,
+    byte_mode,
+    line_scan,
+    many_small_files,
+    huge_single_file
+);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+// 生成 --version 输出的构建信息：git 提交、构建日期、启用的 cargo 特性和目标平台
+use std::{env, fs, path::Path, process::Command};
+
+// 与 S3 请求签名共用的公历日期换算
+#[path = "src/civil.rs"]
+mod civil;
+
+fn main() {
+    let mut info = format!(
+        "{}\ncommit: {}",
+        env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+        git_commit()
+    );
+    if let Some(date) = build_date() {
+        info.push_str(&format!("\nbuilt: {}", date));
+    }
+    info.push_str(&format!(
+        "\nfeatures: {}\ntarget: {}",
+        features(),
+        env::var("TARGET").unwrap_or_default()
+    ));
+    // 多行内容不能通过 cargo:rustc-env 传递，写入 OUT_DIR 后由 include_str! 读取
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(Path::new(&out_dir).join("long_version.txt"), info)
+        .expect("failed to write long_version.txt");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+// 当前提交的短哈希，工作区有改动时加上 -dirty；不在 git 仓库中（例如从 crates.io 构建）时为 unknown
+fn git_commit() -> String {
+    // 提交、切换分支或暂存改动后重新运行；只登记存在的文件，否则 cargo 每次构建都会重新运行
+    let git_dir = Path::new(".git");
+    if git_dir.is_dir() {
+        let mut watched = vec![
+            "HEAD".to_string(),
+            "packed-refs".to_string(),
+            "index".to_string(),
+        ];
+        if let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) {
+            if let Some(branch) = head.trim().strip_prefix("ref: ") {
+                watched.push(branch.to_string());
+            }
+        }
+        for file in watched {
+            if git_dir.join(&file).exists() {
+                println!("cargo:rerun-if-changed=.git/{}", file);
+            }
+        }
+    }
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(commit) if !commit.is_empty() => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit
+            }
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+// 构建日期（UTC），只取自 SOURCE_DATE_EPOCH 以便重现构建；没有设置时不输出
+fn build_date() -> Option<String> {
+    let secs = env::var("SOURCE_DATE_EPOCH").ok()?.parse::<i64>().ok()?;
+    let (year, month, day) = civil::civil_from_days(secs.div_euclid(86400));
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+// 启用的特性（不含 default），按名称排序，以逗号分隔
+fn features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .filter(|&feature| feature != "DEFAULT")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    match features.is_empty() {
+        true => "none".to_string(),
+        false => features.join(", "),
+    }
+}
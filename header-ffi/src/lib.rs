@@ -0,0 +1,5 @@
+//! 把 header 的 ffi 特性导出的 C 接口构建成动态库（Linux 上为 libheader_ffi.so）
+//! 头文件是仓库根目录下的 include/header.h
+
+#[cfg(unix)]
+pub use header::ffi::*;
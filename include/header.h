@@ -0,0 +1,58 @@
+#ifndef HEADER_H
+#define HEADER_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ 按行计数
+ */
+#define HEADER_UNIT_LINES 0
+
+/*
+ 按字节计数
+ */
+#define HEADER_UNIT_BYTES 1
+
+/*
+ header_head_file 的返回值
+ */
+typedef enum HeaderStatus {
+  HEADER_STATUS_OK = 0,
+  HEADER_STATUS_INVALID_ARGUMENT = 1,
+  HEADER_STATUS_NOT_FOUND = 2,
+  HEADER_STATUS_PERMISSION_DENIED = 3,
+  HEADER_STATUS_OPEN_FAILED = 4,
+  HEADER_STATUS_IO_ERROR = 5,
+} HeaderStatus;
+
+/*
+ 要输出的范围：开头的 count 行或 count 个字节
+ */
+typedef struct HeaderLimits {
+  int unit;
+  uint64_t count;
+} HeaderLimits;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ 把 path 指向的本地文件开头的一部分原样写到 fd
+ 不解压、不识别远程地址，也不会关闭 fd
+
+ # Safety
+ path 必须是以 NUL 结尾的有效字符串，fd 在调用期间必须是打开的、可写的文件描述符
+ */
+HeaderStatus header_head_file(const char *path, HeaderLimits limits, int fd);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* HEADER_H */
@@ -13,6 +13,12 @@ for FILE in $INPUTS/*.txt; do
     head -c 1 $FILE > ${OUT_DIR}/${BASENAME}.c1.out
     head -c 2 $FILE > ${OUT_DIR}/${BASENAME}.c2.out
     head -c 4 $FILE > ${OUT_DIR}/${BASENAME}.c4.out
+    head -v   $FILE > ${OUT_DIR}/${BASENAME}.v.out
+    head -n -2 $FILE > ${OUT_DIR}/${BASENAME}.n-2.out
+    head -c -4 $FILE > ${OUT_DIR}/${BASENAME}.c-4.out
+    tail      $FILE > ${OUT_DIR}/${BASENAME}.tail.out
+    tail -n 2 $FILE > ${OUT_DIR}/${BASENAME}.tail.n2.out
+    tail -n +3 $FILE > ${OUT_DIR}/${BASENAME}.tail.n-2.out
 done
 
 ALL="$INPUTS/empty.txt $INPUTS/one.txt $INPUTS/two.txt $INPUTS/three.txt \
@@ -22,4 +28,14 @@ head -n 2 $ALL > $OUT_DIR/all.n2.out
 head -n 4 $ALL > $OUT_DIR/all.n4.out
 head -c 1 $ALL > $OUT_DIR/all.c1.out
 head -c 2 $ALL > $OUT_DIR/all.c2.out
-head -c 4 $ALL > $OUT_DIR/all.c4.out
\ No newline at end of file
+head -c 4 $ALL > $OUT_DIR/all.c4.out
+head -q      $ALL > $OUT_DIR/all.q.out
+head -q -n 2 $ALL > $OUT_DIR/all.q.n2.out
+head -q -c 4 $ALL > $OUT_DIR/all.q.c4.out
+head -q -v   $ALL > $OUT_DIR/all.v.out
+head -n -2 $ALL > $OUT_DIR/all.n-2.out
+head -c -4 $ALL > $OUT_DIR/all.c-4.out
+head -n 0 $ALL > $OUT_DIR/all.n0.out
+head -c 0 $ALL > $OUT_DIR/all.c0.out
+tail      $ALL > $OUT_DIR/all.tail.out
+tail -n 2 $ALL > $OUT_DIR/all.tail.n2.out
@@ -0,0 +1,80 @@
+//! 供 C 程序调用的接口，开启 ffi 特性时可用，由 header-ffi 构建成 cdylib 导出
+//! 对应的头文件是 include/header.h，由 cbindgen 按 cbindgen.toml 生成：
+//! `cbindgen --config cbindgen.toml --output include/header.h`
+
+use std::{
+    ffi::{c_char, c_int, CStr, OsStr},
+    fs::File,
+    io::{BufReader, ErrorKind},
+    mem::ManuallyDrop,
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::Path,
+};
+
+use crate::{head_bytes, head_lines};
+
+/// 按行计数
+pub const HEADER_UNIT_LINES: c_int = 0;
+/// 按字节计数
+pub const HEADER_UNIT_BYTES: c_int = 1;
+
+/// 要输出的范围：开头的 count 行或 count 个字节
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderLimits {
+    pub unit: c_int, // HEADER_UNIT_LINES 或 HEADER_UNIT_BYTES
+    pub count: u64,  // 行数或字节数
+}
+
+/// header_head_file 的返回值
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderStatus {
+    Ok = 0,
+    InvalidArgument = 1,  // path 为空指针、unit 不认识或 fd 为负数
+    NotFound = 2,         // 文件不存在
+    PermissionDenied = 3, // 没有读取文件的权限
+    OpenFailed = 4,       // 其他原因导致无法打开文件
+    IoError = 5,          // 读取文件或写入 fd 时出错，可能已经写出了一部分内容
+}
+
+/// 把 path 指向的本地文件开头的一部分原样写到 fd
+/// 不解压、不识别远程地址，也不会关闭 fd
+///
+/// # Safety
+/// path 必须是以 NUL 结尾的有效字符串，fd 在调用期间必须是打开的、可写的文件描述符
+#[no_mangle]
+pub unsafe extern "C" fn header_head_file(
+    path: *const c_char,
+    limits: HeaderLimits,
+    fd: c_int,
+) -> HeaderStatus {
+    if path.is_null() || fd < 0 {
+        return HeaderStatus::InvalidArgument;
+    }
+    // SAFETY: 调用者保证 path 是以 NUL 结尾的有效字符串
+    let path = Path::new(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+    ));
+    let file = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            return match e.kind() {
+                ErrorKind::NotFound => HeaderStatus::NotFound,
+                ErrorKind::PermissionDenied => HeaderStatus::PermissionDenied,
+                _ => HeaderStatus::OpenFailed,
+            }
+        }
+    };
+    // SAFETY: 调用者保证 fd 有效；ManuallyDrop 保证这里不会关闭它
+    let mut out = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let result = match limits.unit {
+        HEADER_UNIT_LINES => head_lines(file, &mut *out, limits.count),
+        HEADER_UNIT_BYTES => head_bytes(file, &mut *out, limits.count),
+        _ => return HeaderStatus::InvalidArgument,
+    };
+    match result {
+        Ok(()) => HeaderStatus::Ok,
+        Err(_) => HeaderStatus::IoError,
+    }
+}
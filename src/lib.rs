@@ -1,63 +1,1229 @@
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Read},
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    env,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write},
+    net::{TcpStream, ToSocketAddrs},
+    ops::ControlFlow,
+    path::{Component, Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
 };
 
-use anyhow::{anyhow, Context, Result};
-use clap::{App, Arg};
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use rand::Rng;
+use regex::bytes::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+#[cfg(feature = "s3")]
+mod civil;
+#[cfg(all(feature = "ffi", unix))]
+pub mod ffi;
+
+// 这些压缩格式依赖 C 库，wasm32-wasi 下没有可用的 C 工具链
+#[cfg(all(
+    target_os = "wasi",
+    any(feature = "zstd", feature = "xz", feature = "bzip2", feature = "http")
+))]
+compile_error!(
+    "zstd, xz, bzip2 and http are not supported on WASI; build with --no-default-features --features wasi"
+);
+
+/// 库接口返回的错误
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderError {
+    /// 计数、大小等数值参数无法解析，例如 `-n abc`
+    #[error("Failed to parse {what}: {value}: {reason}")]
+    ParseCount {
+        what: String,   // 参数的含义，例如 "lines count"
+        value: String,  // 无法解析的原始字符串
+        reason: String, // 无法解析的原因
+    },
+    /// 无法打开输入文件
+    #[error("Failed to open file: {}", path.display())]
+    OpenFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 输入是目录；用 -R 可以改为读取目录中的文件
+    #[error("{}: Is a directory", path.display())]
+    IsDirectory { path: PathBuf },
+    /// 命令行参数有误，或者请求了 --help / --version
+    #[error(transparent)]
+    Args(#[from] clap::Error),
+    /// 读写过程中的 I/O 错误
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// 参数组合、模式或输入内容不合法
+    #[error("{0}")]
+    Invalid(String),
+    /// 附加了说明的错误，source 为原始错误
+    #[error("{context}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<HeaderError>,
+    },
+    /// 依赖库（解压、网络、JSON 等）返回的错误
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// 库接口的返回类型
+pub type Result<T, E = HeaderError> = std::result::Result<T, E>;
+
+impl HeaderError {
+    fn parse_count(what: &str, value: &str, reason: impl Into<HeaderError>) -> Self {
+        HeaderError::ParseCount {
+            what: what.to_string(),
+            value: value.to_string(),
+            reason: error_chain(&reason.into()),
+        }
+    }
+
+    /// 是否因为输出的管道已经被关闭（例如 `header file | head -1`）而失败
+    pub fn is_broken_pipe(&self) -> bool {
+        match self {
+            HeaderError::Io(e) => e.kind() == io::ErrorKind::BrokenPipe,
+            HeaderError::Context { source, .. } => source.is_broken_pipe(),
+            _ => false,
+        }
+    }
+
+    /// 错误及其全部 source，用 ": " 连接，与 anyhow 的 `{:#}` 格式相同
+    pub fn full_message(&self) -> String {
+        error_chain(self)
+    }
+
+    /// 按 GNU head 的格式描述错误，不带程序名，
+    /// 例如 `cannot open 'x' for reading: No such file or directory`
+    pub fn gnu_message(&self) -> String {
+        let mut parts = Vec::new();
+        let mut current: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(e) = current {
+            match e.downcast_ref() {
+                Some(HeaderError::ParseCount { what, value, .. })
+                    if what == "lines count" || what == "bytes count" =>
+                {
+                    let unit = what.trim_end_matches(" count");
+                    parts.push(format!("invalid number of {}: '{}'", unit, value));
+                    break;
+                }
+                Some(HeaderError::OpenFile { path, source }) => {
+                    parts.push(format!(
+                        "cannot open '{}' for reading: {}",
+                        path.display(),
+                        strerror(source)
+                    ));
+                    break;
+                }
+                Some(HeaderError::IsDirectory { path }) => {
+                    parts.push(format!(
+                        "error reading '{}': Is a directory",
+                        path.display()
+                    ));
+                    break;
+                }
+                _ => {}
+            }
+            parts.push(strerror(e));
+            current = e.source();
+        }
+        parts.join(": ")
+    }
+
+    /// 按 BSD head 的格式描述错误，不带程序名，例如 `x: No such file or directory`
+    pub fn bsd_message(&self) -> String {
+        let mut parts = Vec::new();
+        let mut current: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(e) = current {
+            match e.downcast_ref() {
+                Some(HeaderError::ParseCount { what, value, .. })
+                    if what == "lines count" || what == "bytes count" =>
+                {
+                    let unit = what.trim_end_matches("s count");
+                    parts.push(format!("illegal {} count -- {}", unit, value));
+                    break;
+                }
+                Some(HeaderError::OpenFile { path, source }) => {
+                    parts.push(format!("{}: {}", path.display(), strerror(source)));
+                    break;
+                }
+                Some(e @ HeaderError::IsDirectory { .. }) => {
+                    parts.push(e.to_string());
+                    break;
+                }
+                _ => {}
+            }
+            parts.push(strerror(e));
+            current = e.source();
+        }
+        parts.join(": ")
+    }
+}
+
+// 依赖库的错误统一归入 Other
+macro_rules! other_errors {
+    ($($error:ty),* $(,)?) => {
+        $(impl From<$error> for HeaderError {
+            fn from(e: $error) -> Self {
+                HeaderError::Other(Box::new(e))
+            }
+        })*
+    };
+}
+
+other_errors!(
+    std::num::ParseIntError,
+    std::str::Utf8Error,
+    regex::Error,
+    glob::PatternError,
+    glob::GlobError,
+    zip::result::ZipError,
+    csv::Error,
+    serde_json::Error,
+    toml::de::Error,
+    rayon::ThreadPoolBuildError,
+);
+
+// 构造 HeaderError::Invalid，用法与 format! 相同
+macro_rules! invalid {
+    ($($arg:tt)*) => {
+        HeaderError::Invalid(format!($($arg)*))
+    };
+}
+
+// 为错误附加说明，用法与 anyhow::Context 相同
+trait Context<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T>;
+    fn with_context<C: fmt::Display>(self, f: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<HeaderError>> Context<T> for Result<T, E> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.map_err(|e| HeaderError::Context {
+            context: context.to_string(),
+            source: Box::new(e.into()),
+        })
+    }
+
+    fn with_context<C: fmt::Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| HeaderError::Context {
+            context: f().to_string(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+// 打开文件失败时记录文件名
+fn open_error(path: &Path) -> impl FnOnce(io::Error) -> HeaderError + '_ {
+    move |source| HeaderError::OpenFile {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+// 把错误及其全部 source 用 ": " 连接起来，与 anyhow 的 `{:#}` 格式相同
+fn error_chain(e: &HeaderError) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+// 与 C 库的 strerror 一样，去掉系统错误末尾的 " (os error N)"
+fn strerror(e: &(dyn std::error::Error + 'static)) -> String {
+    let code = match e.downcast_ref::<HeaderError>() {
+        Some(HeaderError::Io(e)) => e.raw_os_error(),
+        _ => e
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::raw_os_error),
+    };
+    let message = e.to_string();
+    match code {
+        Some(code) => message
+            .trim_end_matches(&format!(" (os error {code})"))
+            .to_string(),
+        None => message,
+    }
+}
+
+/// 计数方式：输出开头的 N 个，输出除末尾 N 个以外的全部，或者输出一个范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Count {
+    First(u64),      // 对应 `-n N` / `-c N`
+    AllButLast(u64), // 对应 `-n -N` / `-c -N`
+    Range(u64, u64), // 对应 `-n START:END` / `-c START-END`，从 1 开始计数，包含两端
+    Percent(u8),     // 对应 `-n P%` / `-c P%`，只适用于普通文件
+}
 
 // 配置结构体，存储命令行参数
+// 开启 serde 特性时可以与 JSON 等格式互相转换，未给出的字段取默认值，反序列化的结果经过 ConfigBuilder::build 检查
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self", default)
+)]
+#[rustfmt::skip]
 pub struct Config {
-    files: Vec<String>,   // 要处理的文件列表
-    lines: usize,         // 要显示的行数
-    bytes: Option<usize>, // 要显示的字节数（可选）
+    files: Vec<PathBuf>,                 // 要处理的文件列表
+    lines: Count,                        // 要显示的行数
+    bytes: Option<Count>,                // 要显示的字节数（可选）
+    mmap: bool,                          // 字节模式下是否通过内存映射读取普通文件
+    utf8_boundary: Option<Utf8Boundary>, // -c 截断在多字节字符中间时如何调整（可选）
+    quiet: bool,                         // 是否不打印文件名标题
+    verbose: bool,                       // 是否总是打印文件名标题
+    delimiter: Vec<u8>,                  // 记录（行）分隔符，默认为换行符
+    fail_fast: bool,                     // 遇到第一个无法打开的文件时立即退出
+    gnu_errors: bool,                    // 是否按 GNU head 的格式报告错误
+    compat: Option<Compat>,              // 模仿的平台 head（可选）
+    tail: bool,                          // 是否输出末尾而不是开头的行
+    follow: bool,                        // 输出完后是否继续输出文件新追加的内容
+    skip: u64,                           // 输出前先跳过的行数
+    step: usize,                         // 每隔多少行输出一行
+    jobs: usize,                         // 同时处理多少个文件
+    max_memory: usize,                   // -n -K / -c -K、--tail 等读取管道时在内存中暂存的最大字节数
+    max_line_bytes: usize,               // 逐行处理时一行最多暂存的字节数，超出的部分提前输出
+    io_backend: IoBackend,               // 打开和读取本地文件的方式
+    sample: Option<usize>,               // 随机抽样输出的行数（可选）
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::regex"))]
+    until: Option<Regex>,                // 遇到匹配该正则的行后停止输出（可选）
+    until_exclusive: bool,               // 是否不输出匹配 until 的那一行
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::regex"))]
+    from: Option<Regex>,                 // 从第一行匹配该正则的行开始输出（可选）
+    words: Option<usize>,                // 要显示的单词数（可选）
+    chars: Option<usize>,                // 要显示的字符数（可选）
+    strict_utf8: bool,                   // 字符模式下遇到非法 UTF-8 是否报错而不是替换为 U+FFFD
+    graphemes: Option<usize>,            // 要显示的字素簇数（可选）
+    paragraphs: Option<usize>,           // 要显示的段落数（可选）
+    csv: bool,                           // 是否按 CSV 记录计数并总是保留表头行
+    ndjson: bool,                        // 是否把每行当作 JSON 值校验后再输出
+    pretty: bool,                        // NDJSON 模式下是否格式化输出每条记录
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::regex"))]
+    record_start: Option<Regex>,         // 匹配该正则的行开始一条新记录，其余行归入上一条（可选）
+    skip_binary: bool,                   // 是否以提示代替二进制文件的内容
+    label: String,                       // 标准输入在标题中显示的名称
+    open_timeout: Option<Duration>,      // 等待 FIFO 写入者或 TCP 连接建立的最长时间（可选）
+    read_timeout: Option<Duration>,      // TCP 输入两次收到数据之间的最长等待时间（可选）
+    #[cfg_attr(feature = "serde", serde(with = "serde_support::encoding"))]
+    encoding: Option<&'static Encoding>, // 输入的字符编码，处理前先转换为 UTF-8（可选）
+    list_encodings: bool,                // 是否只列出支持的编码名称
+    #[cfg_attr(feature = "serde", serde(skip))]
+    completions: Option<Shell>,          // 只输出该 shell 的补全脚本（可选）
+    strip_bom: bool,                     // 是否去掉每个输入开头的 BOM
+    crlf: Crlf,                          // 输入中 \r\n 和单独的 \r 如何处理
+    number: Option<Numbering>,           // 输出时给行加上行号（可选）
+    offsets: Option<OffsetFormat>,       // 输出时给行加上它在文件中的起始字节偏移（可选）
+    hex: bool,                           // 是否以 xxd 风格的十六进制转储输出
+    palette: Palette,                    // 标题、行号、偏移等的着色方案
+    output: OutputFormat,                // 输出格式
+    output_dir: Option<PathBuf>,         // 把每个文件的输出分别写到该目录下（可选）
+    output_file: Option<PathBuf>,        // 把全部输出写到该文件而不是标准输出（可选）
+    header_format: String,               // 文件名标题的模板
+    final_newline: Option<FinalNewline>, // 每个文件的输出末尾补上或去掉分隔符（可选）
+    squeeze_blank: bool,                 // 是否把连续的多个空行合并为一个
+    truncate_lines: Option<usize>,       // 超过该列数的行截断显示（可选）
+    show_nonprinting: bool,              // 是否像 cat -v 一样显示不可打印的字节
+    syntax: bool,                        // 是否按文件类型做语法高亮
+    pager: bool,                         // 输出超过一屏时是否通过分页程序显示
+    tty_warning: bool,                   // 开始前是否提示正在等待从终端输入
+    print0: bool,                        // 输出的每条记录是否以 NUL 而不是分隔符结尾
+    eol: Option<Eol>,                    // 输出时把换行统一为 LF、CRLF 或 CR（可选）
+    stats: bool,                         // 结束后是否在标准错误上输出每个文件输出的行数和字节数
+    #[cfg_attr(feature = "serde", serde(skip))] // 由 from、until 和着色设置推出
+    highlight: Option<Regex>,            // 着色时高亮 --from/--until 匹配的部分（可选）
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stop: Arc<AtomicBool>,               // 置位后 --follow 不再等待新内容，见 Config::stop_flag
+}
+
+/// --output 选择的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputFormat {
+    Text, // 原样输出，多个文件时带文件名标题
+    Json, // 每个文件输出一行 JSON
+    Tsv,  // 每行输出一条以制表符分隔的 `文件名、行号、内容` 记录
+    Csv,  // 与 Tsv 相同，但按 CSV 规则分隔和加引号
+}
+
+/// --utf8-boundary：-c 在多字节字符中间截断时把截断位置移到哪一侧
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Utf8Boundary {
+    Back,    // 不输出被截断的字符
+    Forward, // 把被截断的字符输出完整
+}
+
+/// --crlf：输入中 Windows（\r\n）和旧式 Mac（单独的 \r）换行符的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Crlf {
+    Keep,    // 原样输出
+    Strip,   // 把 \r\n 换成 \n，单独的 \r 保持不变
+    Convert, // 把 \r\n 和单独的 \r 都换成 \n，单独的 \r 也算作一行的结尾
+}
+
+/// --eol：输出使用的换行符
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Eol {
+    Lf,   // \n
+    Crlf, // \r\n
+    Cr,   // \r
+}
+
+impl Eol {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Eol::Lf => b"\n",
+            Eol::Crlf => b"\r\n",
+            Eol::Cr => b"\r",
+        }
+    }
+}
+
+/// --compat：模仿哪个平台的 head，影响错误信息的措辞和可用的选项
+/// 没有给出 --compat 而设置了 POSIXLY_CORRECT 时只按 Gnu 的格式报告错误
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compat {
+    Gnu,   // GNU coreutils：错误信息与 --gnu-errors 相同
+    Bsd,   // BSD：错误信息为 `FILE: 原因`，计数必须是正整数
+    Posix, // POSIX：与 Bsd 相同，但没有 -q 和 -v
+}
+
+/// --io-backend 选择的读取方式
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IoBackend {
+    Std,   // 逐个打开、读取
+    Uring, // 通过 io_uring 成批打开并读取文件的开头，只在 Linux 上可用
+}
+
+/// 每个文件输出末尾的分隔符如何处理
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FinalNewline {
+    Ensure, // 没有以分隔符结尾时补上
+    Strip,  // 以分隔符结尾时去掉
+}
+
+// --offsets 输出偏移量的进制
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OffsetFormat {
+    Dec,
+    Hex,
+}
+
+// 行号的格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Numbering {
+    nonblank: bool, // 空行不编号，也不占用行号
+    width: usize,   // 行号右对齐的宽度
+}
+
+// --follow 模式下两次检查文件是否有新内容之间的间隔
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+// --version 输出的完整版本信息（版本号、git 提交、构建日期、特性和目标平台），由 build.rs 在构建时生成
+const LONG_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/long_version.txt"));
+
+/// 命令行参数，字段与各个选项一一对应，由 get_args_from 转换为 Config
+/// 可以通过 clap::CommandFactory::command 取得完整的命令定义，用来生成补全脚本或手册页
+#[derive(Debug, Parser)]
+#[command(
+    name = "header",
+    version,
+    long_version = LONG_VERSION,
+    disable_version_flag = true,
+    about = "Rust version of the 'head' command",
+    long_about = None,
+    args_override_self = true // 与 GNU head 一样，重复给出的选项以最后一次为准
+)]
+pub struct Cli {
+    // 设置 --profile 参数，使用配置文件中 [profile.NAME] 预设的选项
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Apply the options preset in [profile.NAME] of the config file; options on the command line override them"
+    )]
+    profile: Option<String>,
+    // 设置 -n/--lines 参数，用于指定显示的行数
+    #[arg(
+        short = 'n',
+        long,
+        value_name = "LINES",
+        allow_hyphen_values = true, // 允许 `-n -5` 这样的负数写法
+        value_hint = ValueHint::Other, // 补全时不提示文件名
+        value_parser = count_parser("lines count", parse_lines),
+        help = "Number of lines to show; with a leading '-', all but the last LINES lines; \
+                START:END shows that range of lines [default: 10] [env: HEADER_LINES]"
+    )]
+    lines: Option<Count>,
+    // 设置 -c/--bytes 参数，用于指定显示的字节数
+    #[arg(
+        short = 'c',
+        long,
+        value_name = "BYTES",
+        allow_hyphen_values = true,    // 允许 `-c -5` 这样的负数写法
+        value_hint = ValueHint::Other, // 补全时不提示文件名
+        conflicts_with = "lines",      // bytes 参数和 lines 参数互斥
+        value_parser = count_parser("bytes count", parse_bytes),
+        help = "Number of bytes to show; with a leading '-', all but the last BYTES bytes; \
+                START-END shows that range of bytes [env: HEADER_BYTES]"
+    )]
+    bytes: Option<Count>,
+    // 设置 --mmap 参数，字节模式下把普通文件映射到内存后直接输出
+    #[arg(
+        long,
+        requires = "bytes",
+        help = "Memory-map regular files in byte mode instead of reading them through a buffer"
+    )]
+    mmap: bool,
+    // 设置 --utf8-boundary 参数，-c 不在多字节字符的中间截断
+    #[arg(
+        long,
+        value_name = "DIRECTION",
+        num_args = 0..=1,
+        require_equals = true,
+        requires = "bytes",
+        help = "With -c, move a cut inside a UTF-8 character back before it or forward past it [default: back]"
+    )]
+    utf8_boundary: Option<Option<Utf8Boundary>>,
+    // 设置 -q/--quiet 参数，多个文件时也不打印文件名标题
+    #[arg(
+        short,
+        long,
+        visible_alias = "silent",
+        overrides_with = "verbose", // 与 verbose 同时出现时以后者为准
+        help = "Never print headers giving file names"
+    )]
+    quiet: bool,
+    // 设置 -v/--verbose 参数，单个文件或标准输入时也打印文件名标题
+    #[arg(
+        short,
+        long,
+        overrides_with = "quiet",
+        help = "Always print headers giving file names"
+    )]
+    verbose: bool,
+    // 设置 -z/--zero-terminated 参数，以 NUL 作为行分隔符
+    #[arg(short, long, help = "Line delimiter is NUL, not newline")]
+    zero_terminated: bool,
+    // 设置 --fail-fast 参数，遇到第一个错误时立即终止
+    #[arg(long, help = "Abort on the first file that cannot be opened")]
+    fail_fast: bool,
+    // 设置 --gnu-errors 参数，错误信息与 GNU head 的格式相同，便于在测试脚本中替换 head
+    #[arg(long, help = "Report errors in the same format as GNU head")]
+    gnu_errors: bool,
+    // 设置 --compat 参数，作为某个平台 head 的替代品运行；设置了 POSIXLY_CORRECT 时默认为 gnu
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        help = "Behave like the head of that platform: error wording, count syntax and -q/-v [default: gnu if POSIXLY_CORRECT is set]"
+    )]
+    compat: Option<Compat>,
+    // 设置 -t/--tail 参数，输出最后 N 行
+    #[arg(
+        short,
+        long,
+        conflicts_with = "bytes",
+        help = "Print the last LINES lines instead of the first; with -n -K, all but the first K lines"
+    )]
+    tail: bool,
+    // 设置 -f/--follow 参数，持续输出文件新追加的内容
+    #[arg(
+        short,
+        long,
+        help = "Output appended data as the file grows, until interrupted (WASI has no signals, so there only until the process is killed)"
+    )]
+    follow: bool,
+    // 设置 --skip 参数，先丢弃开头的若干行再开始计数
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["bytes", "tail"],
+        value_parser = count_parser("skip count", parse_size),
+        help = "Skip the first N lines before printing"
+    )]
+    skip: Option<u64>,
+    // 设置 --step 参数，在输出窗口内每 N 行输出一行
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["bytes", "tail"],
+        value_parser = count_parser("step", parse_positive),
+        help = "Print every Nth line (lines 1, N+1, 2N+1, ...)"
+    )]
+    step: Option<usize>,
+    // 设置 -j/--jobs 参数，同时处理多个文件，仍按参数的顺序输出
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        value_parser = count_parser("jobs", parse_positive),
+        help = "Process up to N files concurrently, still printing them in argument order [default: 1]"
+    )]
+    jobs: Option<usize>,
+    // 设置 --max-memory 参数，超过后把 -n -K / -c -K、--tail 等暂存的内容写到临时文件中
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = count_parser("memory limit", parse_usize),
+        help = "Spill content held back by -n -K/-c -K, --tail and --paragraphs on pipes to a temporary file beyond SIZE bytes [default: 64M]"
+    )]
+    max_memory: Option<usize>,
+    // 设置 --max-line-bytes 参数，没有换行的超长行分块输出，不整行读入内存
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = count_parser("line limit", parse_positive),
+        help = "Process lines longer than SIZE bytes in pieces instead of holding them in memory; longer --ndjson records are an error [default: 1M]"
+    )]
+    max_line_bytes: Option<usize>,
+    // 设置 --io-backend 参数，选择打开和读取本地文件的方式
+    #[arg(
+        long,
+        value_name = "BACKEND",
+        conflicts_with = "jobs",
+        help = "Read local files one at a time (std) or in batches through io_uring (uring) [default: std]"
+    )]
+    io_backend: Option<IoBackend>,
+    // 设置 --sample 参数，均匀随机抽取 N 行代替开头的行
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["bytes", "tail", "step", "lines"],
+        value_parser = count_parser("sample size", parse_usize),
+        help = "Print N uniformly sampled lines, in their original order"
+    )]
+    sample: Option<usize>,
+    // 设置 --until 参数，输出到第一行匹配正则的行为止
+    #[arg(
+        long,
+        value_name = "REGEX",
+        conflicts_with_all = ["bytes", "tail", "step", "sample"],
+        help = "Stop after the first line matching REGEX; -n is only a limit when given"
+    )]
+    until: Option<String>,
+    // 设置 --until-exclusive 参数，不输出匹配的那一行
+    #[arg(
+        long,
+        requires = "until",
+        help = "Do not print the line matching --until"
+    )]
+    until_exclusive: bool,
+    // 设置 --from 参数，从第一行匹配正则的行开始输出
+    #[arg(
+        long,
+        value_name = "REGEX",
+        conflicts_with_all = ["bytes", "tail"],
+        help = "Start printing at the first line matching REGEX"
+    )]
+    from: Option<String>,
+    // 设置 -w/--words 参数，输出开头的 N 个单词
+    #[arg(
+        short,
+        long,
+        value_name = "WORDS",
+        conflicts_with_all = ["bytes", "lines", "tail", "step", "sample", "until"],
+        value_parser = count_parser("words count", parse_usize),
+        help = "Number of whitespace-separated words to show"
+    )]
+    words: Option<usize>,
+    // 设置 -m/--chars 参数，输出开头的 N 个 Unicode 字符
+    #[arg(
+        short = 'm',
+        long,
+        value_name = "CHARS",
+        conflicts_with_all = ["bytes", "lines", "words", "tail", "step", "sample", "until"],
+        value_parser = count_parser("chars count", parse_usize),
+        help = "Number of UTF-8 characters to show"
+    )]
+    chars: Option<usize>,
+    // 设置 --strict-utf8 参数，字符模式下遇到非法 UTF-8 时报错
+    #[arg(
+        long,
+        requires = "chars",
+        help = "Fail on invalid UTF-8 in --chars mode instead of substituting U+FFFD"
+    )]
+    strict_utf8: bool,
+    // 设置 --graphemes 参数，输出开头的 N 个字素簇（用户看到的字符）
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "bytes", "lines", "words", "chars", "tail", "step", "sample", "until",
+        ],
+        value_parser = count_parser("graphemes count", parse_usize),
+        help = "Number of grapheme clusters (user-perceived characters) to show"
+    )]
+    graphemes: Option<usize>,
+    // 设置 --paragraphs 参数，输出开头的 N 个以空行分隔的段落
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "bytes", "lines", "words", "chars", "graphemes", "tail", "step", "sample",
+            "until",
+        ],
+        value_parser = count_parser("paragraphs count", parse_usize),
+        help = "Number of paragraphs (blocks separated by blank lines) to show"
+    )]
+    paragraphs: Option<usize>,
+    // 设置 --delimiter 参数，自定义记录分隔符，-n 按记录计数
+    #[arg(
+        long,
+        value_name = "STRING",
+        allow_hyphen_values = true, // 允许 `---` 这样以 '-' 开头的分隔符
+        conflicts_with_all = ["zero_terminated", "bytes"],
+        help = "Use STRING as the record separator instead of newline (\\n, \\t, \\0, \\\\ escapes allowed)"
+    )]
+    delimiter: Option<String>,
+    // 设置 --csv 参数，按 CSV 记录计数，并总是输出表头行
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "bytes", "words", "chars", "graphemes", "paragraphs", "tail", "step",
+            "sample", "until", "from", "skip", "delimiter", "zero_terminated",
+        ],
+        help = "Print the CSV header row plus the first LINES records (quoted newlines are kept)"
+    )]
+    csv: bool,
+    // 设置 --ndjson 参数，每行必须是合法的 JSON 值
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "bytes", "words", "chars", "graphemes", "paragraphs", "csv", "tail", "step",
+            "sample", "until", "delimiter", "zero_terminated",
+        ],
+        help = "Treat each line as a JSON value and stop at the first invalid record"
+    )]
+    ndjson: bool,
+    // 设置 --pretty 参数，格式化输出 NDJSON 记录
+    #[arg(
+        long,
+        requires = "ndjson",
+        help = "Pretty-print each JSON record in --ndjson mode"
+    )]
+    pretty: bool,
+    // 设置 --record-start 参数，把多行日志（如异常堆栈）合并为一条记录
+    #[arg(
+        long,
+        value_name = "REGEX",
+        conflicts_with_all = [
+            "bytes", "words", "chars", "graphemes", "paragraphs", "csv", "ndjson",
+            "tail", "step", "sample", "until",
+        ],
+        help = "Lines matching REGEX start a new record; other lines join the previous one"
+    )]
+    record_start: Option<String>,
+    // 设置 -R/--recursive 参数，递归处理目录中的普通文件
+    #[arg(
+        short = 'R',
+        long,
+        help = "Read all regular files under each directory, recursively"
+    )]
+    recursive: bool,
+    // 设置 --follow-symlinks 参数，递归时进入指向目录的符号链接
+    #[arg(
+        long,
+        requires = "recursive",
+        overrides_with = "no_follow",
+        help = "With -R, also descend into symbolic links to directories (cycles are skipped)"
+    )]
+    follow_symlinks: bool,
+    // 设置 --no-follow 参数，递归时忽略所有符号链接
+    #[arg(
+        long,
+        requires = "recursive",
+        overrides_with = "follow_symlinks",
+        help = "With -R, ignore all symbolic links"
+    )]
+    no_follow: bool,
+    // 设置 --skip-dangling 参数，递归时跳过悬空的符号链接
+    #[arg(
+        long,
+        requires = "recursive",
+        help = "With -R, silently skip symbolic links whose target does not exist"
+    )]
+    skip_dangling: bool,
+    // 设置 --header-format 参数，自定义文件名标题的格式
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        allow_hyphen_values = true, // 允许 `--- {path}` 这样以 '-' 开头的模板
+        help = "Template for file name headers; {path}, {basename}, {size} and {index} \
+                are replaced [default: ==> {path} <==]"
+    )]
+    header_format: Option<String>,
+    // 设置 --label 参数，指定标准输入在标题中显示的名称
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Name to show in the header for standard input [default: standard input]"
+    )]
+    label: Option<String>,
+    // 设置 --open-timeout 参数，打开没有写入者的 FIFO 时不再无限等待
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = timeout_parser("open timeout"),
+        help = "Give up opening a FIFO without a writer, or connecting to tcp://, after SECS seconds"
+    )]
+    open_timeout: Option<Duration>,
+    // 设置 --read-timeout 参数，TCP 输入长时间没有数据时报错
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = timeout_parser("read timeout"),
+        help = "Fail when a tcp:// input sends no data for SECS seconds"
+    )]
+    read_timeout: Option<Duration>,
+    // 设置 --encoding 参数，按指定编码解码输入
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        value_parser = parse_encoding,
+        help = "Decode input from ENCODING (e.g. gbk, latin1, utf-16le) to UTF-8 before counting"
+    )]
+    encoding: Option<&'static Encoding>,
+    // 设置 --list-encodings 参数，列出 --encoding 支持的编码
+    #[arg(long, help = "List the encodings accepted by --encoding and exit")]
+    list_encodings: bool,
+    // 设置 --generate-completions 参数，输出 shell 补全脚本，供打包时安装，不在帮助中列出
+    #[arg(
+        long,
+        value_name = "SHELL",
+        hide = true,
+        exclusive = true,
+        help = "Print a completion script for SHELL and exit"
+    )]
+    generate_completions: Option<Shell>,
+    // 设置 --strip-bom 参数，去掉输入开头的 UTF-8/UTF-16 BOM
+    #[arg(
+        long,
+        overrides_with = "keep_bom",
+        help = "Remove a UTF-8 or UTF-16 byte order mark from the start of each input"
+    )]
+    strip_bom: bool,
+    // 设置 --keep-bom 参数，原样输出 BOM（默认行为）
+    #[arg(
+        long,
+        overrides_with = "strip_bom",
+        help = "Keep byte order marks in the output (default)"
+    )]
+    keep_bom: bool,
+    // 设置 --crlf 参数，统一 Windows 和旧式 Mac 的换行符
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "Keep \\r\\n line endings, strip them to \\n, or convert \\r\\n and lone \\r to \\n [default: keep]"
+    )]
+    crlf: Option<Crlf>,
+    // 设置 -N/--number 参数，像 cat -n 一样给输出的行编号
+    #[arg(
+        short = 'N',
+        long,
+        help = "Number all output lines, restarting at 1 for each file"
+    )]
+    number: bool,
+    // 设置 --number-nonblank 参数，像 cat -b 一样只给非空行编号
+    #[arg(long, help = "Number non-empty output lines; overrides -N")]
+    number_nonblank: bool,
+    // 设置 -s/--squeeze-blank 参数，像 cat -s 一样合并连续的空行
+    #[arg(
+        short,
+        long,
+        conflicts_with = "hex",
+        help = "Collapse runs of empty output lines into a single empty line"
+    )]
+    squeeze_blank: bool,
+    // 设置 --truncate-lines 参数，截断超过终端宽度的长行
+    #[arg(
+        long,
+        value_name = "COLS",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with = "hex",
+        value_parser = parse_columns,
+        help = "Clip lines wider than COLS columns [default: terminal width], marking them with '…'"
+    )]
+    truncate_lines: Option<Option<usize>>,
+    // 设置 --show-nonprinting 参数，像 cat -v 一样转义控制字符
+    #[arg(
+        long,
+        conflicts_with = "hex",
+        help = "Show control characters as ^X and bytes above 127 as M-X, like cat -v"
+    )]
+    show_nonprinting: bool,
+    // 设置 --highlight 参数，按文件类型做语法高亮
+    #[arg(
+        long,
+        conflicts_with_all = ["hex", "show_nonprinting"],
+        help = "Syntax-highlight output based on the file extension when colors are enabled"
+    )]
+    highlight: bool,
+    // 设置 --number-width 参数，行号右对齐的宽度
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        value_parser = count_parser("number width", |s| Ok(s.parse::<usize>()?)),
+        help = "Width of line numbers [default: 6]"
+    )]
+    number_width: Option<usize>,
+    // 设置 --offsets 参数，在每行前面输出它在文件中的起始字节偏移
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with_all = [
+            "bytes", "tail", "words", "chars", "graphemes", "paragraphs", "csv", "ndjson",
+            "record_start", "sample",
+        ],
+        help = "Prefix each line with its starting byte offset in the input (dec or hex)"
+    )]
+    offsets: Option<Option<OffsetFormat>>,
+    // 设置 -x/--hex 参数，以十六进制转储的形式输出
+    #[arg(
+        short = 'x',
+        long,
+        conflicts_with_all = ["number", "number_nonblank", "offsets"],
+        help = "Print output as an xxd-style hexdump"
+    )]
+    hex: bool,
+    // 设置 --color 参数，给标题、行号、偏移以及匹配的部分着色
+    #[arg(
+        long,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Colorize headers, line numbers, offsets and matches (auto, always or never) [default: auto] [env: HEADER_COLOR]"
+    )]
+    color: Option<Option<ColorWhen>>,
+    // 设置 --output 参数，选择输出格式
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        conflicts_with_all = ["tail", "follow", "hex", "number", "number_nonblank"],
+        help = "Output format: text; json for one {\"file\", \"lines\", \"truncated\"} \
+                object per input; tsv or csv for file, line number and content rows \
+                [default: text]"
+    )]
+    output: Option<OutputFormat>,
+    // 设置 --output-dir 参数，每个文件的输出写到目录下同名的文件中
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with = "follow",
+        help = "Write the output for each FILE to DIR/FILE instead of standard output"
+    )]
+    output_dir: Option<PathBuf>,
+    // 设置 -o/--output-file 参数，输出写到文件，成功后才替换目标文件
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["output_dir", "follow"],
+        help = "Write output to FILE, replacing it atomically once all input is processed"
+    )]
+    output_file: Option<PathBuf>,
+    // 设置 --pager 参数，输出超过一屏时通过 $PAGER 显示
+    #[arg(
+        long,
+        conflicts_with_all = ["follow", "output_dir", "output_file"],
+        help = "Page output through $PAGER (default: less) when it doesn't fit on the terminal"
+    )]
+    pager: bool,
+    // 设置 --no-tty-warning 参数，从终端读取标准输入时不输出提示
+    #[arg(
+        long,
+        help = "Do not print a hint when reading standard input from a terminal"
+    )]
+    no_tty_warning: bool,
+    // 设置 --stats 参数，结束后统计每个文件输出了多少行和字节
+    #[arg(
+        long,
+        help = "Print the number of lines and bytes written for each FILE and in total to standard error"
+    )]
+    stats: bool,
+    // 设置 --print0 参数，输出的每条记录以 NUL 结尾（配合 xargs -0）
+    #[arg(
+        long,
+        conflicts_with_all = ["no_trailing_newline", "output", "hex", "follow"],
+        help = "End each output record with NUL instead of newline (or the record delimiter)"
+    )]
+    print0: bool,
+    // 设置 --eol 参数，输出时统一换行符，与输入使用哪种换行符无关
+    #[arg(
+        long,
+        value_name = "EOL",
+        conflicts_with = "print0",
+        help = "End output lines with \\n (lf), \\r\\n (crlf) or \\r (cr), whatever the input used"
+    )]
+    eol: Option<Eol>,
+    // 设置 --ensure-newline 参数，保证每个文件的输出以分隔符结尾
+    #[arg(
+        long,
+        overrides_with = "no_trailing_newline",
+        help = "End each file's output with a newline (or the record delimiter) if it lacks one"
+    )]
+    ensure_newline: bool,
+    // 设置 --no-trailing-newline 参数，去掉每个文件输出末尾的分隔符
+    #[arg(
+        long,
+        overrides_with = "ensure_newline",
+        help = "Remove the newline (or the record delimiter) at the end of each file's output"
+    )]
+    no_trailing_newline: bool,
+    // 设置 --skip-binary 参数，不输出看起来是二进制的文件
+    #[arg(
+        long,
+        overrides_with = "binary",
+        help = "Print a notice instead of the contents of binary files"
+    )]
+    skip_binary: bool,
+    // 设置 --binary 参数，总是输出文件内容（默认行为）
+    #[arg(
+        long,
+        overrides_with = "skip_binary",
+        help = "Always print file contents, even for binary files (default)"
+    )]
+    binary: bool,
+    // 设置 --files-from 参数，从文件（或标准输入）读取要处理的文件列表
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        help = "Read input file names from FILE, one per line; - reads standard input"
+    )]
+    files_from: Option<String>,
+    // 设置 -0/--null 参数，文件列表以 NUL 分隔（配合 find -print0）
+    #[arg(
+        short = '0',
+        long,
+        requires = "files_from",
+        help = "File names read by --files-from are NUL-separated"
+    )]
+    null: bool,
+    // 设置 --archive-member 参数，读取每个归档文件中的指定成员
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read member PATH inside each archive FILE (same as FILE::PATH)"
+    )]
+    archive_member: Option<String>,
+    // 设置文件参数，可以接收多个文件，没有给出时从标准输入读取
+    #[arg(
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        help = "Input file(s) [default: -]"
+    )]
+    files: Vec<PathBuf>,
+    // 设置 -V/--version 参数，-V 只输出版本号，--version 还输出构建信息
+    #[arg(
+        short = 'V',
+        long,
+        action = clap::ArgAction::Version,
+        help = "Print version (see build details with '--version')"
+    )]
+    version: Option<bool>,
+}
+
+// --color 的取值
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ColorWhen {
+    Auto,   // 只在标准输出是终端且没有设置 NO_COLOR 时着色
+    Always, // 总是着色
+    Never,  // 从不着色
 }
 
 /// 解析命令行参数并返回配置
+/// 参数有误或者请求了 --help / --version 时由 clap 输出信息并退出进程
+/// HEADER_LINES、HEADER_BYTES 和 HEADER_COLOR 环境变量提供默认值，命令行上给出的选项优先
+/// 环境变量的值有误时与命令行参数有误一样返回 HeaderError::Invalid，错误信息中给出变量名和值
 pub fn get_args() -> Result<Config> {
-    // 创建命令行应用程序
-    let matches = App::new("header")
-        .version("0.1.0")
-        //.author("Your Name <you@example.com>")
-        .about("Rust version of the 'head' command")
-        // 设置 -n/--lines 参数，用于指定显示的行数
-        .arg(
-            Arg::with_name("lines")
-                .short("n")
-                .long("lines")
-                .value_name("LINES")
-                .help("Number of lines to show")
-                .default_value("10"), // 默认显示10行
-        )
-        // 设置 -c/--bytes 参数，用于指定显示的字节数
-        .arg(
-            Arg::with_name("bytes")
-                .short("c")
-                .long("bytes")
-                .value_name("BYTES")
-                .takes_value(true)
-                .conflicts_with("lines") // bytes 参数和 lines 参数互斥
-                .help("Number of bytes to show"),
-        )
-        // 设置文件参数，可以接收多个文件
-        .arg(
-            Arg::with_name("files")
-                .value_name("FILE")
-                .help("Input file(s)")
-                .multiple(true) // 允许多个文件
-                .default_value("-"), // 默认从标准输入读取
-        )
-        .get_matches();
+    match parse_args(env::args_os(), &EnvDefaults::from_env()?) {
+        Err(HeaderError::Args(e)) => e.exit(),
+        result => result,
+    }
+}
+
+/// 从给定的参数（第一个是程序名）解析配置，不读取进程的参数，也不会退出进程
+/// 不使用 HEADER_LINES 等环境变量提供的默认值
+/// 参数有误或者请求了 --help / --version 时返回 HeaderError::Args
+pub fn get_args_from<I: IntoIterator<Item = T>, T: Into<OsString>>(args: I) -> Result<Config> {
+    parse_args(args, &EnvDefaults::default())
+}
+
+/// 在完整解析之前从参数（第一个是程序名）中找出报告错误时模仿的平台，
+/// 用于按该平台的格式报告参数本身的错误，例如 `--compat gnu -n abc`
+/// --gnu-errors 等同于 gnu；都没有给出时与 get_args 一样参考 POSIXLY_CORRECT
+pub fn compat_from_args<I: IntoIterator<Item = T>, T: Into<OsString>>(args: I) -> Option<Compat> {
+    let args = expand_obsolete_args(args.into_iter().map(Into::into));
+    // 其他选项的值可能正是出错的地方，全部按原始字符串接受，以免解析在它们那里停下
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .mut_args(|arg| match arg.get_action().takes_values() {
+            true => arg.value_parser(clap::builder::OsStringValueParser::new()),
+            false => arg,
+        })
+        .try_get_matches_from(args);
+    let Ok(matches) = matches else {
+        return posixly_correct();
+    };
+    if matches.get_flag("gnu_errors") {
+        return Some(Compat::Gnu);
+    }
+    matches
+        .get_one::<OsString>("compat")
+        .and_then(|compat| Compat::from_str(&compat.to_string_lossy(), false).ok())
+        .or_else(posixly_correct)
+}
+
+// 没有给出 --compat 时，设置了 POSIXLY_CORRECT 的环境多半是在运行 GNU 工具，
+// 因此只按 GNU head 的格式报告错误，不像显式的 --compat posix 那样拒绝 -q、-v、-n 0 和 -n -K
+fn posixly_correct() -> Option<Compat> {
+    env::var_os("POSIXLY_CORRECT")
+        .is_some()
+        .then_some(Compat::Gnu)
+}
 
-    //定义闭包来解析正整数
-    let parse_positive_int = |s: &str| -> Result<usize> {
-        match s.parse() {
-            Ok(n) if n > 0 => Ok(n),
-            _ => Err(anyhow!("illegal number: {}", s)),
+// 环境变量提供的默认值
+#[derive(Debug, Default)]
+struct EnvDefaults {
+    lines: Option<Count>,     // HEADER_LINES：没有给出 -n 时的行数
+    bytes: Option<Count>,     // HEADER_BYTES：命令行上没有选择计数方式时按字节输出
+    color: Option<ColorWhen>, // HEADER_COLOR：没有给出 --color 时何时着色
+}
+
+impl EnvDefaults {
+    // 读取环境变量，值为空时视为没有设置
+    // 值有误时不论命令行上是否给出了对应的选项，都报告错误
+    fn from_env() -> Result<Self> {
+        fn var<T>(name: &str, parse: impl FnOnce(&str) -> Result<T>) -> Result<Option<T>> {
+            let Some(value) = env::var_os(name).filter(|value| !value.is_empty()) else {
+                return Ok(None);
+            };
+            let value = value.to_string_lossy();
+            parse(&value)
+                .map(Some)
+                .map_err(|e| invalid!("Failed to parse {}: {}: {}", name, value, e.full_message()))
+        }
+        Ok(EnvDefaults {
+            lines: var("HEADER_LINES", parse_lines)?,
+            bytes: var("HEADER_BYTES", parse_bytes)?,
+            color: var("HEADER_COLOR", |value| {
+                ColorWhen::from_str(value, true)
+                    .map_err(|_| invalid!("expected auto, always or never"))
+            })?,
+        })
+    }
+}
+
+// 按给定的默认值解析参数
+fn parse_args<I: IntoIterator<Item = T>, T: Into<OsString>>(
+    args: I,
+    defaults: &EnvDefaults,
+) -> Result<Config> {
+    let mut args = expand_obsolete_args(args.into_iter().map(Into::into));
+    // --profile 预设的选项插在命令行参数的前面，命令行上再次给出的同一选项覆盖它们
+    // 这时还不知道其余参数是否有误，先忽略错误取出 --profile 的值
+    let profile = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok()
+        .and_then(|matches| matches.get_one::<String>("profile").cloned());
+    if let Some(name) = profile {
+        let start = args.len().min(1);
+        args.splice(start..start, load_profile(&name)?);
+    }
+    // 解析命令行参数，值解析器报告的计数等错误原样返回，而不是包装成 clap 的错误
+    let command = Cli::command();
+    let matches = command
+        .clone()
+        .try_get_matches_from(args)
+        .map_err(value_error)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    // 命令行（包括 --profile 预设）上选择了任何计数方式时，HEADER_BYTES 都不起作用
+    if !counting_given(&command, &matches) {
+        cli.bytes = cli.bytes.or(defaults.bytes);
+    }
+    // 报错时引用命令行上原样给出的值
+    let raw = |id: &str| -> String {
+        matches
+            .get_raw(id)
+            .and_then(|mut values| values.next())
+            .map(|value| value.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let compat = cli.compat.or_else(posixly_correct);
+    // BSD 和 POSIX 的 head 只接受正整数计数，报错的措辞也与它们相同
+    if matches!(compat, Some(Compat::Bsd | Compat::Posix)) {
+        let plain = |count: Option<Count>| matches!(count, None | Some(Count::First(1..)));
+        if !plain(cli.lines) {
+            return Err(invalid!("illegal line count -- {}", raw("lines")));
+        }
+        if !plain(cli.bytes) {
+            return Err(invalid!("illegal byte count -- {}", raw("bytes")));
+        }
+    }
+    if compat == Some(Compat::Posix) {
+        for (given, flag) in [(cli.quiet, "-q"), (cli.verbose, "-v")] {
+            if given {
+                return Err(invalid!("{} is not available with --compat posix", flag));
+            }
+        }
+    }
+
+    if cli.io_backend == Some(IoBackend::Uring)
+        && !cfg!(all(feature = "io-uring", target_os = "linux"))
+    {
+        return Err(invalid!(
+            "The io_uring backend is not available in this build"
+        ));
+    }
+
+    // 确定记录分隔符
+    let delimiter = match &cli.delimiter {
+        Some(s) => {
+            let delimiter = unescape(s);
+            if delimiter.is_empty() {
+                return Err(invalid!("Record delimiter must not be empty"));
+            }
+            delimiter
         }
+        None if cli.zero_terminated => vec![b'\0'],
+        None => vec![b'\n'],
     };
+
     /*
      * transpose 函数的作用：
      * 当遇到 `Some(Ok(v))` 时，会返回 `Ok(Some(v))`
@@ -65,79 +1231,5637 @@ pub fn get_args() -> Result<Config> {
      * 当遇到 `None` 时，会返回 `Ok(None)`
      *
      */
-    // 解析 lines 参数
-    let lines = matches
-        .value_of("lines") // 获取 lines 参数的值
-        .map(parse_positive_int) // 将值转换为正整数
-        .transpose() // 将结果转换为 Option<usize>
+    // 解析 until 参数
+    let until = cli
+        .until
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context(format!(
+            "Invalid --until pattern: {}",
+            cli.until.as_deref().unwrap_or("unknown")
+        ))?;
+    // 解析 from 参数
+    let from = cli
+        .from
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
         .context(format!(
-            "Failed to parse lines count: {}",
-            matches.value_of("lines").unwrap_or("unknown")
-        ))?; // 如果转换失败，返回错误并包含失败的字符串
-
-    // 解析 bytes 参数
-    let bytes = matches
-        .value_of("bytes")
-        .map(parse_positive_int)
+            "Invalid --from pattern: {}",
+            cli.from.as_deref().unwrap_or("unknown")
+        ))?;
+    // 解析 record-start 参数
+    let record_start = cli
+        .record_start
+        .as_deref()
+        .map(Regex::new)
         .transpose()
         .context(format!(
-            "Failed to parse bytes count: {}",
-            matches.value_of("bytes").unwrap_or("unknown")
+            "Invalid --record-start pattern: {}",
+            cli.record_start.as_deref().unwrap_or("unknown")
         ))?;
+    // 不带值的 --truncate-lines 使用终端宽度
+    let truncate_lines = cli
+        .truncate_lines
+        .map(|cols| cols.unwrap_or_else(terminal_width));
+    let number = (cli.number_nonblank || cli.number).then(|| Numbering {
+        nonblank: cli.number_nonblank,
+        width: cli.number_width.unwrap_or(6),
+    });
+    let output = cli.output.unwrap_or(OutputFormat::Text);
+    if cli.highlight && !cfg!(feature = "highlight") {
+        return Err(invalid!("Syntax highlighting is not enabled in this build"));
+    }
+    // 结构化输出中不能混入 ANSI 转义序列
+    // 不带值的 --color 等同于 always；auto 只在标准输出是终端且没有设置 NO_COLOR 时着色
+    let color = output == OutputFormat::Text
+        && match cli.color.or(defaults.color.map(Some)) {
+            Some(Some(ColorWhen::Always) | None) => true,
+            Some(Some(ColorWhen::Never)) => false,
+            _ => {
+                env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && io::stdout().is_terminal()
+                    && cli.output_dir.is_none()
+                    && cli.output_file.is_none()
+            }
+        };
+    let (lines, highlight) = resolve_patterns(from.as_ref(), until.as_ref(), cli.lines, color)?;
 
-    // 获取文件列表
-    let files = matches.values_of_lossy("files").unwrap_or_default();
+    // 获取文件列表，--files-from 读到的文件排在命令行给出的文件之后
+    // 两者都没有给出时读取标准输入
+    let mut files = cli.files.clone();
+    if let Some(list) = &cli.files_from {
+        let separator = if cli.null { b'\0' } else { b'\n' };
+        files.extend(read_file_list(list, separator)?);
+    } else if files.is_empty() {
+        files.push(PathBuf::from("-"));
+    }
+    let mut files = expand_globs(files)?;
+    if cli.recursive {
+        // 默认处理指向文件的链接，但不进入指向目录的链接
+        let policy = SymlinkPolicy {
+            follow_dirs: cli.follow_symlinks,
+            follow_files: !cli.no_follow,
+            skip_dangling: cli.skip_dangling,
+        };
+        files = expand_dirs(files, policy)?;
+    }
+    if let Some(member) = &cli.archive_member {
+        files = files
+            .into_iter()
+            .map(|file| {
+                let mut file = file.into_os_string();
+                file.push("::");
+                file.push(member);
+                PathBuf::from(file)
+            })
+            .collect();
+    }
 
     // 返回配置对象
     Ok(Config {
         files,
-        lines: lines.unwrap_or(10),
-        bytes,
+        lines: lines.or(defaults.lines).unwrap_or(Count::First(10)),
+        bytes: cli.bytes,
+        mmap: cli.mmap,
+        // 不带值的 --utf8-boundary 向前退到完整的字符
+        utf8_boundary: cli
+            .utf8_boundary
+            .map(|boundary| boundary.unwrap_or(Utf8Boundary::Back)),
+        quiet: cli.quiet,
+        verbose: cli.verbose,
+        delimiter,
+        fail_fast: cli.fail_fast,
+        gnu_errors: cli.gnu_errors,
+        compat,
+        tail: cli.tail,
+        follow: cli.follow,
+        skip: cli.skip.unwrap_or(0),
+        step: cli.step.unwrap_or(1),
+        jobs: cli.jobs.unwrap_or(1),
+        max_memory: cli.max_memory.unwrap_or(64 * 1024 * 1024),
+        max_line_bytes: cli.max_line_bytes.unwrap_or(MAX_LINE_BYTES),
+        io_backend: cli.io_backend.unwrap_or(IoBackend::Std),
+        sample: cli.sample,
+        until,
+        until_exclusive: cli.until_exclusive,
+        from,
+        words: cli.words,
+        chars: cli.chars,
+        strict_utf8: cli.strict_utf8,
+        graphemes: cli.graphemes,
+        paragraphs: cli.paragraphs,
+        csv: cli.csv,
+        ndjson: cli.ndjson,
+        pretty: cli.pretty,
+        record_start,
+        skip_binary: cli.skip_binary,
+        label: cli.label.unwrap_or_else(|| "standard input".to_string()),
+        open_timeout: cli.open_timeout,
+        read_timeout: cli.read_timeout,
+        encoding: cli.encoding,
+        list_encodings: cli.list_encodings,
+        completions: cli.generate_completions,
+        strip_bom: cli.strip_bom,
+        crlf: cli.crlf.unwrap_or(Crlf::Keep),
+        number,
+        // 不带值的 --offsets 使用十进制
+        offsets: cli
+            .offsets
+            .map(|format| format.unwrap_or(OffsetFormat::Dec)),
+        hex: cli.hex,
+        palette: Palette { enabled: color },
+        output,
+        output_dir: cli.output_dir,
+        output_file: cli.output_file,
+        header_format: cli
+            .header_format
+            .unwrap_or_else(|| "==> {path} <==".to_string()),
+        squeeze_blank: cli.squeeze_blank,
+        truncate_lines,
+        show_nonprinting: cli.show_nonprinting,
+        syntax: cli.highlight && color,
+        pager: cli.pager && io::stdout().is_terminal(),
+        // 没有给出任何文件时默认读取标准输入，标准输入是终端时看起来就像卡住了
+        tty_warning: !cli.no_tty_warning
+            && cli.files.is_empty()
+            && cli.files_from.is_none()
+            && io::stdin().is_terminal(),
+        print0: cli.print0,
+        eol: cli.eol,
+        stats: cli.stats,
+        // --print0 的最后一条记录也要以 NUL 结尾
+        final_newline: if cli.ensure_newline || cli.print0 {
+            Some(FinalNewline::Ensure)
+        } else if cli.no_trailing_newline {
+            Some(FinalNewline::Strip)
+        } else {
+            None
+        },
+        highlight,
+        stop: Arc::default(),
     })
 }
 
-fn open(filename: &str) -> Result<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(
-            File::open(filename).context(format!("Failed to open file: {}", filename))?,
-        ))),
+// 命令行和 ConfigBuilder 共用：根据 --from/--until 确定行数和高亮，lines 是显式给出的行数
+// --until 时只有显式给出行数才限制行数，返回 None 表示使用默认的行数
+// 着色时高亮匹配的部分，--from 与 --until 同时给出时两者匹配的部分都要高亮
+fn resolve_patterns(
+    from: Option<&Regex>,
+    until: Option<&Regex>,
+    lines: Option<Count>,
+    color: bool,
+) -> Result<(Option<Count>, Option<Regex>)> {
+    let lines = match (until, lines) {
+        (Some(_), None) => Some(Count::First(u64::MAX)),
+        (until, lines) => {
+            check_until_lines(until, lines)?;
+            lines
+        }
+    };
+    let highlight = match (from, until) {
+        (Some(from), Some(until)) => Some(Regex::new(&format!(
+            "(?:{})|(?:{})",
+            from.as_str(),
+            until.as_str()
+        ))?),
+        (from, until) => from.or(until).cloned(),
+    };
+    Ok((lines, highlight.filter(|_| color)))
+}
+
+// --until 在读到匹配的行时停止，只能与普通的行数一起使用，命令行、构造器和反序列化都按这条规则检查
+fn check_until_lines(until: Option<&Regex>, lines: Option<Count>) -> Result<()> {
+    match (until, lines) {
+        (Some(_), Some(Count::First(_)) | None) | (None, _) => Ok(()),
+        (Some(_), Some(_)) => Err(invalid!("--until only supports a plain line count")),
     }
 }
 
-/// 运行程序的主要逻辑
-pub fn run(config: Config) -> Result<()> {
-    let num_files = config.files.len();
-    for (file_num, filename) in config.files.iter().enumerate() {
-        match open(&filename) {
-            Err(e) => eprintln!("{}: {}", filename, e),
-            Ok(mut file) => {
-                //多个文件处理
-                if num_files > 1 {
-                    println!(
-                        "{}==> {} <==",
-                        if file_num > 0 { "\n" } else { "" },
-                        &filename
-                    );
-                }
-                if let Some(num_bytes) = config.bytes {
-                    let mut handle = file.take(num_bytes as u64);
-                    let mut buffer = vec![0; num_bytes];
-                    let bytes_read = handle.read(&mut buffer)?;
-                    print!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
-                } else {
-                    let mut line = String::new();
-                    for _ in 0..config.lines {
-                        let bytes = file.read_line(&mut line)?;
-                        if bytes == 0 {
-                            break;
-                        }
-                        print!("{}", line);
-                        line.clear();
-                    }
-                }
+// 命令行上是否选择了计数方式：给出了 -c 本身，或者给出了与 -c 互斥的选项（-n、-w、--tail 等）
+fn counting_given(command: &clap::Command, matches: &clap::ArgMatches) -> bool {
+    let given = |arg: &clap::Arg| {
+        matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    };
+    let Some(bytes) = command.get_arguments().find(|arg| arg.get_id() == "bytes") else {
+        return false;
+    };
+    // 互斥关系可能声明在 -c 上，也可能声明在另一个选项上
+    given(bytes)
+        || command.get_arg_conflicts_with(bytes).into_iter().any(given)
+        || command.get_arguments().any(|arg| {
+            given(arg)
+                && command
+                    .get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|other| other.get_id() == "bytes")
+        })
+}
+
+// 配置文件的位置：HEADER_CONFIG 指定的文件，否则是用户配置目录下的 header/config.toml
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("HEADER_CONFIG").filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+        None => Path::new(&env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("header").join("config.toml"))
+}
+
+// 读取配置文件中 [profile.NAME] 预设的选项，转换为命令行参数
+// 键是长选项名，值为 true 表示给出该开关，false 表示不给出，其余的值作为选项的值
+fn load_profile(name: &str) -> Result<Vec<OsString>> {
+    let path = config_path()
+        .ok_or_else(|| invalid!("Cannot locate the config file for --profile {}", name))?;
+    let text = fs::read_to_string(&path).map_err(open_error(&path))?;
+    let table: toml::Table = text
+        .parse()
+        .with_context(|| format!("Invalid config file: {}", path.display()))?;
+    let profile = table
+        .get("profile")
+        .and_then(|profiles| profiles.get(name))
+        .and_then(toml::Value::as_table)
+        .ok_or_else(|| invalid!("No profile '{}' in {}", name, path.display()))?;
+    let command = Cli::command();
+    let mut args = Vec::new();
+    for (key, value) in profile {
+        // 文件等位置参数仍在命令行上给出，也不能在预设中再选择预设
+        let known = key != "profile"
+            && command
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(key.as_str()));
+        if !known {
+            return Err(invalid!(
+                "Unknown option '{}' in profile '{}' ({})",
+                key,
+                name,
+                path.display()
+            ));
+        }
+        match value {
+            toml::Value::Boolean(true) => args.push(format!("--{}", key).into()),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(value) => args.push(format!("--{}={}", key, value).into()),
+            toml::Value::Integer(value) => args.push(format!("--{}={}", key, value).into()),
+            toml::Value::Float(value) => args.push(format!("--{}={}", key, value).into()),
+            _ => {
+                return Err(invalid!(
+                    "Unsupported value for '{}' in profile '{}' ({})",
+                    key,
+                    name,
+                    path.display()
+                ))
             }
         }
     }
-    Ok(())
+    Ok(args)
+}
+
+// 值解析器返回的 ParseCount 和 Invalid 错误从 clap 的错误中取出，保持原来的错误信息
+fn value_error(e: clap::Error) -> HeaderError {
+    let source = std::error::Error::source(&e).and_then(|source| source.downcast_ref());
+    match source {
+        Some(HeaderError::ParseCount {
+            what,
+            value,
+            reason,
+        }) => HeaderError::ParseCount {
+            what: what.clone(),
+            value: value.clone(),
+            reason: reason.clone(),
+        },
+        Some(HeaderError::Invalid(message)) => HeaderError::Invalid(message.clone()),
+        _ => HeaderError::Args(e),
+    }
+}
+
+// 计数参数的值解析器，解析失败时报告是哪一项计数以及原样给出的值
+fn count_parser<T: 'static>(
+    what: &'static str,
+    parse: fn(&str) -> Result<T>,
+) -> impl Fn(&str) -> Result<T> + Clone + Send + Sync + 'static {
+    move |s| parse(s).map_err(|e| HeaderError::parse_count(what, s, e))
+}
+
+// 以秒为单位的超时时间的值解析器，允许小数
+fn timeout_parser(
+    what: &'static str,
+) -> impl Fn(&str) -> Result<Duration> + Clone + Send + Sync + 'static {
+    move |secs| {
+        secs.parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| invalid!("Failed to parse {}: {}", what, secs))
+    }
+}
+
+// 解析带符号的计数，前导 '-' 表示“除末尾 N 个以外的全部”
+// 数值允许为 0，并可以带 K、MB、GiB 等倍数后缀，或者是 0% 到 100% 的百分比
+fn parse_count(s: &str) -> Result<Count> {
+    if let Some(percent) = s.strip_suffix('%') {
+        return match percent.parse() {
+            Ok(percent) if percent <= 100 => Ok(Count::Percent(percent)),
+            _ => Err(invalid!("invalid percentage: {}", s)),
+        };
+    }
+    match s.strip_prefix('-') {
+        Some(rest) => parse_size(rest).map(Count::AllButLast),
+        None => parse_size(s).map(Count::First),
+    }
+}
+
+// 解析行数，除普通计数外还支持 `START:END` 形式的范围，两端都可以省略
+fn parse_lines(s: &str) -> Result<Count> {
+    match s.split_once(':') {
+        Some((start, end)) => {
+            let start = match start {
+                "" => 1,
+                _ => parse_size(start)?,
+            };
+            let end = match end {
+                "" => u64::MAX,
+                _ => parse_size(end)?,
+            };
+            if start == 0 || end < start {
+                return Err(invalid!("invalid range: {}", s));
+            }
+            Ok(Count::Range(start, end))
+        }
+        None => parse_count(s),
+    }
+}
+
+// 解析字节数，除普通计数外还支持 `START-END` 形式的范围，END 可以省略
+fn parse_bytes(s: &str) -> Result<Count> {
+    match s.split_once('-') {
+        Some((start, end)) if !start.is_empty() => {
+            let start = parse_size(start)?;
+            let end = match end {
+                "" => u64::MAX,
+                _ => parse_size(end)?,
+            };
+            if start == 0 || end < start {
+                return Err(invalid!("invalid range: {}", s));
+            }
+            Ok(Count::Range(start, end))
+        }
+        _ => parse_count(s),
+    }
+}
+
+// 解析必须为正数的计数，例如步长、线程数
+fn parse_positive(s: &str) -> Result<usize> {
+    match parse_usize(s)? {
+        0 => Err(invalid!("illegal number: {}", s)),
+        n => Ok(n),
+    }
+}
+
+// 解析 --truncate-lines 的列数
+fn parse_columns(cols: &str) -> Result<usize> {
+    match cols.parse::<usize>() {
+        Ok(cols) if cols > 0 => Ok(cols),
+        _ => Err(invalid!("Failed to parse column count: {}", cols)),
+    }
+}
+
+// 解析 --encoding 的编码名称
+fn parse_encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| invalid!("Unknown encoding: {} (see --list-encodings)", label))
+}
+
+impl Default for Config {
+    // 与不带任何选项运行时相同：读取标准输入的开头 10 行
+    fn default() -> Self {
+        Config {
+            files: vec![PathBuf::from("-")],
+            lines: Count::First(10),
+            bytes: None,
+            mmap: false,
+            utf8_boundary: None,
+            quiet: false,
+            verbose: false,
+            delimiter: vec![b'\n'],
+            fail_fast: false,
+            gnu_errors: false,
+            compat: None,
+            tail: false,
+            follow: false,
+            skip: 0,
+            step: 1,
+            jobs: 1,
+            max_memory: 64 * 1024 * 1024,
+            max_line_bytes: MAX_LINE_BYTES,
+            io_backend: IoBackend::Std,
+            sample: None,
+            until: None,
+            until_exclusive: false,
+            from: None,
+            words: None,
+            chars: None,
+            strict_utf8: false,
+            graphemes: None,
+            paragraphs: None,
+            csv: false,
+            ndjson: false,
+            pretty: false,
+            record_start: None,
+            skip_binary: false,
+            label: "standard input".to_string(),
+            open_timeout: None,
+            read_timeout: None,
+            encoding: None,
+            list_encodings: false,
+            completions: None,
+            strip_bom: false,
+            crlf: Crlf::Keep,
+            number: None,
+            offsets: None,
+            hex: false,
+            palette: Palette::default(),
+            output: OutputFormat::Text,
+            output_dir: None,
+            output_file: None,
+            header_format: "==> {path} <==".to_string(),
+            final_newline: None,
+            squeeze_blank: false,
+            truncate_lines: None,
+            show_nonprinting: false,
+            syntax: false,
+            pager: false,
+            tty_warning: false,
+            print0: false,
+            eol: None,
+            stats: false,
+            highlight: None,
+            stop: Arc::default(),
+        }
+    }
+}
+
+impl Config {
+    /// 不经过命令行参数构造配置，未设置的项与命令行的默认值相同
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// 是否按 GNU head 的格式报告错误
+    pub fn gnu_errors(&self) -> bool {
+        self.gnu_errors
+    }
+
+    /// 是否在输出完后继续输出文件新追加的内容
+    pub fn follow(&self) -> bool {
+        self.follow
+    }
+
+    /// --follow 的停止标志，置为 true 后 run 在下一次检查时结束；库不处理信号，
+    /// 由调用者在 Ctrl-C 等时机置位。标志不会被清除，每个 Config 只能停止一次
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    /// 报告错误时模仿哪个平台的 head，--gnu-errors 等同于 gnu
+    pub fn compat(&self) -> Option<Compat> {
+        if self.gnu_errors {
+            Some(Compat::Gnu)
+        } else {
+            self.compat
+        }
+    }
+
+    /// 检查不依赖命令行解析的约束，ConfigBuilder::build 和反序列化时都会调用
+    pub fn validate(&self) -> Result<()> {
+        if self.files.is_empty() {
+            return Err(invalid!("No input files"));
+        }
+        if self.delimiter.is_empty() {
+            return Err(invalid!("Record delimiter must not be empty"));
+        }
+        if self.step == 0 {
+            return Err(HeaderError::parse_count(
+                "step",
+                "0",
+                invalid!("illegal number: 0"),
+            ));
+        }
+        if self.jobs == 0 {
+            return Err(HeaderError::parse_count(
+                "jobs",
+                "0",
+                invalid!("illegal number: 0"),
+            ));
+        }
+        if self.max_line_bytes == 0 {
+            return Err(HeaderError::parse_count(
+                "line limit",
+                "0",
+                invalid!("illegal number: 0"),
+            ));
+        }
+        if self.tail && self.bytes.is_some() {
+            return Err(invalid!("tail and bytes cannot be used together"));
+        }
+        check_until_lines(self.until.as_ref(), Some(self.lines))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Config {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Config::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Config {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // 交给 ConfigBuilder::build 做与构造器相同的检查和整理，例如 --until 的行数和高亮
+        let mut config = Config::deserialize(deserializer)?;
+        let builder = ConfigBuilder {
+            lines: Some(config.lines).filter(|_| config.bytes.is_none()),
+            until: config.until.take().map(|until| until.as_str().to_string()),
+            from: config.from.take().map(|from| from.as_str().to_string()),
+            config,
+        };
+        builder
+            .build()
+            .map_err(|e| serde::de::Error::custom(error_chain(&e)))
+    }
+}
+
+// Config 中没有实现 serde 的字段：正则表达式保存为模式字符串，编码保存为名称
+#[cfg(feature = "serde")]
+mod serde_support {
+    pub mod regex {
+        use regex::bytes::Regex;
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            regex: &Option<Regex>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match regex {
+                Some(regex) => serializer.serialize_some(regex.as_str()),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Regex>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|pattern| Regex::new(&pattern).map_err(D::Error::custom))
+                .transpose()
+        }
+    }
+
+    pub mod encoding {
+        use encoding_rs::Encoding;
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            encoding: &Option<&'static Encoding>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match encoding {
+                Some(encoding) => serializer.serialize_some(encoding.name()),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<&'static Encoding>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|label| {
+                    Encoding::for_label(label.as_bytes())
+                        .ok_or_else(|| D::Error::custom(format!("unknown encoding: {}", label)))
+                })
+                .transpose()
+        }
+    }
+}
+
+impl From<u64> for Count {
+    fn from(count: u64) -> Self {
+        Count::First(count)
+    }
+}
+
+/// Config 的构造器，build 时做与命令行参数相同的检查
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+    lines: Option<Count>,  // 与 bytes 不能同时设置
+    until: Option<String>, // build 时才编译正则表达式
+    from: Option<String>,
+}
+
+impl ConfigBuilder {
+    /// 要处理的文件，`-` 表示标准输入
+    pub fn files<I: IntoIterator<Item = P>, P: Into<PathBuf>>(mut self, files: I) -> Self {
+        self.config.files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 要显示的行数，`lines(5)` 即开头 5 行
+    pub fn lines(mut self, lines: impl Into<Count>) -> Self {
+        self.lines = Some(lines.into());
+        self
+    }
+
+    /// 要显示的字节数
+    pub fn bytes(mut self, bytes: impl Into<Count>) -> Self {
+        self.config.bytes = Some(bytes.into());
+        self
+    }
+
+    /// 多个文件时也不打印文件名标题
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    /// 总是打印文件名标题
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    /// 记录分隔符，默认为换行符
+    pub fn delimiter(mut self, delimiter: impl Into<Vec<u8>>) -> Self {
+        self.config.delimiter = delimiter.into();
+        self
+    }
+
+    /// 遇到第一个无法打开的文件时立即返回错误
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// 按 GNU head 的格式报告错误
+    pub fn gnu_errors(mut self, gnu_errors: bool) -> Self {
+        self.config.gnu_errors = gnu_errors;
+        self
+    }
+
+    /// 模仿某个平台 head 的错误信息措辞
+    pub fn compat(mut self, compat: Compat) -> Self {
+        self.config.compat = Some(compat);
+        self
+    }
+
+    /// 输出末尾而不是开头的行
+    pub fn tail(mut self, tail: bool) -> Self {
+        self.config.tail = tail;
+        self
+    }
+
+    /// 输出前先跳过的行数
+    pub fn skip(mut self, skip: u64) -> Self {
+        self.config.skip = skip;
+        self
+    }
+
+    /// 每隔 step 行输出一行
+    pub fn step(mut self, step: usize) -> Self {
+        self.config.step = step;
+        self
+    }
+
+    /// 遇到匹配该正则的行后停止输出，exclusive 时不输出这一行
+    pub fn until(mut self, pattern: impl Into<String>, exclusive: bool) -> Self {
+        self.until = Some(pattern.into());
+        self.config.until_exclusive = exclusive;
+        self
+    }
+
+    /// 从第一行匹配该正则的行开始输出
+    pub fn from(mut self, pattern: impl Into<String>) -> Self {
+        self.from = Some(pattern.into());
+        self
+    }
+
+    /// 给输出的行加上行号
+    pub fn number(mut self, number: bool) -> Self {
+        self.config.number = number.then_some(Numbering {
+            nonblank: false,
+            width: 6,
+        });
+        self
+    }
+
+    /// 以提示代替二进制文件的内容
+    pub fn skip_binary(mut self, skip_binary: bool) -> Self {
+        self.config.skip_binary = skip_binary;
+        self
+    }
+
+    /// 标准输入在标题中显示的名称
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.config.label = label.into();
+        self
+    }
+
+    /// 文件名标题的模板，占位符与 --header-format 相同
+    pub fn header_format(mut self, template: impl Into<String>) -> Self {
+        self.config.header_format = template.into();
+        self
+    }
+
+    /// 输出格式
+    pub fn output(mut self, output: OutputFormat) -> Self {
+        self.config.output = output;
+        self
+    }
+
+    /// 把全部输出写到该文件而不是标准输出
+    pub fn output_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.output_file = Some(path.into());
+        self
+    }
+
+    /// 同时处理多少个文件
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.config.jobs = jobs;
+        self
+    }
+
+    /// 检查各项设置并返回配置
+    /// 与命令行一样，设置了 until 而没有设置 lines 时不限制行数
+    pub fn build(self) -> Result<Config> {
+        let mut config = self.config;
+        if self.lines.is_some() && config.bytes.is_some() {
+            return Err(invalid!("lines and bytes cannot be used together"));
+        }
+        if let Some(pattern) = self.until {
+            config.until = Some(
+                Regex::new(&pattern).context(format!("Invalid --until pattern: {}", pattern))?,
+            );
+        }
+        if let Some(pattern) = self.from {
+            config.from =
+                Some(Regex::new(&pattern).context(format!("Invalid --from pattern: {}", pattern))?);
+        }
+        let (lines, highlight) = resolve_patterns(
+            config.from.as_ref(),
+            config.until.as_ref(),
+            self.lines,
+            config.palette.enabled,
+        )?;
+        config.lines = lines.unwrap_or(config.lines);
+        config.highlight = highlight;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+// 标准输出所在终端的宽度；不是终端时依次使用 COLUMNS 环境变量和 80
+fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(terminal_size::Width(width), _)| usize::from(width))
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .filter(|&width| width > 0)
+        .unwrap_or(80)
+}
+
+// 展开文件参数中的通配符，便于在不展开通配符的 shell（如 Windows）下使用
+// 存在同名文件时按字面处理；没有匹配任何文件的模式视为错误
+// 不是合法 UTF-8 的文件名不会是模式，按字面处理
+fn expand_globs(files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(files.len());
+    for file in files {
+        let pattern = file.to_str().filter(|file| {
+            file.contains(['*', '?', '['])
+                && *file != "-"
+                && !is_remote(file)
+                && !Path::new(file).exists()
+        });
+        let Some(pattern) = pattern else {
+            expanded.push(file);
+            continue;
+        };
+        let paths = glob::glob(pattern).context(format!("Invalid glob pattern: {}", pattern))?;
+        let before = expanded.len();
+        for path in paths {
+            expanded.push(path?);
+        }
+        if expanded.len() == before {
+            return Err(invalid!("No files match pattern: {}", file.display()));
+        }
+    }
+    Ok(expanded)
+}
+
+// -R 遍历目录时对符号链接的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SymlinkPolicy {
+    follow_dirs: bool,   // 进入指向目录的符号链接
+    follow_files: bool,  // 处理指向文件的符号链接
+    skip_dangling: bool, // 悬空的符号链接不报错，直接跳过
+}
+
+// 将目录参数替换为其下所有普通文件，其余参数保持原样
+fn expand_dirs(files: Vec<PathBuf>, policy: SymlinkPolicy) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(files.len());
+    for file in files {
+        if file != Path::new("-") && file.is_dir() {
+            let mut ancestors = Vec::new();
+            walk_dir(&file, policy, &mut ancestors, &mut expanded)?;
+        } else {
+            expanded.push(file);
+        }
+    }
+    Ok(expanded)
+}
+
+// 深度优先遍历目录，同一目录内按文件名排序，保证输出顺序稳定
+// ancestors 记录当前路径上各目录的真实路径，跟随符号链接时借此跳过循环
+fn walk_dir(
+    dir: &Path,
+    policy: SymlinkPolicy,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let real =
+        fs::canonicalize(dir).context(format!("Failed to read directory: {}", dir.display()))?;
+    if ancestors.contains(&real) {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(dir)
+        .context(format!("Failed to read directory: {}", dir.display()))?
+        .collect::<io::Result<Vec<_>>>()
+        .context(format!("Failed to read directory: {}", dir.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    ancestors.push(real);
+    for entry in entries {
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .context(format!("Failed to read file type: {}", path.display()))?;
+        if file_type.is_dir() {
+            walk_dir(&path, policy, ancestors, files)?;
+        } else if file_type.is_symlink() {
+            // --no-follow 忽略所有符号链接，悬空的也不例外
+            if !policy.follow_files && !policy.follow_dirs {
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() && policy.follow_dirs => {
+                    walk_dir(&path, policy, ancestors, files)?
+                }
+                Ok(meta) if meta.is_file() && policy.follow_files => files.push(path),
+                Ok(_) => {}
+                // 悬空链接交给后续的打开步骤报错，除非要求跳过
+                Err(_) if policy.skip_dangling => {}
+                Err(_) => files.push(path),
+            }
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+// 读取 --files-from 给出的文件列表，忽略空项
+fn read_file_list(list: &str, separator: u8) -> Result<Vec<PathBuf>> {
+    let mut reader: Box<dyn BufRead> = match list {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(
+            File::open(list).context(format!("Failed to open file list: {}", list))?,
+        )),
+    };
+    let mut files = Vec::new();
+    let mut buf = Vec::new();
+    while reader.read_until(separator, &mut buf)? > 0 {
+        if buf.last() == Some(&separator) {
+            buf.pop();
+        }
+        if separator == b'\n' && buf.last() == Some(&b'\r') {
+            buf.pop();
+        }
+        if !buf.is_empty() {
+            files.push(path_from_bytes(buf.clone()));
+        }
+        buf.clear();
+    }
+    Ok(files)
+}
+
+// 文件列表中的文件名按原样的字节处理，只有不支持任意字节文件名的平台才做有损转换
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(target_os = "wasi")]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::wasi::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(any(unix, target_os = "wasi")))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// 将旧式写法 `head -NUM` 改写为 `head -n NUM`，只检查第一个参数
+// 开头的 --files-from 和它的值跳过，`header --files-from list -5` 同样改写
+// 与 --files-from 一起出现的 `-0` 是 --null 的短写，不做改写
+fn expand_obsolete_args(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.into_iter().collect();
+    let is_files_from = |arg: &str| arg == "--files-from" || arg.starts_with("--files-from=");
+    let files_from = args
+        .iter()
+        .skip(1)
+        .map_while(|arg| arg.to_str().filter(|arg| *arg != "--"))
+        .any(is_files_from);
+    let mut index = 1;
+    while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()) {
+        match arg {
+            "--files-from" => index += 2,
+            _ if is_files_from(arg) => index += 1,
+            _ => break,
+        }
+    }
+    let count = args
+        .get(index)
+        .and_then(|arg| arg.to_str())
+        .and_then(|arg| arg.strip_prefix('-'))
+        .filter(|num| !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()))
+        .filter(|num| !(files_from && *num == "0"))
+        .map(OsString::from);
+    if let Some(count) = count {
+        args.splice(index..index + 1, [OsString::from("-n"), count]);
+    }
+    args
+}
+
+// 处理分隔符中的 \n、\t、\r、\0 与 \\ 转义，其余字符原样保留
+fn unescape(s: &str) -> Vec<u8> {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result.into_bytes()
+}
+
+/// 解析带可选倍数后缀的非负整数，与 GNU head 的写法一致：
+/// `b` = 512，`K`/`KiB` = 1024，`KB` = 1000，M、G、T、P、E 依此类推（字母不区分大小写）
+/// 结果总是 64 位的，不受目标平台 usize 宽度的限制；超出 u64 范围时报告数值过大
+fn parse_size(s: &str) -> Result<u64> {
+    let digits = s.strip_prefix('+').unwrap_or(s);
+    let split = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    let (number, suffix) = digits.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|e: std::num::ParseIntError| match e.kind() {
+            std::num::IntErrorKind::PosOverflow => invalid!("number too large: {}", s),
+            _ => invalid!("illegal number: {}", s),
+        })?;
+    let multiplier =
+        size_multiplier(suffix).ok_or_else(|| invalid!("invalid suffix '{}'", suffix))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| invalid!("number too large: {}", s))
+}
+
+// 线程数、内存上限等需要放进 usize 的数值，超出当前平台的范围时报告数值过大
+fn parse_usize(s: &str) -> Result<usize> {
+    usize::try_from(parse_size(s)?).map_err(|_| invalid!("number too large: {}", s))
+}
+
+// 根据后缀返回对应的倍数，不认识的后缀返回 None
+fn size_multiplier(suffix: &str) -> Option<u64> {
+    if suffix.is_empty() {
+        return Some(1);
+    }
+    if suffix == "b" {
+        return Some(512);
+    }
+    let mut chars = suffix.chars();
+    let power = match chars.next()?.to_ascii_uppercase() {
+        'K' => 1,
+        'M' => 2,
+        'G' => 3,
+        'T' => 4,
+        'P' => 5,
+        'E' => 6,
+        _ => return None,
+    };
+    // K 与 KiB 为 1024 的幂（IEC），KB 为 1000 的幂（SI）
+    let base: u64 = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    base.checked_pow(power)
+}
+
+// 打开后的输入
+struct Input {
+    reader: Box<dyn BufRead>,
+    size: Option<u64>, // 未经解压的普通文件的长度，可以 seek；其余情况为 None
+}
+
+// 支持透明解压的压缩格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "xz")]
+    Xz,
+    #[cfg(feature = "bzip2")]
+    Bzip2,
+}
+
+impl Compression {
+    // 各格式的扩展名和文件开头的魔数
+    const FORMATS: &'static [(Compression, &'static str, &'static [u8])] = &[
+        (Compression::Gzip, ".gz", &[0x1f, 0x8b]),
+        #[cfg(feature = "zstd")]
+        (Compression::Zstd, ".zst", &[0x28, 0xb5, 0x2f, 0xfd]),
+        #[cfg(feature = "xz")]
+        (
+            Compression::Xz,
+            ".xz",
+            &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        ),
+        #[cfg(feature = "bzip2")]
+        (Compression::Bzip2, ".bz2", b"BZh"),
+    ];
+
+    // 先按扩展名识别，再按魔数识别
+    fn detect(filename: &str, head: &[u8]) -> Option<Compression> {
+        let formats = Compression::FORMATS.iter();
+        formats
+            .clone()
+            .find(|(_, ext, _)| filename.ends_with(ext))
+            .or_else(|| {
+                formats
+                    .clone()
+                    .find(|(_, _, magic)| head.starts_with(magic))
+            })
+            .map(|&(compression, _, _)| compression)
+    }
+
+    fn decoder(self, reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>> {
+        Ok(match self {
+            Compression::Gzip => {
+                Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(BufReader::new(
+                zstd::stream::read::Decoder::with_buffer(reader)?,
+            )),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Box::new(BufReader::new(
+                xz2::bufread::XzDecoder::new_multi_decoder(reader),
+            )),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => {
+                Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader)))
+            }
+        })
+    }
+}
+
+// 打开输入时用到的参数
+#[derive(Debug, Default, Clone, Copy)]
+struct OpenParams {
+    prefix: Option<u64>,            // 字节模式下最多需要的前缀长度
+    open_timeout: Option<Duration>, // 打开 FIFO 或建立 TCP 连接的超时时间
+    read_timeout: Option<Duration>, // 读取 TCP 输入的超时时间
+}
+
+// 字节模式下最多需要读取的前缀长度，供远程输入只下载所需部分
+fn byte_prefix(config: &Config) -> Option<u64> {
+    match config.bytes {
+        Some(Count::First(n)) if n > 0 => Some(n),
+        Some(Count::Range(_, end)) if end != u64::MAX => Some(end),
+        _ => None,
+    }
+}
+
+// 判断参数是否为 HTTP(S) 地址
+fn is_url(filename: &str) -> bool {
+    filename.starts_with("http://") || filename.starts_with("https://")
+}
+
+// 判断参数是否为远程输入（HTTP(S) 地址或 S3 对象）
+fn is_remote(filename: &str) -> bool {
+    is_url(filename) || filename.starts_with("s3://") || filename.starts_with("tcp://")
+}
+
+// 未指定 --open-timeout 时建立 TCP 连接的最长时间
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 连接 tcp://HOST:PORT，依次尝试解析出的各个地址
+fn open_tcp(address: &str, params: OpenParams) -> Result<TcpInput> {
+    let connect_timeout = params.open_timeout.unwrap_or(TCP_CONNECT_TIMEOUT);
+    let mut last_error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, connect_timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(params.read_timeout)?;
+                return Ok(TcpInput {
+                    stream,
+                    read_timeout: params.read_timeout,
+                });
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => e.into(),
+        None => invalid!("no addresses found for {}", address),
+    })
+}
+
+// TCP 输入，把读超时转换为容易理解的错误
+struct TcpInput {
+    stream: TcpStream,
+    read_timeout: Option<Duration>,
+}
+
+impl Read for TcpInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "no data received for {:?}",
+                    self.read_timeout.unwrap_or_default()
+                ),
+            ),
+            _ => e,
+        })
+    }
+}
+
+// 以流的方式读取 HTTP(S) 响应，已知所需长度时只请求开头的 prefix 个字节
+#[cfg(feature = "http")]
+fn open_url(
+    url: &str,
+    prefix: Option<u64>,
+    headers: &[(&str, String)],
+) -> Result<Box<dyn BufRead>> {
+    let mut request = ureq::get(url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    if let Some(prefix) = prefix {
+        request = request.set("Range", &format!("bytes=0-{}", prefix - 1));
+    }
+    let response = request.call().map_err(|e| match e {
+        ureq::Error::Status(code, response) => {
+            invalid!("HTTP status {} {}", code, response.status_text())
+        }
+        e => HeaderError::Other(Box::new(e)),
+    })?;
+    // 服务器不支持 Range 时返回 200 和完整内容，照常流式读取即可
+    Ok(Box::new(BufReader::new(response.into_reader())))
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(
+    _url: &str,
+    _prefix: Option<u64>,
+    _headers: &[(&str, String)],
+) -> Result<Box<dyn BufRead>> {
+    Err(invalid!("HTTP support is not enabled in this build"))
+}
+
+// 用 AWS Signature Version 4 签名的 GetObject 请求读取 S3 对象
+// 凭证与区域取自 AWS_ACCESS_KEY_ID、AWS_SECRET_ACCESS_KEY、AWS_SESSION_TOKEN、AWS_REGION；
+// 设置 AWS_ENDPOINT_URL 时改用路径形式访问该地址（如 MinIO）
+#[cfg(feature = "s3")]
+fn open_s3(location: &str, prefix: Option<u64>) -> Result<Box<dyn BufRead>> {
+    use hmac::{Hmac, KeyInit, Mac};
+    use sha2::{Digest, Sha256};
+
+    let (bucket, key) = location
+        .strip_prefix("s3://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| invalid!("invalid S3 location, expected s3://BUCKET/KEY"))?;
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+    let access_key =
+        var("AWS_ACCESS_KEY_ID").ok_or_else(|| invalid!("AWS_ACCESS_KEY_ID is not set"))?;
+    let secret_key =
+        var("AWS_SECRET_ACCESS_KEY").ok_or_else(|| invalid!("AWS_SECRET_ACCESS_KEY is not set"))?;
+    let region = var("AWS_REGION")
+        .or_else(|| var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|| "us-east-1".to_string());
+
+    // 对象键按 URI 规则编码，保留 '/'
+    let mut path = String::new();
+    for &b in key.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                path.push(b as char)
+            }
+            _ => path.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    let (base, path) = match var("AWS_ENDPOINT_URL") {
+        Some(endpoint) => (
+            endpoint.trim_end_matches('/').to_string(),
+            format!("/{}/{}", bucket, path),
+        ),
+        None => (
+            format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+            format!("/{}", path),
+        ),
+    };
+    let host = base
+        .split_once("://")
+        .map_or(base.as_str(), |(_, rest)| rest)
+        .to_string();
+
+    let (date, datetime) = amz_timestamp();
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", "UNSIGNED-PAYLOAD".to_string()),
+        ("x-amz-date", datetime.clone()),
+    ];
+    if let Some(token) = var("AWS_SESSION_TOKEN") {
+        headers.push(("x-amz-security-token", token));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "GET\n{}\n\n{}\n{}\nUNSIGNED-PAYLOAD",
+        path, canonical_headers, signed_headers
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        datetime,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let hmac = |key: &[u8], data: &str| -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    };
+    let signing_key = ["s3", "aws4_request"].iter().fold(
+        hmac(
+            hmac(format!("AWS4{}", secret_key).as_bytes(), &date).as_slice(),
+            &region,
+        ),
+        |key, part| hmac(&key, part),
+    );
+    let signature = hex(&hmac(&signing_key, &string_to_sign));
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key, scope, signed_headers, signature
+        ),
+    ));
+    // host 头由 HTTP 客户端自行设置
+    headers.retain(|(name, _)| *name != "host");
+    open_url(&format!("{}{}", base, path), prefix, &headers)
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3(_location: &str, _prefix: Option<u64>) -> Result<Box<dyn BufRead>> {
+    Err(invalid!("S3 support is not enabled in this build"))
+}
+
+// 小写十六进制编码
+#[cfg(feature = "s3")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 当前 UTC 时间，返回签名所需的 `YYYYMMDD` 与 `YYYYMMDDTHHMMSSZ` 两种形式
+#[cfg(feature = "s3")]
+fn amz_timestamp() -> (String, String) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil::civil_from_days(days as i64);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let datetime = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    );
+    (date, datetime)
+}
+
+// 打开远程输入，fetch 按给定的前缀长度发出请求
+fn open_remote(
+    name: &str,
+    prefix: Option<u64>,
+    fetch: impl Fn(Option<u64>) -> Result<Box<dyn BufRead>>,
+) -> Result<Input> {
+    // 压缩内容的前缀解压后长度未知，此时不能只请求前缀
+    let prefix = prefix.filter(|_| Compression::detect(name, &[]).is_none());
+    let mut reader = fetch(prefix)?;
+    if let Some(compression) = Compression::detect(name, reader.fill_buf()?) {
+        if prefix.is_some() {
+            reader = fetch(None)?;
+        }
+        reader = compression.decoder(reader)?;
+    }
+    Ok(Input { reader, size: None })
+}
+
+// 拆分 `ARCHIVE::MEMBER` 形式的参数，要求归档部分是已存在的文件
+fn split_archive_member(filename: &str) -> Option<(&str, &str)> {
+    filename
+        .split_once("::")
+        .filter(|(archive, member)| !member.is_empty() && Path::new(archive).is_file())
+}
+
+// zip 文件开头的魔数
+const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+
+// 不解包归档，直接读取其中的一个成员；按扩展名或魔数区分 zip 与 tar
+fn open_archive_member(archive: &str, member: &str) -> Result<Input> {
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    File::open(archive)
+        .map_err(open_error(Path::new(archive)))?
+        .take(ZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let is_zip = archive.ends_with(".zip") || magic == ZIP_MAGIC;
+    // 成员本身是压缩文件时同样透明解压
+    let mut reader: Box<dyn BufRead> = match is_zip {
+        true => Box::new(open_zip_member(archive, member)?),
+        false => Box::new(open_tar_member(archive, member)?),
+    };
+    if let Some(compression) = Compression::detect(member, reader.fill_buf()?) {
+        reader = compression.decoder(reader)?;
+    }
+    Ok(Input { reader, size: None })
+}
+
+// 在 tar 归档（可以是压缩的）中定位成员，返回只读取该成员内容的 reader
+// tar 的条目借用归档本身，因此先扫描出成员的位置与长度，再重新打开归档读取
+fn open_tar_member(archive: &str, member: &str) -> Result<impl BufRead> {
+    let wanted = Path::new(member.trim_start_matches("./"));
+    let input = open(Path::new(archive), OpenParams::default())?;
+    let seekable = input.size.is_some();
+    let mut tar = tar::Archive::new(input.reader);
+    let mut found = None;
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        if entry.header().entry_type().is_file()
+            && path.strip_prefix("./").unwrap_or(&path) == wanted
+        {
+            found = Some((entry.raw_file_position(), entry.size()));
+            break;
+        }
+    }
+    let (position, len) =
+        found.ok_or_else(|| invalid!("no member {} in archive {}", member, archive))?;
+    let reader: Box<dyn BufRead> = if seekable {
+        let mut file = File::open(archive).map_err(open_error(Path::new(archive)))?;
+        file.seek(SeekFrom::Start(position))?;
+        Box::new(BufReader::new(file))
+    } else {
+        let mut reader = open(Path::new(archive), OpenParams::default())?.reader;
+        io::copy(&mut reader.by_ref().take(position), &mut io::sink())?;
+        reader
+    };
+    Ok(reader.take(len))
+}
+
+// 在 zip 归档中定位成员，返回流式解压该成员的 reader
+// 与 tar 相同，先从中央目录取得成员数据的位置，再单独打开文件读取
+fn open_zip_member(archive: &str, member: &str) -> Result<Box<dyn BufRead>> {
+    let file = File::open(archive).map_err(open_error(Path::new(archive)))?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(file))
+        .context(format!("Invalid zip archive: {}", archive))?;
+    let name = member.trim_start_matches("./");
+    let index = zip
+        .index_for_name(name)
+        .ok_or_else(|| invalid!("no member {} in archive {}", member, archive))?;
+    let entry = zip.by_index_raw(index)?;
+    if !entry.is_file() {
+        return Err(invalid!(
+            "member {} in archive {} is not a file",
+            member,
+            archive
+        ));
+    }
+    if entry.encrypted() {
+        return Err(invalid!(
+            "member {} in archive {} is encrypted",
+            member,
+            archive
+        ));
+    }
+    let (position, len, method) = (
+        entry.data_start(),
+        entry.compressed_size(),
+        entry.compression(),
+    );
+    let mut file = File::open(archive).map_err(open_error(Path::new(archive)))?;
+    file.seek(SeekFrom::Start(position))?;
+    let data = BufReader::new(file).take(len);
+    match method {
+        zip::CompressionMethod::Stored => Ok(Box::new(data)),
+        zip::CompressionMethod::Deflated => Ok(Box::new(BufReader::new(
+            flate2::bufread::DeflateDecoder::new(data),
+        ))),
+        other => Err(invalid!(
+            "member {} in archive {} uses unsupported compression method {}",
+            member,
+            archive,
+            other
+        )),
+    }
+}
+
+// 打开本地文件；没有写入者的 FIFO 会一直阻塞，给定超时时间时改在后台线程中打开
+fn open_file(filename: &Path, timeout: Option<Duration>) -> io::Result<File> {
+    #[cfg(unix)]
+    if let Some(timeout) = timeout {
+        use std::os::unix::fs::FileTypeExt;
+        if fs::metadata(filename).is_ok_and(|meta| meta.file_type().is_fifo()) {
+            return open_fifo(filename, timeout);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = timeout;
+    File::open(filename)
+}
+
+#[cfg(unix)]
+fn open_fifo(filename: &Path, timeout: Duration) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let path = filename.to_path_buf();
+    thread::spawn(move || sender.send(File::open(path)));
+    match receiver.recv_timeout(timeout) {
+        Ok(file) => file,
+        Err(_) => {
+            // 以非阻塞方式作为写入者打开一次，让后台线程中的 open 返回，避免线程一直挂起
+            let _ = fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(filename);
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {:?} waiting for a writer", timeout),
+            ))
+        }
+    }
+}
+
+fn open(filename: &Path, params: OpenParams) -> Result<Input> {
+    let prefix = params.prefix;
+    if let Some((archive, member)) = filename.to_str().and_then(split_archive_member) {
+        if !filename.exists() {
+            return open_archive_member(archive, member);
+        }
+    }
+    // 标准输入和远程输入的写法都是 UTF-8，其余的按本地文件打开
+    let reader: Box<dyn BufRead> = match filename.to_str() {
+        Some("-") => Box::new(BufReader::new(io::stdin())),
+        Some(url) if is_url(url) => {
+            return open_remote(url, prefix, |prefix| open_url(url, prefix, &[]))
+                .context(format!("Failed to open URL: {}", url));
+        }
+        Some(object) if object.starts_with("s3://") => {
+            return open_remote(object, prefix, |prefix| open_s3(object, prefix))
+                .context(format!("Failed to open S3 object: {}", object));
+        }
+        Some(tcp) if tcp.starts_with("tcp://") => {
+            let stream = open_tcp(&tcp["tcp://".len()..], params)
+                .context(format!("Failed to connect: {}", tcp))?;
+            return Ok(Input {
+                reader: Box::new(BufReader::new(stream)),
+                size: None,
+            });
+        }
+        // 先检查是否为目录，不必等到读取时才得到 EISDIR
+        _ if fs::metadata(filename).is_ok_and(|meta| meta.is_dir()) => {
+            return Err(HeaderError::IsDirectory {
+                path: filename.to_path_buf(),
+            });
+        }
+        _ => Box::new(BufReader::new(
+            open_file(filename, params.open_timeout).map_err(open_error(filename))?,
+        )),
+    };
+    into_input(filename, reader)
+}
+
+// 压缩的输入透明解压，之后的处理与普通输入相同
+fn into_input(filename: &Path, mut reader: Box<dyn BufRead>) -> Result<Input> {
+    if let Some(compression) = Compression::detect(&filename.to_string_lossy(), reader.fill_buf()?)
+    {
+        return Ok(Input {
+            reader: compression.decoder(reader)?,
+            size: None,
+        });
+    }
+    Ok(Input {
+        reader,
+        size: regular_file_len(filename),
+    })
+}
+
+// --io-backend uring 每批预先打开的文件数，以及每个文件预先读取的字节数
+const PREFETCH_BATCH: usize = 256;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const PREFETCH_LEN: usize = 16 * 1024;
+
+// 预先打开的文件和从开头读到的内容，文件的读取位置仍在开头
+type Prefetched = io::Result<(File, Vec<u8>)>;
+
+// 先输出预先读到的内容，再接着读取文件的其余部分
+fn open_prefetched(filename: &Path, prefetched: Prefetched) -> Result<Input> {
+    let (mut file, head) = prefetched.map_err(open_error(filename))?;
+    file.seek(SeekFrom::Start(head.len() as u64))?;
+    into_input(
+        filename,
+        Box::new(BufReader::new(Cursor::new(head).chain(file))),
+    )
+}
+
+// 通过 io_uring 打开 files 中的普通文件并读取开头的 PREFETCH_LEN 个字节，
+// 先成批提交所有的 openat，再成批提交所有的 read；结果以 first 开始的序号为键
+// FIFO、标准输入、远程输入等不预先打开；io_uring 不可用时返回空表，全部按原来的方式打开
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn prefetch(files: &[PathBuf], first: usize) -> HashMap<usize, Prefetched> {
+    use io_uring::{opcode, types, IoUring};
+    use std::{
+        ffi::CString,
+        os::unix::ffi::OsStrExt,
+        os::unix::io::{AsRawFd, FromRawFd},
+    };
+
+    let mut prefetched = HashMap::new();
+    let paths: Vec<(usize, CString)> = files
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| {
+            !file.to_str().is_some_and(is_remote)
+                && fs::metadata(file).is_ok_and(|meta| meta.is_file())
+        })
+        .filter_map(|(i, file)| Some((first + i, CString::new(file.as_os_str().as_bytes()).ok()?)))
+        .collect();
+    if paths.is_empty() {
+        return prefetched;
+    }
+    let Ok(mut ring) = IoUring::new(paths.len().next_power_of_two() as u32) else {
+        return prefetched;
+    };
+    for (file_num, path) in &paths {
+        let open = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr())
+            .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+            .build()
+            .user_data(*file_num as u64);
+        // SAFETY: path 在等待完成期间一直有效
+        if unsafe { ring.submission().push(&open) }.is_err() {
+            // 还没有提交任何请求，全部按原来的方式打开
+            return prefetched;
+        }
+    }
+    let mut files = HashMap::new();
+    let opened = wait_all(
+        &mut ring,
+        paths.len(),
+        &mut |file_num, result| match result {
+            // SAFETY: openat 成功时返回一个新的文件描述符，由 File 接管
+            fd if fd >= 0 => {
+                files.insert(file_num, unsafe { File::from_raw_fd(fd) });
+            }
+            // 文件描述符不够用时留给之后的 File::open，那时前面的文件已经关闭
+            errno if -errno == libc::EMFILE || -errno == libc::ENFILE => {}
+            errno => {
+                prefetched.insert(file_num, Err(io::Error::from_raw_os_error(-errno)));
+            }
+        },
+    );
+    if !opened {
+        // 内核可能仍在使用这些路径，不能释放；已经打开的文件随 files 关闭，全部按原来的方式打开
+        std::mem::forget(paths);
+        return HashMap::new();
+    }
+    let mut heads: HashMap<usize, Vec<u8>> = files
+        .keys()
+        .map(|&file_num| (file_num, vec![0; PREFETCH_LEN]))
+        .collect();
+    let mut submitted = 0;
+    for (&file_num, head) in heads.iter_mut() {
+        let read = opcode::Read::new(
+            types::Fd(files[&file_num].as_raw_fd()),
+            head.as_mut_ptr(),
+            PREFETCH_LEN as u32,
+        )
+        .offset(0)
+        .build()
+        .user_data(file_num as u64);
+        // SAFETY: head 和文件在等待完成期间一直有效
+        if unsafe { ring.submission().push(&read) }.is_err() {
+            break;
+        }
+        submitted += 1;
+    }
+    let mut lens = HashMap::new();
+    let read = wait_all(&mut ring, submitted, &mut |file_num, result| {
+        lens.insert(file_num, result);
+    });
+    if !read {
+        // 内核可能仍在写入这些缓冲区，不能释放
+        std::mem::forget(heads);
+        return HashMap::new();
+    }
+    for (file_num, file) in files {
+        let mut head = heads.remove(&file_num).unwrap_or_default();
+        match lens.get(&file_num) {
+            Some(&len) if len >= 0 => head.truncate(len as usize),
+            Some(&errno) => {
+                prefetched.insert(file_num, Err(io::Error::from_raw_os_error(-errno)));
+                continue;
+            }
+            // 没能放入提交队列的读取，之后从头读
+            None => head.clear(),
+        }
+        prefetched.insert(file_num, Ok((file, head)));
+    }
+    prefetched
+}
+
+// 提交已经放入提交队列的请求，并等待其中 pending 个全部完成，对每个结果调用 on_complete(序号, 结果)
+// 被信号中断或内核暂时无法接受时重试；返回 false 表示无法继续等待，这时可能还有请求没有完成
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn wait_all(
+    ring: &mut io_uring::IoUring,
+    mut pending: usize,
+    on_complete: &mut dyn FnMut(usize, i32),
+) -> bool {
+    while pending > 0 {
+        let submitted = ring.submit_and_wait(1);
+        for completion in ring.completion() {
+            pending -= 1;
+            on_complete(completion.user_data() as usize, completion.result());
+        }
+        match submitted.map_err(|e| e.raw_os_error()) {
+            Ok(_) | Err(Some(libc::EINTR | libc::EAGAIN | libc::EBUSY)) => {}
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+fn prefetch(_files: &[PathBuf], _first: usize) -> HashMap<usize, Prefetched> {
+    HashMap::new()
+}
+
+/// 把 reader 开头的 num_lines 行原样写到 writer，不足 num_lines 行时全部写出
+/// 供其他 crate 直接调用，不涉及命令行参数、文件和标准输出
+pub fn head_lines<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    num_lines: u64,
+) -> Result<()> {
+    print_lines(
+        &mut reader,
+        &mut writer,
+        &mut Offsets::new(None),
+        num_lines,
+        1,
+        b"\n",
+    )?;
+    Ok(writer.flush()?)
+}
+
+/// 把 reader 开头的 num_bytes 个字节原样写到 writer，不足 num_bytes 个字节时全部写出
+pub fn head_bytes<R: BufRead, W: Write>(reader: R, mut writer: W, num_bytes: u64) -> Result<()> {
+    print_bytes(Box::new(reader), &mut writer, num_bytes, None)?;
+    Ok(writer.flush()?)
+}
+
+/// 对 reader 开头的 num_lines 行依次调用 f，传入的行包含结尾的换行符
+/// f 返回 ControlFlow::Break 时立即结束，不再读取后面的内容
+pub fn head_with<R, F>(mut reader: R, num_lines: u64, mut f: F) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(&[u8]) -> ControlFlow<()>,
+{
+    // 同一个缓冲区反复使用，每行不必单独分配
+    let mut record = Vec::new();
+    for _ in 0..num_lines {
+        if read_record(&mut reader, b"\n", &mut record)? == 0 {
+            break;
+        }
+        if f(&record).is_break() {
+            break;
+        }
+        record.clear();
+    }
+    Ok(())
+}
+
+/// head_lines 的异步版本，读取时不阻塞线程，供基于 tokio 的服务使用
+#[cfg(feature = "async")]
+pub async fn head_lines_async<R, W>(mut reader: R, mut writer: W, num_lines: u64) -> Result<()>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let mut line = Vec::new();
+    for _ in 0..num_lines {
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        writer.write_all(&line).await?;
+        line.clear();
+    }
+    Ok(writer.flush().await?)
+}
+
+/// 逐条读取 reader 开头最多 num_lines 条记录的迭代器，记录包含结尾的分隔符
+/// 只在迭代时才读取，读到需要的记录数后不再读取剩下的内容
+#[derive(Debug)]
+pub struct HeadLines<R> {
+    reader: R,
+    remaining: u64,
+    delimiter: Vec<u8>,
+}
+
+impl<R: BufRead> HeadLines<R> {
+    /// 以换行符分隔记录
+    pub fn new(reader: R, num_lines: u64) -> Self {
+        HeadLines::with_delimiter(reader, num_lines, b"\n")
+    }
+
+    /// 以 delimiter 分隔记录，delimiter 为空时整个输入是一条记录
+    pub fn with_delimiter(reader: R, num_lines: u64, delimiter: &[u8]) -> Self {
+        HeadLines {
+            reader,
+            remaining: num_lines,
+            delimiter: delimiter.to_vec(),
+        }
+    }
+
+    /// 取回 reader，其中还有没读取的内容
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: BufRead> Iterator for HeadLines<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut record = Vec::new();
+        match read_record(&mut self.reader, &self.delimiter, &mut record) {
+            Ok(0) => {
+                self.remaining = 0;
+                None
+            }
+            Ok(_) => {
+                self.remaining -= 1;
+                Some(Ok(record))
+            }
+            // 出错后不再继续读取
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// 单个输入的处理结果
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,              // 配置中给出的文件
+    pub lines: u64,                 // 写出的行数，结尾不完整的一行也算一行
+    pub bytes: u64,                 // 写出的字节数
+    pub error: Option<HeaderError>, // 无法打开或读取出错时的错误，这时 lines 和 bytes 为 0
+}
+
+impl FileReport {
+    /// 按 GNU head 的格式描述这个输入的错误，不带程序名
+    pub fn gnu_error(&self) -> Option<String> {
+        self.error.as_ref().map(|e| match e {
+            HeaderError::OpenFile { .. } | HeaderError::IsDirectory { .. } => e.gnu_message(),
+            _ => format!(
+                "error reading '{}': {}",
+                self.path.display(),
+                e.gnu_message()
+            ),
+        })
+    }
+
+    /// 按 compat 指定平台的 head 的格式描述这个输入的错误，不带程序名
+    pub fn compat_error(&self, compat: Compat) -> Option<String> {
+        match compat {
+            Compat::Gnu => self.gnu_error(),
+            Compat::Bsd | Compat::Posix => self.error.as_ref().map(|e| match e {
+                HeaderError::OpenFile { .. } | HeaderError::IsDirectory { .. } => e.bsd_message(),
+                _ => format!("{}: {}", self.path.display(), e.bsd_message()),
+            }),
+        }
+    }
+}
+
+/// run 的结果，按配置中的顺序列出每个输入的处理结果
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub files: Vec<FileReport>,
+}
+
+impl RunReport {
+    /// 进程的退出码：有文件出错时为 1，否则为 0
+    pub fn exit_code(&self) -> i32 {
+        i32::from(self.files.iter().any(|file| file.error.is_some()))
+    }
+
+    // 记录一个无法打开或读取出错的文件，并立即交给 on_error 报告
+    fn failed(
+        &mut self,
+        filename: &Path,
+        error: HeaderError,
+        on_error: &mut dyn FnMut(&FileReport),
+    ) {
+        let file = FileReport {
+            path: filename.to_path_buf(),
+            lines: 0,
+            bytes: 0,
+            error: Some(error),
+        };
+        on_error(&file);
+        self.files.push(file);
+    }
+}
+
+/// 运行程序的主要逻辑，返回每个文件的处理结果
+/// 有文件无法打开或读到一半出错时继续处理其余文件，错误记录在结果中而不是直接输出，
+/// 只有 fail_fast 或写入已关闭的管道时才立即返回错误
+pub fn run(config: Config) -> Result<RunReport> {
+    run_with(config, |_| {})
+}
+
+/// 与 run 相同，但每遇到一个无法打开的文件就立即用它的结果调用 on_error
+/// 调用时排在它前面的文件已经全部输出，在这里报告错误不会打乱与输出的先后顺序，
+/// --follow 时也不必等到结束
+pub fn run_with(config: Config, mut on_error: impl FnMut(&FileReport)) -> Result<RunReport> {
+    if config.list_encodings {
+        for encoding in ENCODINGS {
+            println!("{}", encoding.name());
+        }
+        return Ok(RunReport::default());
+    }
+    if let Some(shell) = config.completions {
+        clap_complete::generate(shell, &mut Cli::command(), "header", &mut io::stdout());
+        return Ok(RunReport::default());
+    }
+    if config.tty_warning {
+        let eof = if cfg!(windows) {
+            "Ctrl-Z, Enter"
+        } else {
+            "Ctrl-D"
+        };
+        eprintln!(
+            "header: reading from stdin; press {} to end, or pass FILE",
+            eof
+        );
+    }
+    let num_files = config.files.len();
+    // 结构化输出中每条记录都带有文件名，写到单独的文件时也不需要区分，都不再输出标题
+    let show_headers = (num_files > 1 || config.verbose)
+        && !config.quiet
+        && config.output == OutputFormat::Text
+        && config.output_dir.is_none();
+    let banner = show_headers.then_some(Banner {
+        template: &config.header_format,
+        palette: config.palette,
+    });
+    let destination = match &config.output_file {
+        Some(path) => {
+            check_not_input(path, &config.files)?;
+            Some(AtomicFile::create(path)?)
+        }
+        None => None,
+    };
+    let pager = config.pager.then(|| Rc::new(RefCell::new(Pager::new())));
+    let locked = LockedStdout::new();
+    // 标题等不经过格式化器的内容也要写到 -o 指定的文件中
+    let stdout = || -> io::Result<Box<dyn Write>> {
+        match (&destination, &pager) {
+            (Some(destination), _) => destination.writer(),
+            (None, Some(pager)) => Ok(Box::new(PagerWriter(Rc::clone(pager)))),
+            (None, None) => Ok(Box::new(locked.clone())),
+        }
+    };
+    let mut report = RunReport::default();
+    let mut followable = Vec::new();
+    let params = OpenParams {
+        prefix: byte_prefix(&config),
+        open_timeout: config.open_timeout,
+        read_timeout: config.read_timeout,
+    };
+    // 多个文件时输出文件名标题，separate 表示前面已有输出，先空一行
+    let write_banner = |file_num: usize, filename: &Path, separate: bool| -> Result<()> {
+        let Some(banner) = banner else {
+            return Ok(());
+        };
+        let name = display_name(&config, filename);
+        // 标题也按 --eol 换行
+        let mut header: Box<dyn OutputFormatter> = match config.eol {
+            Some(eol) => Box::new(ConvertEol::new(stdout()?, eol)),
+            None => Box::new(stdout()?),
+        };
+        writeln!(
+            header,
+            "{}{}",
+            if separate { "\n" } else { "" },
+            banner.render(&name.to_string_lossy(), filename, file_num)
+        )?;
+        header.finish(&mut io::empty())?;
+        Ok(())
+    };
+    // 输出文件名标题，并返回这个文件的内容应该写到哪里
+    let open_sink = |file_num: usize, filename: &Path| -> Result<Box<dyn Write>> {
+        write_banner(file_num, filename, file_num > 0)?;
+        Ok(match &config.output_dir {
+            Some(dir) => Box::new(create_output_file(dir, display_name(&config, filename))?),
+            None => stdout()?,
+        })
+    };
+    if config.jobs > 1 {
+        // 工作线程把每个文件的输出写到内存中，这里按参数的顺序依次输出
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.jobs)
+            .build()
+            .context("Failed to start worker threads")?;
+        let (sender, receiver) = mpsc::channel();
+        pool.in_place_scope(|scope| -> Result<()> {
+            for (file_num, filename) in config.files.iter().enumerate() {
+                let sender = sender.clone();
+                let config = &config;
+                scope.spawn(move |_| {
+                    // 读到一半出错时已经写出的部分仍然保留，与不开 --jobs 时一样先输出再报告
+                    let result = open(filename, params).map(|input| {
+                        let buffer = SharedBuffer::default();
+                        let headed = head_input(config, filename, input, Box::new(buffer.clone()));
+                        (buffer.take(), headed)
+                    });
+                    // 出错提前返回后接收端已经关闭，剩下的结果直接丢弃
+                    let _ = sender.send((file_num, result));
+                });
+            }
+            drop(sender);
+            let mut pending = HashMap::new();
+            let mut next = 0;
+            for (file_num, result) in receiver {
+                pending.insert(file_num, result);
+                while let Some(result) = pending.remove(&next) {
+                    let filename = &config.files[next];
+                    match result {
+                        Err(e) if config.fail_fast => return Err(e),
+                        Err(e) => report.failed(filename, e, &mut on_error),
+                        Ok((output, headed)) => {
+                            let mut sink = open_sink(next, filename)?;
+                            sink.write_all(&output)?;
+                            sink.flush()?;
+                            match headed {
+                                Ok(headed) => {
+                                    headed.record(next, filename, &mut followable, &mut report)
+                                }
+                                Err(e) if e.is_broken_pipe() || config.fail_fast => return Err(e),
+                                Err(e) => report.failed(filename, e, &mut on_error),
+                            }
+                        }
+                    }
+                    next += 1;
+                }
+            }
+            Ok(())
+        })?;
+    } else {
+        let mut prefetched = HashMap::new();
+        for (file_num, filename) in config.files.iter().enumerate() {
+            if config.io_backend == IoBackend::Uring && file_num % PREFETCH_BATCH == 0 {
+                let batch = &config.files[file_num..(file_num + PREFETCH_BATCH).min(num_files)];
+                prefetched = prefetch(batch, file_num);
+            }
+            let opened = match prefetched.remove(&file_num) {
+                Some(result) => open_prefetched(filename, result),
+                None => open(filename, params),
+            };
+            match opened {
+                Err(e) if config.fail_fast => return Err(e),
+                Err(e) => report.failed(filename, e, &mut on_error),
+                Ok(input) => {
+                    let sink = open_sink(file_num, filename)?;
+                    // 读取或解压到一半出错时记录在这个文件的结果中，继续处理后面的文件
+                    match head_input(&config, filename, input, sink) {
+                        Ok(headed) => {
+                            headed.record(file_num, filename, &mut followable, &mut report)
+                        }
+                        Err(e) if e.is_broken_pipe() || config.fail_fast => return Err(e),
+                        Err(e) => {
+                            // 先写出出错前的部分，错误信息排在它后面
+                            stdout()?.flush()?;
+                            report.failed(filename, e, &mut on_error)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if config.follow {
+        follow(&config, &followable, &write_banner, &stdout)?;
+    }
+    if config.stats {
+        print_stats(&config, &report);
+    }
+    if let Some(destination) = destination {
+        destination.commit()?;
+    }
+    if let Some(pager) = pager {
+        pager.borrow_mut().finish()?;
+    }
+    locked.clone().flush()?;
+    Ok(report)
+}
+
+// 标准输出在整个运行期间只加锁一次，并且不再逐行 flush，而是整块写出
+// 每个文件的输出结束时格式化器都会 flush，因此与标准错误上的错误信息不会乱序
+#[derive(Clone)]
+struct LockedStdout(Rc<RefCell<BufWriter<Box<dyn Write>>>>);
+
+impl LockedStdout {
+    fn new() -> Self {
+        let stdout: Box<dyn Write> = Box::new(io::stdout().lock());
+        // Windows 控制台上原样写出 GBK、Big5 等旧代码页的字节会显示为乱码，
+        // 这时改为转换成 UTF-16 写出；重定向到文件或管道时仍然原样输出
+        #[cfg(windows)]
+        let stdout: Box<dyn Write> = match console::Console::stdout() {
+            Some(console) => Box::new(console),
+            None => stdout,
+        };
+        LockedStdout(Rc::new(RefCell::new(BufWriter::new(stdout))))
+    }
+}
+
+// Windows 控制台的输出：合法的 UTF-8 按 UTF-8 转换，其余内容按系统的 ANSI 代码页转换，
+// 再通过 WriteConsoleW 以 UTF-16 写出
+// 切分和转换的逻辑在 Encoder 中，与平台无关，在其他平台上也能测试
+#[cfg(any(windows, test))]
+mod console {
+    use std::io;
+
+    // 按代码页把字节转换为 UTF-16 追加到第二个参数
+    pub(crate) type Decode = dyn Fn(&[u8], &mut Vec<u16>) -> io::Result<()>;
+
+    // 把写入的字节转换为 UTF-16，写入末尾不完整的字符留到下一次与后面的内容一起转换
+    #[derive(Default)]
+    pub(crate) struct Encoder {
+        pending: Vec<u8>,
+    }
+
+    impl Encoder {
+        // 转换 buf 并把结果追加到 wide。is_lead 判断代码页中双字节字符的前导字节，
+        // decode 按代码页把字节转换为 UTF-16 追加到 wide
+        pub(crate) fn encode(
+            &mut self,
+            buf: &[u8],
+            wide: &mut Vec<u16>,
+            is_lead: &dyn Fn(u8) -> bool,
+            decode: &Decode,
+        ) -> io::Result<()> {
+            self.pending.extend_from_slice(buf);
+            let pending = std::mem::take(&mut self.pending);
+            // 开头合法的 UTF-8 部分按 UTF-8 转换，从第一个非法字节开始按代码页转换
+            let (text, rest) = match std::str::from_utf8(&pending) {
+                Ok(text) => (text, &[][..]),
+                Err(e) => {
+                    let (valid, rest) = pending.split_at(e.valid_up_to());
+                    (std::str::from_utf8(valid).unwrap_or_default(), rest)
+                }
+            };
+            wide.extend(text.encode_utf16());
+            let keep = match std::str::from_utf8(rest) {
+                // 剩下的只是一个被截断的 UTF-8 字符
+                Err(e) if e.valid_up_to() == 0 && e.error_len().is_none() => rest.len(),
+                _ => incomplete_dbcs(rest, is_lead),
+            };
+            let (complete, incomplete) = rest.split_at(rest.len() - keep);
+            decode(complete, wide)?;
+            self.pending = incomplete.to_vec();
+            Ok(())
+        }
+
+        // 输出结束时剩下的不完整字符按代码页转换
+        pub(crate) fn finish(&mut self, wide: &mut Vec<u16>, decode: &Decode) -> io::Result<()> {
+            decode(&std::mem::take(&mut self.pending), wide)
+        }
+    }
+
+    // 末尾只有前导字节的双字节字符的长度，没有时为 0
+    fn incomplete_dbcs(bytes: &[u8], is_lead: &dyn Fn(u8) -> bool) -> usize {
+        let mut i = 0;
+        while i < bytes.len() {
+            i += if is_lead(bytes[i]) { 2 } else { 1 };
+        }
+        i - bytes.len()
+    }
+
+    #[cfg(windows)]
+    pub(crate) use windows::Console;
+
+    #[cfg(windows)]
+    mod windows {
+        use std::io::{self, IsTerminal, Write};
+
+        use windows_sys::Win32::{
+            Foundation::HANDLE,
+            Globalization::{GetACP, IsDBCSLeadByteEx, MultiByteToWideChar},
+            System::Console::{GetStdHandle, WriteConsoleW, STD_OUTPUT_HANDLE},
+        };
+
+        use super::Encoder;
+
+        pub(crate) struct Console {
+            handle: HANDLE,
+            codepage: u32, // 系统的 ANSI 代码页，例如 936（GBK）、950（Big5）
+            encoder: Encoder,
+        }
+
+        impl Console {
+            // 标准输出是控制台时才返回 Some
+            pub(crate) fn stdout() -> Option<Self> {
+                if !io::stdout().is_terminal() {
+                    return None;
+                }
+                // SAFETY: GetStdHandle 与 GetACP 没有前置条件
+                let (handle, codepage) = unsafe { (GetStdHandle(STD_OUTPUT_HANDLE), GetACP()) };
+                Some(Console {
+                    handle,
+                    codepage,
+                    encoder: Encoder::default(),
+                })
+            }
+
+            fn write_wide(&self, mut wide: &[u16]) -> io::Result<()> {
+                while !wide.is_empty() {
+                    let len = u32::try_from(wide.len()).unwrap_or(u32::MAX);
+                    let mut written = 0;
+                    // SAFETY: wide 在调用期间有效，written 指向局部变量
+                    let ok = unsafe {
+                        WriteConsoleW(
+                            self.handle,
+                            wide.as_ptr(),
+                            len,
+                            &mut written,
+                            std::ptr::null(),
+                        )
+                    };
+                    if ok == 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    wide = &wide[written as usize..];
+                }
+                Ok(())
+            }
+        }
+
+        // 代码页中双字节字符的前导字节
+        fn is_lead(codepage: u32, byte: u8) -> bool {
+            // SAFETY: IsDBCSLeadByteEx 没有前置条件
+            unsafe { IsDBCSLeadByteEx(codepage, byte) != 0 }
+        }
+
+        // 按代码页把 bytes 转换为 UTF-16 追加到 wide
+        fn decode_codepage(codepage: u32, bytes: &[u8], wide: &mut Vec<u16>) -> io::Result<()> {
+            if bytes.is_empty() {
+                return Ok(());
+            }
+            let len = i32::try_from(bytes.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+            let start = wide.len();
+            // SAFETY: 第一次调用只计算需要的长度，第二次调用写入的缓冲区正好有这么长
+            unsafe {
+                let needed =
+                    MultiByteToWideChar(codepage, 0, bytes.as_ptr(), len, std::ptr::null_mut(), 0);
+                if needed <= 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                wide.resize(start + needed as usize, 0);
+                let n = MultiByteToWideChar(
+                    codepage,
+                    0,
+                    bytes.as_ptr(),
+                    len,
+                    wide[start..].as_mut_ptr(),
+                    needed,
+                );
+                if n <= 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                wide.truncate(start + n as usize);
+            }
+            Ok(())
+        }
+
+        impl Write for Console {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let codepage = self.codepage;
+                let mut wide = Vec::new();
+                self.encoder.encode(
+                    buf,
+                    &mut wide,
+                    &|byte| is_lead(codepage, byte),
+                    &|bytes, wide| decode_codepage(codepage, bytes, wide),
+                )?;
+                self.write_wide(&wide)?;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Drop for Console {
+            fn drop(&mut self) {
+                let codepage = self.codepage;
+                let mut wide = Vec::new();
+                let decode =
+                    |bytes: &[u8], wide: &mut Vec<u16>| decode_codepage(codepage, bytes, wide);
+                if self.encoder.finish(&mut wide, &decode).is_ok() {
+                    let _ = self.write_wide(&wide);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::Encoder;
+        use std::io;
+
+        // 模拟 GBK：0x81..=0xFE 是前导字节，双字节字符转换为 (前导字节 << 8 | 后续字节)，其余字节原样转换
+        fn is_lead(byte: u8) -> bool {
+            (0x81..=0xFE).contains(&byte)
+        }
+
+        fn decode(bytes: &[u8], wide: &mut Vec<u16>) -> io::Result<()> {
+            let mut i = 0;
+            while i < bytes.len() {
+                match bytes.get(i + 1) {
+                    Some(&next) if is_lead(bytes[i]) => {
+                        wide.push(u16::from(bytes[i]) << 8 | u16::from(next));
+                        i += 2;
+                    }
+                    _ => {
+                        wide.push(u16::from(bytes[i]));
+                        i += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // 依次写入 writes，返回每次写入后得到的 UTF-16 和结束时剩下的部分
+        fn encode(writes: &[&[u8]]) -> (Vec<Vec<u16>>, Vec<u16>) {
+            let mut encoder = Encoder::default();
+            let outputs = writes
+                .iter()
+                .map(|buf| {
+                    let mut wide = Vec::new();
+                    encoder.encode(buf, &mut wide, &is_lead, &decode).unwrap();
+                    wide
+                })
+                .collect();
+            let mut rest = Vec::new();
+            encoder.finish(&mut rest, &decode).unwrap();
+            (outputs, rest)
+        }
+
+        fn utf16(text: &str) -> Vec<u16> {
+            text.encode_utf16().collect()
+        }
+
+        #[test]
+        fn utf8_split_across_writes() {
+            // “中” 的三个字节分在两次写入中，第一次写入不输出任何内容
+            let (outputs, rest) = encode(&[b"a\xE4", b"\xB8\xADb"]);
+            assert_eq!(outputs, [utf16("a"), utf16("中b")]);
+            assert!(rest.is_empty());
+            // 四字节字符逐字节写入
+            let bytes = "😀".as_bytes();
+            let writes: Vec<&[u8]> = bytes.chunks(1).collect();
+            let (outputs, _) = encode(&writes);
+            assert_eq!(outputs.concat(), utf16("😀"));
+            assert!(outputs[..3].iter().all(Vec::is_empty));
+        }
+
+        #[test]
+        fn codepage_after_invalid_utf8() {
+            // GBK 的 “中” 是 D6 D0：D6 像是 UTF-8 双字节字符的开头，等到下一次写入才知道不是 UTF-8
+            let (outputs, rest) = encode(&[b"x\xD6", b"\xD0y"]);
+            assert_eq!(outputs, [utf16("x"), vec![0xD6D0, u16::from(b'y')]]);
+            assert!(rest.is_empty());
+        }
+
+        #[test]
+        fn codepage_lead_byte_carried() {
+            // 按代码页转换的部分末尾只有前导字节时，留到下一次写入
+            let (outputs, rest) = encode(&[b"\xFF\xC4", b"\xE3z"]);
+            assert_eq!(outputs, [vec![0xFF], vec![0xC4E3, u16::from(b'z')]]);
+            assert!(rest.is_empty());
+        }
+
+        #[test]
+        fn incomplete_character_at_end() {
+            // 结束时仍不完整的字符按代码页转换
+            let (outputs, rest) = encode(&[b"ok\xE4\xB8"]);
+            assert_eq!(outputs, [utf16("ok")]);
+            assert_eq!(rest, [0xE4B8]);
+        }
+    }
+}
+
+impl Write for LockedStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// 标准输入在标题等处显示为 --label 指定的名称
+fn display_name<'a>(config: &'a Config, filename: &'a Path) -> &'a Path {
+    match filename.to_str() {
+        Some("-") => Path::new(&config.label),
+        _ => filename,
+    }
+}
+
+// 处理完一个文件后需要记录下来的信息
+struct Headed {
+    followable: bool, // --follow 只跟踪未压缩的普通文件
+    stats: Stats,
+}
+
+impl Headed {
+    fn record(
+        self,
+        file_num: usize,
+        filename: &Path,
+        followable: &mut Vec<(usize, PathBuf)>,
+        report: &mut RunReport,
+    ) {
+        if self.followable {
+            followable.push((file_num, filename.to_path_buf()));
+        }
+        report.files.push(FileReport {
+            path: filename.to_path_buf(),
+            lines: self.stats.lines(),
+            bytes: self.stats.bytes,
+            error: None,
+        });
+    }
+}
+
+// 处理一个已经打开的输入，把格式化后的内容写到 sink
+fn head_input(
+    config: &Config,
+    filename: &Path,
+    mut input: Input,
+    sink: Box<dyn Write>,
+) -> Result<Headed> {
+    if let Some(encoding) = config.encoding {
+        input = decode(input, encoding, config.strip_bom);
+    } else if config.strip_bom {
+        input = strip_bom(input)?;
+    }
+    if config.crlf != Crlf::Keep {
+        input = normalize_crlf(input, config.crlf);
+    }
+    let name = &display_name(config, filename).to_string_lossy();
+    // 二进制文件的提示不算作输出的内容
+    let counted = Rc::new(Cell::new(Stats::default()));
+    if config.skip_binary && looks_binary(input.reader.fill_buf()?, &config.delimiter) {
+        // 结构化输出中表现为没有任何行的记录，JSON 中标记为内容被截断
+        match config.output {
+            OutputFormat::Text => {
+                let mut sink = sink;
+                writeln!(sink, "<binary file skipped>")?;
+                sink.flush()?;
+            }
+            _ => formatter(config, name, count_output(config, sink, &counted))
+                .finish(&mut input.reader)?,
+        }
+        return Ok(Headed {
+            followable: false,
+            stats: counted.get(),
+        });
+    }
+    let mut out = formatter(config, name, count_output(config, sink, &counted));
+    head_file(config, filename, &mut input, &mut out, &counted)?;
+    out.finish(&mut input.reader)?;
+    Ok(Headed {
+        followable: input.size.is_some(),
+        stats: counted.get(),
+    })
+}
+
+// --jobs 的工作线程中收集一个文件的全部输出
+#[derive(Debug, Default, Clone)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// --stats 的统计结果：与 `wc -lc` 一样，最后一行没有分隔符时也算作一行
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    lines: u64,
+    bytes: u64,
+    partial: bool, // 输出是否以一行不完整的内容结尾
+}
+
+impl Stats {
+    fn lines(&self) -> u64 {
+        self.lines + u64::from(self.partial)
+    }
+
+    // 计入一段写出的内容
+    fn add(&mut self, buf: &[u8], delimiter: u8) {
+        if let Some(&last) = buf.last() {
+            self.lines += memchr::memchr_iter(delimiter, buf).count() as u64;
+            self.bytes += buf.len() as u64;
+            self.partial = last != delimiter;
+        }
+    }
+}
+
+// 统计实际写出的内容，也就是行号、偏移等格式化之后的输出
+struct CountOutput {
+    inner: Box<dyn Write>,
+    stats: Rc<Cell<Stats>>,
+    delimiter: u8, // 多字节分隔符以最后一个字节为准
+}
+
+impl Write for CountOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut stats = self.stats.get();
+        stats.add(&buf[..written], self.delimiter);
+        self.stats.set(stats);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn count_output(config: &Config, sink: Box<dyn Write>, stats: &Rc<Cell<Stats>>) -> Box<dyn Write> {
+    Box::new(CountOutput {
+        inner: sink,
+        stats: Rc::clone(stats),
+        delimiter: counted_delimiter(config),
+    })
+}
+
+// 统计的是 --print0 把分隔符换成 NUL、--eol 转换换行符之后的输出
+fn counted_delimiter(config: &Config) -> u8 {
+    match (config.print0, config.eol) {
+        (true, _) => b'\0',
+        (false, Some(Eol::Cr)) => b'\r',
+        (false, Some(_)) => b'\n',
+        (false, None) => config.delimiter.last().copied().unwrap_or(b'\n'),
+    }
+}
+
+// 按 wc 的格式输出：行数、字节数、文件名，多个文件时再输出合计
+// 出错的文件不参与统计
+fn print_stats(config: &Config, report: &RunReport) {
+    let (mut lines, mut bytes, mut count) = (0, 0, 0);
+    for file in report.files.iter().filter(|file| file.error.is_none()) {
+        let name = display_name(config, &file.path);
+        eprintln!("{:>7} {:>7} {}", file.lines, file.bytes, name.display());
+        lines += file.lines;
+        bytes += file.bytes;
+        count += 1;
+    }
+    if count > 1 {
+        eprintln!("{:>7} {:>7} total", lines, bytes);
+    }
+}
+
+// 终端的高度；取不到时依次使用 LINES 环境变量和 24
+fn terminal_height() -> usize {
+    terminal_size::terminal_size()
+        .map(|(_, terminal_size::Height(height))| usize::from(height))
+        .or_else(|| env::var("LINES").ok()?.parse().ok())
+        .filter(|&height| height > 0)
+        .unwrap_or(24)
+}
+
+// --pager 的输出：先缓存起来，超过一屏时才启动分页程序并把缓存和后续内容都交给它，
+// 全部输出都不满一屏时在结束时直接写到标准输出
+enum Pager {
+    Buffering {
+        buffer: Vec<u8>,
+        lines: usize,
+    },
+    Paging {
+        child: Child,
+        stdin: Option<ChildStdin>,
+    },
+    Direct(io::Stdout),
+}
+
+impl Pager {
+    fn new() -> Self {
+        Pager::Buffering {
+            buffer: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    // 使用 $PAGER，没有设置时使用 less；LESS 没有设置时让 less 保留颜色、不清屏
+    fn spawn() -> io::Result<Child> {
+        let command = env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| "less".to_string());
+        let mut args = command.split_whitespace();
+        let program = args.next().unwrap_or("less");
+        let mut pager = Command::new(program);
+        pager.args(args).stdin(Stdio::piped());
+        if env::var_os("LESS").is_none() {
+            pager.env("LESS", "FRX");
+        }
+        pager.spawn()
+    }
+
+    // 输出已经超过一屏：启动分页程序，启动失败时退回到直接输出
+    fn start(&mut self) -> io::Result<()> {
+        let Pager::Buffering { buffer, .. } = self else {
+            return Ok(());
+        };
+        let buffer = std::mem::take(buffer);
+        *self = match Pager::spawn() {
+            Ok(mut child) => Pager::Paging {
+                stdin: child.stdin.take(),
+                child,
+            },
+            Err(_) => Pager::Direct(io::stdout()),
+        };
+        self.write_all(&buffer)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Pager::Buffering { buffer, .. } => {
+                let mut stdout = io::stdout();
+                stdout.write_all(buffer)?;
+                stdout.flush()
+            }
+            Pager::Paging { child, stdin } => {
+                drop(stdin.take()); // 关闭管道，分页程序才知道输出已经结束
+                child.wait()?;
+                Ok(())
+            }
+            Pager::Direct(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Pager::Buffering { buffer, lines } => {
+                buffer.extend_from_slice(buf);
+                *lines += buf.iter().filter(|&&b| b == b'\n').count();
+                if *lines >= terminal_height() {
+                    self.start()?;
+                }
+                Ok(buf.len())
+            }
+            // 用户提前退出分页程序时丢弃剩下的输出，而不是报错
+            Pager::Paging { stdin, .. } => match stdin.as_mut().map(|stdin| stdin.write(buf)) {
+                Some(Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    *stdin = None;
+                    Ok(buf.len())
+                }
+                Some(result) => result,
+                None => Ok(buf.len()),
+            },
+            Pager::Direct(stdout) => stdout.write(buf),
+        }
+    }
+
+    // 缓存中的内容要等到确定是否需要分页后才输出
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Pager::Buffering { .. } => Ok(()),
+            Pager::Paging { stdin, .. } => match stdin.as_mut().map(|stdin| stdin.flush()) {
+                Some(Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    *stdin = None;
+                    Ok(())
+                }
+                result => result.unwrap_or(Ok(())),
+            },
+            Pager::Direct(stdout) => stdout.flush(),
+        }
+    }
+}
+
+// 多个文件的输出共用同一个分页程序
+struct PagerWriter(Rc<RefCell<Pager>>);
+
+impl Write for PagerWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// 文件名标题：按 --header-format 的模板生成，再按着色方案输出
+#[derive(Debug, Clone, Copy)]
+struct Banner<'a> {
+    template: &'a str,
+    palette: Palette,
+}
+
+impl Banner<'_> {
+    // name 是标题中显示的名称，filename 是实际打开的文件，file_num 从 0 开始
+    // {size} 是文件的字节数，标准输入等长度未知时显示为 ?；不认识的占位符原样保留
+    fn render(&self, name: &str, filename: &Path, file_num: usize) -> String {
+        let mut header = String::new();
+        let mut rest = self.template;
+        while let Some(start) = rest.find('{') {
+            header.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            match &rest[1..end] {
+                "path" => header.push_str(name),
+                "basename" => header.push_str(
+                    &Path::new(name)
+                        .file_name()
+                        .map_or(name.into(), |base| base.to_string_lossy()),
+                ),
+                "size" => match regular_file_len(filename) {
+                    Some(len) => header.push_str(&len.to_string()),
+                    None => header.push('?'),
+                },
+                "index" => header.push_str(&(file_num + 1).to_string()),
+                _ => header.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        header.push_str(rest);
+        self.palette.paint(Style::Header, header)
+    }
+}
+
+// 输出文件与某个输入是同一个文件时报错，避免在读取之前就把输入覆盖掉
+fn check_not_input(output: &Path, files: &[PathBuf]) -> Result<()> {
+    let Ok(output) = fs::canonicalize(output) else {
+        return Ok(()); // 目标文件还不存在
+    };
+    for file in files {
+        let path = file
+            .to_str()
+            .and_then(split_archive_member)
+            .map_or(file.as_path(), |(archive, _)| Path::new(archive));
+        if fs::canonicalize(path).is_ok_and(|path| path == output) {
+            return Err(invalid!(
+                "{}: refusing to overwrite an input file",
+                output.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// -o 的输出文件：先写到同一目录下的临时文件，全部成功后再改名为目标文件，
+// 这样中途出错或被中断时目标文件保持原样，不会只剩下一半内容
+struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    fn create(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| invalid!("Invalid output file: {}", path.display()))?;
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let temp = dir.join(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        let file = File::create_new(&temp)
+            .context(format!("Failed to create file: {}", temp.display()))?;
+        Ok(AtomicFile {
+            path: path.to_path_buf(),
+            temp,
+            file,
+            committed: false,
+        })
+    }
+
+    // 写入临时文件的句柄，各句柄共享文件的写入位置
+    fn writer(&self) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(BufWriter::new(self.file.try_clone()?)))
+    }
+
+    fn commit(mut self) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp, &self.path)
+            .context(format!("Failed to write file: {}", self.path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+// 输出格式化器：包装标准输出，对写入的内容做变换后输出
+// 每个文件使用一个新的格式化器，处理完后调用 finish 输出缓存的内容，
+// rest 是输入中没有被读取的部分
+trait OutputFormatter: Write {
+    fn finish(&mut self, _rest: &mut dyn BufRead) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl OutputFormatter for Box<dyn Write> {}
+
+// 输出中着色的部分
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Header,     // 文件名标题
+    LineNumber, // -N 的行号
+    Offset,     // --offsets 的偏移
+    Match,      // --from/--until 匹配的部分
+}
+
+impl Style {
+    // 对应的 SGR 参数，配色与 grep 的默认值相近
+    fn sgr(self) -> &'static str {
+        match self {
+            Style::Header => "1;35",
+            Style::LineNumber | Style::Offset => "32",
+            Style::Match => "1;31",
+        }
+    }
+}
+
+/// 输出的着色方案，关闭时原样输出文本
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    // 开启着色时用 ANSI 转义序列包住文本
+    fn paint(self, style: Style, text: impl fmt::Display) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", style.sgr(), text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// 根据配置为一个文件创建输出格式化器，name 是标题中显示的文件名，sink 是最终的输出
+fn formatter(config: &Config, name: &str, sink: Box<dyn Write>) -> Box<dyn OutputFormatter> {
+    // 行号等格式化都按原来的分隔符处理，写出时才换成 NUL
+    let shared = |last: Box<dyn OutputFormatter>| {
+        let last = Rc::new(RefCell::new(last));
+        let sink: Box<dyn Write> = Box::new(SharedFormatter(Rc::clone(&last)));
+        (sink, Some(last))
+    };
+    let (sink, last) = match (config.print0, config.eol) {
+        (true, _) => shared(Box::new(Print0::new(sink, &config.delimiter))),
+        (false, Some(eol)) => shared(Box::new(ConvertEol::new(sink, eol))),
+        (false, None) => (sink, None),
+    };
+    let out: Box<dyn OutputFormatter> = if config.output != OutputFormat::Text {
+        Box::new(Records::new(config.output, name, &config.delimiter, sink))
+    } else if config.hex {
+        Box::new(HexDump::new(sink))
+    } else {
+        match config.number {
+            Some(numbering) => Box::new(NumberLines::new(
+                sink,
+                numbering,
+                &config.delimiter,
+                config.palette,
+            )),
+            None => Box::new(sink),
+        }
+    };
+    // 与 cat -sn 一样先合并空行再编号
+    // 截断把高亮加上的转义序列当作不占宽度
+    #[cfg(feature = "highlight")]
+    let out: Box<dyn OutputFormatter> = match config.syntax {
+        true => Box::new(SyntaxHighlight::new(out, name)),
+        false => out,
+    };
+    let out: Box<dyn OutputFormatter> = match config.truncate_lines {
+        Some(width) => Box::new(TruncateLines::new(out, width, &config.delimiter)),
+        None => out,
+    };
+    // 截断按转义后的宽度计算
+    let out: Box<dyn OutputFormatter> = match config.show_nonprinting {
+        true => Box::new(ShowNonprinting::new(out, &config.delimiter)),
+        false => out,
+    };
+    let out: Box<dyn OutputFormatter> = match config.squeeze_blank {
+        true => Box::new(SqueezeBlank::new(out, &config.delimiter)),
+        false => out,
+    };
+    // 末尾的分隔符属于文件的内容，在其余格式化之前处理
+    let out: Box<dyn OutputFormatter> = match config.final_newline {
+        Some(mode) => Box::new(AdjustFinalNewline::new(out, mode, &config.delimiter)),
+        None => out,
+    };
+    match last {
+        Some(last) => Box::new(FinishLast { out, last }),
+        None => out,
+    }
+}
+
+// --ensure-newline / --no-trailing-newline：在文件输出的末尾补上或去掉分隔符
+// 总是暂存最后 delimiter.len() 个字节，到 finish 时才知道它们是不是输出的结尾
+struct AdjustFinalNewline {
+    inner: Box<dyn OutputFormatter>,
+    mode: FinalNewline,
+    delimiter: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl AdjustFinalNewline {
+    fn new(inner: Box<dyn OutputFormatter>, mode: FinalNewline, delimiter: &[u8]) -> Self {
+        AdjustFinalNewline {
+            inner,
+            mode,
+            delimiter: delimiter.to_vec(),
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl Write for AdjustFinalNewline {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tail.extend_from_slice(buf);
+        if self.tail.len() > self.delimiter.len() {
+            let excess = self.tail.len() - self.delimiter.len();
+            self.inner.write_all(&self.tail[..excess])?;
+            self.tail.drain(..excess);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputFormatter for AdjustFinalNewline {
+    // 没有任何输出时什么也不补
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        let ends_with_delimiter = self.tail == self.delimiter;
+        match self.mode {
+            FinalNewline::Ensure if !self.tail.is_empty() && !ends_with_delimiter => {
+                self.inner.write_all(&self.tail)?;
+                self.inner.write_all(&self.delimiter)?;
+            }
+            FinalNewline::Strip if ends_with_delimiter => {}
+            _ => self.inner.write_all(&self.tail)?,
+        }
+        self.tail.clear();
+        self.inner.finish(rest)
+    }
+}
+
+// 创建 --output-dir 下与输入对应的输出文件，需要时先创建上级目录
+// 输入路径中的根目录、`.` 以及远程地址的协议前缀都被去掉，不允许包含 `..`
+fn create_output_file(dir: &Path, name: &Path) -> Result<BufWriter<File>> {
+    let relative = name
+        .to_str()
+        .and_then(|name| name.split_once("://"))
+        .map_or(name, |(_, rest)| Path::new(rest));
+    let mut path = dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => {
+                return Err(invalid!(
+                    "{}: refusing to write outside the output directory",
+                    name.display()
+                ))
+            }
+            _ => {}
+        }
+    }
+    if path == dir {
+        return Err(invalid!("{}: no file name to write to", name.display()));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let file = File::create(&path).context(format!("Failed to create file: {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+// --output json/tsv/csv：收集一个文件的全部输出，结束时按格式输出结构化的记录
+// json 每个文件一行，包含文件名、去掉分隔符的各行，以及输入在输出的内容之后是否还有剩余；
+// tsv 与 csv 每行输出一条 `文件名、行号、内容` 记录，行号与 -N 一样从 1 开始
+struct Records {
+    format: OutputFormat,
+    file: String,
+    delimiter: Vec<u8>,
+    output: Vec<u8>,
+    sink: Box<dyn Write>,
+}
+
+impl Records {
+    fn new(format: OutputFormat, file: &str, delimiter: &[u8], sink: Box<dyn Write>) -> Self {
+        Records {
+            format,
+            file: file.to_string(),
+            delimiter: delimiter.to_vec(),
+            output: Vec::new(),
+            sink,
+        }
+    }
+
+    // 按分隔符切分收集到的输出，去掉每行结尾的分隔符
+    fn lines(&self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let mut output = self.output.as_slice();
+        let mut line = Vec::new();
+        while read_record(&mut output, &self.delimiter, &mut line)? > 0 {
+            let content = line
+                .strip_suffix(self.delimiter.as_slice())
+                .unwrap_or(&line);
+            lines.push(String::from_utf8_lossy(content).into_owned());
+            line.clear();
+        }
+        Ok(lines)
+    }
+}
+
+impl Write for Records {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputFormatter for Records {
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        let lines = self.lines()?;
+        match self.format {
+            OutputFormat::Text => self.sink.write_all(&self.output)?,
+            OutputFormat::Json => {
+                let record = serde_json::json!({
+                    "file": self.file,
+                    "lines": lines,
+                    "truncated": !rest.fill_buf()?.is_empty(),
+                });
+                writeln!(self.sink, "{}", record)?;
+            }
+            OutputFormat::Tsv => {
+                for (index, line) in lines.iter().enumerate() {
+                    writeln!(
+                        self.sink,
+                        "{}\t{}\t{}",
+                        escape_tsv(&self.file),
+                        index + 1,
+                        escape_tsv(line)
+                    )?;
+                }
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut self.sink);
+                for (index, line) in lines.iter().enumerate() {
+                    writer.write_record([&self.file, &(index + 1).to_string(), line])?;
+                }
+                writer.flush()?;
+            }
+        }
+        self.sink.flush()
+    }
+}
+
+// TSV 字段中的制表符、换行与反斜杠写成 \t、\n、\r、\\ 转义
+fn escape_tsv(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// xxd 风格的十六进制转储：每行 16 个字节，依次是偏移、按两个字节分组的十六进制和可打印字符
+struct HexDump<W> {
+    inner: W,
+    offset: u64,
+    row: Vec<u8>, // 还不满一行的字节
+}
+
+impl<W: Write> HexDump<W> {
+    const ROW_LEN: usize = 16;
+
+    fn new(inner: W) -> Self {
+        HexDump {
+            inner,
+            offset: 0,
+            row: Vec::with_capacity(Self::ROW_LEN),
+        }
+    }
+
+    fn write_row(&mut self) -> io::Result<()> {
+        let mut hex = String::new();
+        for (i, byte) in self.row.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let ascii: String = self
+            .row
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(self.inner, "{:08x}: {:<39}  {}", self.offset, hex, ascii)?;
+        self.offset += self.row.len() as u64;
+        self.row.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HexDump<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.row.push(byte);
+            if self.row.len() == Self::ROW_LEN {
+                self.write_row()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> OutputFormatter for HexDump<W> {
+    // 最后不满一行的字节也要输出
+    fn finish(&mut self, _rest: &mut dyn BufRead) -> io::Result<()> {
+        if !self.row.is_empty() {
+            self.write_row()?;
+        }
+        self.flush()
+    }
+}
+
+// --print0：把输出中的分隔符换成 NUL
+// 多字节分隔符可能被拆开写入，已经匹配的前缀暂存起来，确定不是分隔符时再原样写出
+// 按 KMP 的失配函数回退，例如分隔符为 aab 时 aaab 中的后三个字节仍然是分隔符
+struct Print0 {
+    inner: Box<dyn Write>,
+    delimiter: Vec<u8>,
+    fallback: Vec<usize>, // fallback[i]：delimiter[..=i] 既是前缀又是后缀的最长真子串的长度
+    matched: usize,
+}
+
+impl Print0 {
+    fn new(inner: Box<dyn Write>, delimiter: &[u8]) -> Self {
+        let mut fallback = vec![0; delimiter.len()];
+        let mut k = 0;
+        for i in 1..delimiter.len() {
+            while k > 0 && delimiter[i] != delimiter[k] {
+                k = fallback[k - 1];
+            }
+            if delimiter[i] == delimiter[k] {
+                k += 1;
+            }
+            fallback[i] = k;
+        }
+        Print0 {
+            inner,
+            delimiter: delimiter.to_vec(),
+            fallback,
+            matched: 0,
+        }
+    }
+}
+
+impl Write for Print0 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            // 失配时回退到仍然可能匹配的最长前缀，回退掉的字节确定不属于分隔符
+            while self.matched > 0 && byte != self.delimiter[self.matched] {
+                let keep = self.fallback[self.matched - 1];
+                out.extend_from_slice(&self.delimiter[..self.matched - keep]);
+                self.matched = keep;
+            }
+            if byte == self.delimiter[self.matched] {
+                self.matched += 1;
+                if self.matched == self.delimiter.len() {
+                    out.push(b'\0');
+                    self.matched = 0;
+                }
+            } else {
+                out.push(byte);
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputFormatter for Print0 {
+    // 结尾不完整的分隔符原样写出
+    fn finish(&mut self, _rest: &mut dyn BufRead) -> io::Result<()> {
+        self.inner.write_all(&self.delimiter[..self.matched])?;
+        self.matched = 0;
+        self.inner.flush()
+    }
+}
+
+// --eol：把输出中的 \r\n 和 \n 都换成指定的换行符，单独的 \r 原样输出
+// \r 可能是 \r\n 被拆开写入的前半部分，暂存起来，看到下一个字节时再决定
+struct ConvertEol {
+    inner: Box<dyn Write>,
+    eol: &'static [u8],
+    pending_cr: bool,
+}
+
+impl ConvertEol {
+    fn new(inner: Box<dyn Write>, eol: Eol) -> Self {
+        ConvertEol {
+            inner,
+            eol: eol.as_bytes(),
+            pending_cr: false,
+        }
+    }
+}
+
+impl Write for ConvertEol {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            if self.pending_cr && byte != b'\n' {
+                out.push(b'\r');
+            }
+            self.pending_cr = byte == b'\r';
+            match byte {
+                b'\n' => out.extend_from_slice(self.eol),
+                b'\r' => {}
+                _ => out.push(byte),
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputFormatter for ConvertEol {
+    // 结尾单独的 \r 原样写出
+    fn finish(&mut self, _rest: &mut dyn BufRead) -> io::Result<()> {
+        if self.pending_cr {
+            self.inner.write_all(b"\r")?;
+            self.pending_cr = false;
+        }
+        self.inner.flush()
+    }
+}
+
+// --print0 / --eol 位于格式化器链的最末端，链上其余的格式化器通过 SharedFormatter 把它当作
+// 普通的输出写入；FinishLast 在整条链 finish 之后再 finish 它，写出它暂存的内容
+struct SharedFormatter(Rc<RefCell<Box<dyn OutputFormatter>>>);
+
+impl Write for SharedFormatter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+struct FinishLast {
+    out: Box<dyn OutputFormatter>,
+    last: Rc<RefCell<Box<dyn OutputFormatter>>>,
+}
+
+impl Write for FinishLast {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl OutputFormatter for FinishLast {
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        self.out.finish(rest)?;
+        self.last.borrow_mut().finish(rest)
+    }
+}
+
+// 合并连续的空行，只保留第一个，与 cat -s 相同
+// 行可能分多次写入，因此记录下一次写入是否从行首开始，以及上一行是不是空行
+struct SqueezeBlank {
+    inner: Box<dyn OutputFormatter>,
+    delimiter: u8, // 多字节分隔符以最后一个字节为准
+    at_line_start: bool,
+    previous_blank: bool,
+}
+
+impl SqueezeBlank {
+    fn new(inner: Box<dyn OutputFormatter>, delimiter: &[u8]) -> Self {
+        SqueezeBlank {
+            inner,
+            delimiter: delimiter.last().copied().unwrap_or(b'\n'),
+            at_line_start: true,
+            previous_blank: false,
+        }
+    }
+}
+
+impl OutputFormatter for SqueezeBlank {
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        self.inner.finish(rest)
+    }
+}
+
+impl Write for SqueezeBlank {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(&first) = rest.first() {
+            if self.at_line_start && first == self.delimiter {
+                if !self.previous_blank {
+                    self.inner.write_all(&rest[..1])?;
+                }
+                self.previous_blank = true;
+                rest = &rest[1..];
+                continue;
+            }
+            match rest.iter().position(|&b| b == self.delimiter) {
+                Some(i) => {
+                    self.inner.write_all(&rest[..=i])?;
+                    rest = &rest[i + 1..];
+                    self.at_line_start = true;
+                }
+                None => {
+                    self.inner.write_all(rest)?;
+                    rest = &[];
+                    self.at_line_start = false;
+                }
+            }
+            self.previous_blank = false;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// 与 cat -v 相同：控制字符显示为 ^X，DEL 显示为 ^?，大于 127 的字节显示为 M- 加上
+// 去掉最高位后的形式；换行、制表符和记录分隔符原样输出
+struct ShowNonprinting {
+    inner: Box<dyn OutputFormatter>,
+    delimiter: u8, // 多字节分隔符以最后一个字节为准
+}
+
+impl ShowNonprinting {
+    fn new(inner: Box<dyn OutputFormatter>, delimiter: &[u8]) -> Self {
+        ShowNonprinting {
+            inner,
+            delimiter: delimiter.last().copied().unwrap_or(b'\n'),
+        }
+    }
+}
+
+impl Write for ShowNonprinting {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut shown = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            if byte == b'\n' || byte == b'\t' || byte == self.delimiter {
+                shown.push(byte);
+                continue;
+            }
+            let low = if byte >= 0x80 {
+                shown.extend_from_slice(b"M-");
+                byte - 0x80
+            } else {
+                byte
+            };
+            match low {
+                0x00..=0x1f => shown.extend_from_slice(&[b'^', low + 0x40]),
+                0x7f => shown.extend_from_slice(b"^?"),
+                _ => shown.push(low),
+            }
+        }
+        self.inner.write_all(&shown)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputFormatter for ShowNonprinting {
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        self.inner.finish(rest)
+    }
+}
+
+// 语法高亮用到的语法定义和配色，第一次使用时才加载
+#[cfg(feature = "highlight")]
+fn syntax_assets() -> &'static (syntect::parsing::SyntaxSet, syntect::highlighting::Theme) {
+    static ASSETS: std::sync::OnceLock<(
+        syntect::parsing::SyntaxSet,
+        syntect::highlighting::Theme,
+    )> = std::sync::OnceLock::new();
+    ASSETS.get_or_init(|| {
+        let mut themes = syntect::highlighting::ThemeSet::load_defaults();
+        (
+            syntect::parsing::SyntaxSet::load_defaults_newlines(),
+            themes
+                .themes
+                .remove("base16-ocean.dark")
+                .unwrap_or_default(),
+        )
+    })
+}
+
+// --highlight：逐行做语法高亮，输出 24 位色的 ANSI 转义序列
+// 先按扩展名识别语言，识别不了时再看第一行（如 #!/bin/sh），仍然识别不了或者是纯文本就原样输出
+#[cfg(feature = "highlight")]
+struct SyntaxHighlight {
+    inner: Box<dyn OutputFormatter>,
+    name: String,
+    lines: Option<syntect::easy::HighlightLines<'static>>,
+    detected: bool, // 是否已经确定了语言（或确定无法识别）
+    line: Vec<u8>,  // 还没有遇到换行符的部分
+}
+
+#[cfg(feature = "highlight")]
+impl SyntaxHighlight {
+    fn new(inner: Box<dyn OutputFormatter>, name: &str) -> Self {
+        SyntaxHighlight {
+            inner,
+            name: name.to_string(),
+            lines: None,
+            detected: false,
+            line: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let (syntaxes, theme) = syntax_assets();
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        if !self.detected {
+            let extension = Path::new(&self.name)
+                .extension()
+                .map(|extension| extension.to_string_lossy());
+            let syntax = extension
+                .and_then(|extension| syntaxes.find_syntax_by_extension(&extension))
+                .or_else(|| syntaxes.find_syntax_by_first_line(&line))
+                .filter(|syntax| syntax.name != syntaxes.find_syntax_plain_text().name);
+            self.lines = syntax.map(|syntax| syntect::easy::HighlightLines::new(syntax, theme));
+            self.detected = true;
+        }
+        let regions = match &mut self.lines {
+            Some(lines) => lines
+                .highlight_line(&line, syntaxes)
+                .map_err(io::Error::other)?,
+            None => return self.inner.write_all(line.as_bytes()),
+        };
+        let escaped = syntect::util::as_24_bit_terminal_escaped(&regions, false);
+        // 在换行符之前恢复默认颜色
+        let (text, newline) = match escaped.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (escaped.as_str(), ""),
+        };
+        write!(self.inner, "{}\x1b[0m{}", text, newline)
+    }
+}
+
+#[cfg(feature = "highlight")]
+impl Write for SyntaxHighlight {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&rest[..=i]);
+            self.write_line()?;
+            rest = &rest[i + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "highlight")]
+impl OutputFormatter for SyntaxHighlight {
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+        self.inner.finish(rest)
+    }
+}
+
+// 截断后显示在行尾的标记
+const TRUNCATION_MARKER: &str = "…";
+
+// 把显示宽度超过 width 列的行截断，最后一列显示截断标记
+// 全角字符占两列，制表符对齐到 8 的倍数列；ANSI 转义序列不占宽度，截断后也照常输出，
+// 避免颜色延续到行尾之后。行、字符和转义序列都可能分多次写入
+struct TruncateLines {
+    inner: Box<dyn OutputFormatter>,
+    width: usize,
+    delimiter: u8,         // 多字节分隔符以最后一个字节为准
+    column: usize,         // 当前行已经占用的列数
+    held: Vec<u8>,         // 已经占用最后一列、要看后面还有没有内容才能决定是否输出的部分
+    held_escapes: Vec<u8>, // held 中的转义序列
+    clipped: bool,         // 当前行是否已经截断
+    pending: Vec<u8>,      // 上一次写入末尾不完整的字符或转义序列
+}
+
+impl TruncateLines {
+    fn new(inner: Box<dyn OutputFormatter>, width: usize, delimiter: &[u8]) -> Self {
+        TruncateLines {
+            inner,
+            width,
+            delimiter: delimiter.last().copied().unwrap_or(b'\n'),
+            column: 0,
+            held: Vec::new(),
+            held_escapes: Vec::new(),
+            clipped: false,
+            pending: Vec::new(),
+        }
+    }
+
+    // 开头一个字符或转义序列的长度，不完整时返回 None
+    fn token_len(rest: &[u8]) -> Option<usize> {
+        if rest[0] == 0x1b {
+            return match rest.get(1) {
+                None => None,
+                Some(b'[') => rest[2..]
+                    .iter()
+                    .position(|b| (0x40..=0x7e).contains(b))
+                    .map(|i| i + 3),
+                Some(_) => Some(1),
+            };
+        }
+        let expected = match rest[0] {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        match std::str::from_utf8(&rest[..expected.min(rest.len())]) {
+            Ok(_) => Some(expected),
+            Err(e) if e.error_len().is_none() => None,
+            Err(_) => Some(1), // 非法的字节按占一列处理
+        }
+    }
+
+    fn push_token(&mut self, token: &[u8]) -> io::Result<()> {
+        if token == [self.delimiter] {
+            self.inner.write_all(&self.held)?;
+            self.inner.write_all(token)?;
+            self.held.clear();
+            self.held_escapes.clear();
+            self.column = 0;
+            self.clipped = false;
+            return Ok(());
+        }
+        if token.len() > 1 && token[0] == 0x1b {
+            if self.held.is_empty() {
+                self.inner.write_all(token)?;
+            } else {
+                self.held.extend_from_slice(token);
+                self.held_escapes.extend_from_slice(token);
+            }
+            return Ok(());
+        }
+        if self.clipped {
+            return Ok(());
+        }
+        let width = match std::str::from_utf8(token)
+            .ok()
+            .and_then(|c| c.chars().next())
+        {
+            Some('\t') => 8 - self.column % 8,
+            Some(c) => c.width().unwrap_or(0),
+            None => 1,
+        };
+        if self.held.is_empty() && self.column + width < self.width {
+            self.inner.write_all(token)?;
+        } else if self.column + width <= self.width {
+            self.held.extend_from_slice(token);
+        } else {
+            self.inner.write_all(TRUNCATION_MARKER.as_bytes())?;
+            self.inner.write_all(&self.held_escapes)?;
+            self.held.clear();
+            self.held_escapes.clear();
+            self.clipped = true;
+        }
+        self.column += width;
+        Ok(())
+    }
+}
+
+impl Write for TruncateLines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(buf);
+        let mut pos = 0;
+        while pos < data.len() {
+            match Self::token_len(&data[pos..]) {
+                Some(len) => {
+                    self.push_token(&data[pos..pos + len])?;
+                    pos += len;
+                }
+                None => {
+                    self.pending = data[pos..].to_vec();
+                    break;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputFormatter for TruncateLines {
+    // 结尾不完整的字符按每个字节占一列处理
+    fn finish(&mut self, rest: &mut dyn BufRead) -> io::Result<()> {
+        for byte in std::mem::take(&mut self.pending) {
+            self.push_token(&[byte])?;
+        }
+        self.inner.write_all(&self.held)?;
+        self.held.clear();
+        self.held_escapes.clear();
+        self.inner.finish(rest)
+    }
+}
+
+// 给写入的每一行加上行号，格式与 cat -n / cat -b 相同
+// 行可能分多次写入，因此记录下一次写入是否从行首开始
+struct NumberLines<W> {
+    inner: W,
+    numbering: Numbering,
+    delimiter: u8, // 多字节分隔符以最后一个字节为准
+    palette: Palette,
+    line: usize,
+    at_line_start: bool,
+}
+
+impl<W: Write> NumberLines<W> {
+    fn new(inner: W, numbering: Numbering, delimiter: &[u8], palette: Palette) -> Self {
+        NumberLines {
+            inner,
+            numbering,
+            delimiter: delimiter.last().copied().unwrap_or(b'\n'),
+            palette,
+            line: 0,
+            at_line_start: true,
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for NumberLines<W> {}
+
+impl<W: Write> Write for NumberLines<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(&first) = rest.first() {
+            if self.at_line_start {
+                if !(self.numbering.nonblank && first == self.delimiter) {
+                    self.line += 1;
+                    let number = format!("{:>width$}", self.line, width = self.numbering.width);
+                    write!(
+                        self.inner,
+                        "{}\t",
+                        self.palette.paint(Style::LineNumber, number)
+                    )?;
+                }
+                self.at_line_start = false;
+            }
+            match rest.iter().position(|&b| b == self.delimiter) {
+                Some(i) => {
+                    self.inner.write_all(&rest[..=i])?;
+                    rest = &rest[i + 1..];
+                    self.at_line_start = true;
+                }
+                None => {
+                    self.inner.write_all(rest)?;
+                    rest = &[];
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// --encoding 支持的编码（WHATWG Encoding Standard 定义的全部编码）
+const ENCODINGS: &[&Encoding] = &[
+    encoding_rs::UTF_8,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
+    encoding_rs::GBK,
+    encoding_rs::GB18030,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_JP,
+    encoding_rs::ISO_2022_JP,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_KR,
+    encoding_rs::IBM866,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_3,
+    encoding_rs::ISO_8859_4,
+    encoding_rs::ISO_8859_5,
+    encoding_rs::ISO_8859_6,
+    encoding_rs::ISO_8859_7,
+    encoding_rs::ISO_8859_8,
+    encoding_rs::ISO_8859_8_I,
+    encoding_rs::ISO_8859_10,
+    encoding_rs::ISO_8859_13,
+    encoding_rs::ISO_8859_14,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::ISO_8859_16,
+    encoding_rs::KOI8_R,
+    encoding_rs::KOI8_U,
+    encoding_rs::MACINTOSH,
+    encoding_rs::WINDOWS_874,
+    encoding_rs::WINDOWS_1250,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::WINDOWS_1253,
+    encoding_rs::WINDOWS_1254,
+    encoding_rs::WINDOWS_1255,
+    encoding_rs::WINDOWS_1256,
+    encoding_rs::WINDOWS_1257,
+    encoding_rs::WINDOWS_1258,
+    encoding_rs::X_MAC_CYRILLIC,
+    encoding_rs::X_USER_DEFINED,
+];
+
+// 把输入按指定编码转换为 UTF-8；转换后长度改变，不能再按文件长度 seek
+fn decode(input: Input, encoding: &'static Encoding, strip_bom: bool) -> Input {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .strip_bom(strip_bom)
+        .build(input.reader);
+    Input {
+        reader: Box::new(BufReader::new(decoder)),
+        size: None,
+    }
+}
+
+// --crlf：把 \r\n 换成 \n，Convert 时单独的 \r 也换成 \n；转换后长度改变，不能再按文件长度 seek
+fn normalize_crlf(input: Input, mode: Crlf) -> Input {
+    let reader = CrlfReader {
+        inner: input.reader,
+        lone_cr: match mode {
+            Crlf::Convert => b'\n',
+            _ => b'\r',
+        },
+        pending_cr: false,
+    };
+    Input {
+        reader: Box::new(BufReader::new(reader)),
+        size: None,
+    }
+}
+
+// 逐块转换换行符；\r 要等读到下一个字节才知道是否属于 \r\n，因此先暂存起来
+struct CrlfReader {
+    inner: Box<dyn BufRead>,
+    lone_cr: u8,      // 单独的 \r 输出为什么
+    pending_cr: bool, // 上一块以 \r 结尾，还没有输出
+}
+
+impl Read for CrlfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        // 只读到一个 \r 时还不能返回，否则调用者会误以为已经读完
+        while n == 0 && !buf.is_empty() {
+            let chunk = self.inner.fill_buf()?;
+            if chunk.is_empty() {
+                if self.pending_cr {
+                    self.pending_cr = false;
+                    buf[0] = self.lone_cr;
+                    n = 1;
+                }
+                break;
+            }
+            let mut used = 0;
+            while used < chunk.len() && n < buf.len() {
+                let byte = chunk[used];
+                if self.pending_cr {
+                    self.pending_cr = false;
+                    if byte != b'\n' {
+                        buf[n] = self.lone_cr;
+                        n += 1;
+                        continue;
+                    }
+                } else if byte == b'\r' {
+                    self.pending_cr = true;
+                    used += 1;
+                    continue;
+                }
+                buf[n] = byte;
+                n += 1;
+                used += 1;
+            }
+            self.inner.consume(used);
+        }
+        Ok(n)
+    }
+}
+
+// UTF-8 与 UTF-16 的字节顺序标记
+const BOMS: [&[u8]; 3] = [b"\xef\xbb\xbf", b"\xff\xfe", b"\xfe\xff"];
+
+// 去掉输入开头的 BOM；去掉后内容与文件不再对应，不能再按文件长度 seek
+fn strip_bom(mut input: Input) -> Result<Input> {
+    let head = input.reader.fill_buf()?;
+    if let Some(bom) = BOMS.iter().find(|bom| head.starts_with(bom)) {
+        input.reader.consume(bom.len());
+        input.size = None;
+    }
+    Ok(input)
+}
+
+// 根据开头的一块内容判断是否为二进制文件：含有 NUL 字节（-z 模式除外），
+// 或者超过 1/8 的字节不属于合法的 UTF-8 序列
+fn looks_binary(block: &[u8], delimiter: &[u8]) -> bool {
+    if !delimiter.contains(&0) && block.contains(&0) {
+        return true;
+    }
+    let mut invalid = 0;
+    let mut rest = block;
+    while let Err(e) = std::str::from_utf8(rest) {
+        match e.error_len() {
+            Some(len) => {
+                invalid += len;
+                rest = &rest[e.valid_up_to() + len..];
+            }
+            None => break, // 块末尾被截断的字符不计入
+        }
+    }
+    invalid * 8 > block.len()
+}
+
+// 按配置的模式输出单个文件的内容
+// 只借用输入，处理完后调用者还可以查看剩下没有读取的内容
+// counted 用来统计不经过 out、由内核直接复制到标准输出的内容
+fn head_file(
+    config: &Config,
+    filename: &Path,
+    input: &mut Input,
+    out: &mut dyn Write,
+    counted: &Cell<Stats>,
+) -> Result<()> {
+    let size = input.size;
+    let mut file: Box<dyn BufRead + '_> = Box::new(&mut input.reader);
+    let delimiter = config.delimiter.as_slice();
+    if let Some(bytes) = config.bytes {
+        // 只有未经解压、解码的普通文件才能映射，其余情况仍然按缓冲读取
+        if let (true, Some(len)) = (config.mmap, size) {
+            return print_bytes_mmap(filename, out, len, bytes, config.utf8_boundary);
+        }
+        return print_bytes_count(config, filename, file, out, size, bytes, counted);
+    }
+    if config.tail {
+        return print_tail(filename, &mut file, out, size, config);
+    }
+    if config.csv {
+        return match config.lines {
+            Count::First(num_records) => print_csv(file, out, num_records),
+            _ => Err(invalid!("--csv only supports a plain record count")),
+        };
+    }
+    // --show-nonprinting 会把转义序列当作控制字符显示出来，因此内容不着色；
+    // 语法高亮时内容的颜色由高亮决定
+    let (palette, highlight) = match config.show_nonprinting || config.syntax {
+        true => (Palette::default(), None),
+        false => (config.palette, config.highlight.clone()),
+    };
+    let mut offsets = Offsets::new(config.offsets)
+        .styled(palette, highlight, delimiter)
+        .max_line(config.max_line_bytes);
+    offsets.skip(skip_lines(&mut file, config.skip, delimiter)?);
+    if let Some(from) = &config.from {
+        let (rest, skipped) = skip_to_match(file, from, delimiter, config.max_line_bytes)?;
+        file = rest;
+        offsets.skip(skipped);
+    }
+    if let Some(num_words) = config.words {
+        return print_words(&mut file, out, num_words);
+    }
+    if let Some(num_chars) = config.chars {
+        return print_chars(&mut file, out, num_chars, config.strict_utf8);
+    }
+    if let Some(num_graphemes) = config.graphemes {
+        return print_graphemes(&mut file, out, num_graphemes, config.max_line_bytes);
+    }
+    if let Some(num_paragraphs) = config.paragraphs {
+        return print_paragraphs(&mut file, out, num_paragraphs, config);
+    }
+    if let Some(record_start) = &config.record_start {
+        return match config.lines {
+            Count::First(num_records) => print_grouped_records(
+                &mut file,
+                out,
+                num_records,
+                record_start,
+                delimiter,
+                config.max_line_bytes,
+            ),
+            _ => Err(invalid!(
+                "--record-start only supports a plain record count"
+            )),
+        };
+    }
+    if config.ndjson {
+        return match config.lines {
+            Count::First(num_records) => print_ndjson(
+                &mut file,
+                out,
+                num_records,
+                config.pretty,
+                config.max_line_bytes,
+            ),
+            _ => Err(invalid!("--ndjson only supports a plain record count")),
+        };
+    }
+    if let Some(until) = &config.until {
+        // 解析参数时已保证 --until 只与普通行数一起使用
+        let num_lines = match config.lines {
+            Count::First(num_lines) => num_lines,
+            _ => u64::MAX,
+        };
+        return print_until(
+            &mut file,
+            out,
+            &mut offsets,
+            num_lines,
+            until,
+            config.until_exclusive,
+            delimiter,
+        );
+    }
+    if let Some(num_lines) = config.sample {
+        return print_sample(&mut file, out, num_lines, config);
+    }
+    match config.lines {
+        Count::First(num_lines) => print_lines(
+            &mut file,
+            out,
+            &mut offsets,
+            num_lines,
+            config.step,
+            delimiter,
+        ),
+        Count::AllButLast(num_lines) => {
+            print_lines_but_last(&mut file, out, &mut offsets, num_lines, config)
+        }
+        Count::Range(start, end) => print_line_range(
+            &mut file,
+            out,
+            &mut offsets,
+            start,
+            end,
+            config.step,
+            delimiter,
+        ),
+        // 按字节比例估算，再对齐到行尾
+        Count::Percent(percent) => {
+            let cutoff = percent_of(percent_base_len(filename, size)?, percent);
+            print_lines_up_to(&mut file, out, &mut offsets, cutoff, delimiter)
+        }
+    }
+}
+
+// 百分比计数需要知道输入的总长度，标准输入等长度未知的输入报错
+fn percent_base_len(filename: &Path, size: Option<u64>) -> Result<u64> {
+    size.ok_or_else(|| {
+        invalid!(
+            "{}: percentage counts require a regular file",
+            filename.display()
+        )
+    })
+}
+
+// 计算 len 的 percent%
+fn percent_of(len: u64, percent: u8) -> u64 {
+    len * u64::from(percent) / 100
+}
+
+// 输出的内容是否不经任何转换直接写到标准输出，这时可以让内核直接复制文件内容
+fn writes_unchanged_to_stdout(config: &Config) -> bool {
+    config.output == OutputFormat::Text
+        && config.output_file.is_none()
+        && config.output_dir.is_none()
+        && config.jobs == 1
+        && !config.pager
+        && !config.stats
+        && !config.hex
+        && !config.show_nonprinting
+        && !config.print0
+        && config.eol.is_none()
+        && !config.syntax
+        && config.final_newline.is_none()
+        && config.truncate_lines.is_none()
+        && !config.squeeze_blank
+}
+
+// 字节模式：按计数方式输出字节
+// out 原样写到标准输出时，普通文件中连续的一段内容交给内核复制
+fn print_bytes_count(
+    config: &Config,
+    filename: &Path,
+    mut file: Box<dyn BufRead + '_>,
+    out: &mut dyn Write,
+    size: Option<u64>,
+    bytes: Count,
+    counted: &Cell<Stats>,
+) -> Result<()> {
+    let direct = writes_unchanged_to_stdout(config) && size.is_some();
+    let copy = |offset, len, out: &mut dyn Write| {
+        copy_region(
+            filename,
+            offset,
+            len,
+            out,
+            counted,
+            counted_delimiter(config),
+        )
+    };
+    match bytes {
+        // 要把截断位置对齐到字符边界时不能直接复制
+        Count::First(num_bytes) => match (direct, config.utf8_boundary) {
+            (true, None) => copy(0, num_bytes, out),
+            _ => print_bytes(file, out, num_bytes, config.utf8_boundary),
+        },
+        Count::AllButLast(num_bytes) => match size {
+            // 普通文件可以直接根据长度算出要输出的字节数
+            Some(len) => {
+                let keep = len.saturating_sub(num_bytes);
+                if direct {
+                    return copy(0, keep, out);
+                }
+                io::copy(&mut file.take(keep), out)?;
+                Ok(())
+            }
+            None => print_bytes_but_last(&mut file, out, num_bytes, config.max_memory),
+        },
+        Count::Range(start, end) => {
+            let offset = start - 1;
+            let len = (end - start).saturating_add(1);
+            if direct {
+                return copy(offset, len, out);
+            }
+            let mut file = match size {
+                // 普通文件直接 seek 到起始位置
+                Some(_) => {
+                    let mut file = File::open(filename).map_err(open_error(filename))?;
+                    file.seek(SeekFrom::Start(offset))?;
+                    Box::new(BufReader::new(file))
+                }
+                // 管道只能读取并丢弃起始位置之前的内容
+                None => {
+                    io::copy(&mut file.by_ref().take(offset), &mut io::sink())?;
+                    file
+                }
+            };
+            io::copy(&mut file.by_ref().take(len), out)?;
+            Ok(())
+        }
+        Count::Percent(percent) => {
+            let num_bytes = percent_of(percent_base_len(filename, size)?, percent);
+            match (direct, config.utf8_boundary) {
+                (true, None) => copy(0, num_bytes, out),
+                _ => print_bytes(file, out, num_bytes, config.utf8_boundary),
+            }
+        }
+    }
+}
+
+// 输出普通文件中从 offset 开始的 len 个字节
+// 开启 zero-copy 特性时先在内核中直接复制到标准输出，内核不支持的部分再按缓冲读取
+// 内核复制的内容不经过 out，在这里按 delimiter 计入 counted
+fn copy_region(
+    filename: &Path,
+    offset: u64,
+    len: u64,
+    out: &mut dyn Write,
+    counted: &Cell<Stats>,
+    delimiter: u8,
+) -> Result<()> {
+    let mut file = File::open(filename).map_err(open_error(filename))?;
+    #[cfg(all(feature = "zero-copy", target_os = "linux"))]
+    let copied = {
+        out.flush()?; // 标题等已经写出的内容要排在前面
+        kernel_copy(&file, offset, len)
+    };
+    #[cfg(not(all(feature = "zero-copy", target_os = "linux")))]
+    let copied = 0;
+    if copied > 0 {
+        // 刚复制过的内容还在页缓存中，映射后直接数分隔符
+        // SAFETY: 与 print_bytes_mmap 相同，映射是只读的，只在这里短暂使用
+        let map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(offset)
+                .len(copied as usize)
+                .map(&file)
+        }
+        .context(format!("Failed to map file: {}", filename.display()))?;
+        let mut stats = counted.get();
+        stats.add(&map, delimiter);
+        counted.set(stats);
+    }
+    file.seek(SeekFrom::Start(offset + copied))?;
+    io::copy(&mut file.take(len - copied), out)?;
+    Ok(())
+}
+
+// 用 copy_file_range 复制到标准输出；标准输出是管道时 copy_file_range 不可用，改用 splice
+// 返回复制了多少字节，两者都不可用（例如标准输出是终端）或中途出错时由调用者接着复制
+#[cfg(all(feature = "zero-copy", target_os = "linux"))]
+fn kernel_copy(file: &File, offset: u64, len: u64) -> u64 {
+    use std::os::unix::io::AsRawFd;
+
+    let input = file.as_raw_fd();
+    let Ok(mut offset) = libc::loff_t::try_from(offset) else {
+        return 0;
+    };
+    let mut copied = 0;
+    let mut splice = false;
+    while copied < len {
+        let chunk = (len - copied).min(1 << 30) as usize;
+        // SAFETY: 两个文件描述符在调用期间都有效，offset 指向本函数中的局部变量
+        let n = unsafe {
+            match splice {
+                false => libc::copy_file_range(
+                    input,
+                    &mut offset,
+                    libc::STDOUT_FILENO,
+                    std::ptr::null_mut(),
+                    chunk,
+                    0,
+                ),
+                true => libc::splice(
+                    input,
+                    &mut offset,
+                    libc::STDOUT_FILENO,
+                    std::ptr::null_mut(),
+                    chunk,
+                    0,
+                ),
+            }
+        };
+        match n {
+            0 => break, // 文件比预期的短
+            n if n > 0 => copied += n as u64,
+            _ if !splice && copied == 0 => splice = true,
+            _ => break,
+        }
+    }
+    copied
+}
+
+// --mmap：把普通文件映射到内存，按计数方式算出范围后直接写出这一段，
+// 省去读取时的复制和中间缓冲区
+fn print_bytes_mmap(
+    filename: &Path,
+    out: &mut dyn Write,
+    len: u64,
+    bytes: Count,
+    boundary: Option<Utf8Boundary>,
+) -> Result<()> {
+    if len == 0 {
+        return Ok(()); // 有些平台不能映射空文件
+    }
+    let file = File::open(filename).map_err(open_error(filename))?;
+    // SAFETY: 映射期间文件被其他进程截断时访问会触发 SIGBUS，这与 --mmap 的使用方式
+    // （读取不再变化的大文件）相符；映射是只读的，不会修改文件
+    let map = unsafe { memmap2::Mmap::map(&file) }
+        .context(format!("Failed to map file: {}", filename.display()))?;
+    let len = map.len() as u64; // 以映射时的长度为准
+    let (start, end) = match bytes {
+        Count::First(num_bytes) => (0, num_bytes),
+        Count::AllButLast(num_bytes) => (0, len.saturating_sub(num_bytes)),
+        Count::Range(start, end) => (start - 1, end),
+        Count::Percent(percent) => (0, percent_of(len, percent)),
+    };
+    let mut end = end.min(len) as usize;
+    if let (Count::First(_) | Count::Percent(_), Some(boundary)) = (bytes, boundary) {
+        end = utf8_boundary(&map, end, boundary);
+    }
+    out.write_all(&map[(start as usize).min(end)..end])?;
+    Ok(())
+}
+
+// tail 模式：按计数方式输出末尾的行
+fn print_tail(
+    filename: &Path,
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    size: Option<u64>,
+    config: &Config,
+) -> Result<()> {
+    let delimiter = config.delimiter.as_slice();
+    match config.lines {
+        Count::First(num_lines) => match (size, delimiter) {
+            // 单字节分隔符的普通文件从末尾向前查找，不必读取整个文件
+            (Some(_), &[byte]) => {
+                let mut file = File::open(filename).map_err(open_error(filename))?;
+                print_tail_seek(&mut file, out, num_lines, byte)
+            }
+            _ => print_tail_lines(file, out, num_lines, config),
+        },
+        // 与 head 的 `-n -K` 对称：输出除开头 K 行以外的全部
+        Count::AllButLast(num_lines) => {
+            skip_lines(file, num_lines, delimiter)?;
+            io::copy(file, out)?;
+            Ok(())
+        }
+        // 范围是从文件开头算起的，与输出方向无关
+        Count::Range(start, end) => {
+            print_line_range(file, out, &mut Offsets::new(None), start, end, 1, delimiter)
+        }
+        // 跳过开头的部分后再对齐到下一行的行首
+        Count::Percent(percent) => {
+            let len = percent_base_len(filename, size)?;
+            let skip = len - percent_of(len, percent);
+            io::copy(&mut file.take(skip), &mut io::sink())?;
+            if skip > 0 {
+                skip_lines(file, 1, delimiter)?;
+            }
+            io::copy(file, out)?;
+            Ok(())
+        }
+    }
+}
+
+// 持续输出普通文件新追加的内容，直到 config.stop 被置位（命令行程序在收到 Ctrl-C 时置位）
+// 标准输入和管道无法判断是否会有新内容，由调用者过滤掉
+// filenames 中同时记录每个文件在输入中的序号，用于标题中的 {index}
+// 标题由 write_banner 输出，内容与其余输出一样经过每个文件各自的格式化器写到 stdout 给出的输出
+fn follow(
+    config: &Config,
+    filenames: &[(usize, PathBuf)],
+    write_banner: &dyn Fn(usize, &Path, bool) -> Result<()>,
+    stdout: &dyn Fn() -> io::Result<Box<dyn Write>>,
+) -> Result<()> {
+    let mut files = Vec::new();
+    for (file_num, filename) in filenames {
+        if let Ok(mut file) = File::open(filename) {
+            let pos = file.seek(SeekFrom::End(0))?;
+            let name = display_name(config, filename).to_string_lossy();
+            let out = formatter(config, &name, stdout()?);
+            files.push((*file_num, filename, file, pos, out));
+        }
+    }
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut buffer = vec![0; 8192];
+    // 最近一次输出内容的文件，切换文件时重新打印标题
+    let mut last_printed = files
+        .iter()
+        .position(|(_, filename, ..)| Some(*filename) == filenames.last().map(|(_, f)| f));
+    while !config.stop.load(Ordering::SeqCst) {
+        let mut idle = true;
+        for (index, (file_num, filename, file, pos, out)) in files.iter_mut().enumerate() {
+            // 文件被截断后从头开始读
+            if file.metadata()?.len() < *pos {
+                eprintln!("{}: file truncated", filename.display());
+                *pos = file.seek(SeekFrom::Start(0))?;
+            }
+            let bytes_read = file.read(&mut buffer)?;
+            if bytes_read == 0 {
+                continue;
+            }
+            if last_printed != Some(index) {
+                write_banner(*file_num, filename, true)?;
+            }
+            out.write_all(&buffer[..bytes_read])?;
+            out.flush()?;
+            *pos += bytes_read as u64;
+            last_printed = Some(index);
+            idle = false;
+        }
+        if idle {
+            thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+    for (.., out) in &mut files {
+        out.finish(&mut io::empty())?;
+    }
+    Ok(())
+}
+
+// 普通文件返回其长度，标准输入、管道等返回 None
+fn regular_file_len(filename: &Path) -> Option<u64> {
+    if filename == Path::new("-") {
+        return None;
+    }
+    fs::metadata(filename)
+        .ok()
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+}
+
+// 原样输出开头的 num_bytes 个字节，输出与输入的前缀逐字节相同
+// 给出 boundary 时把截断位置移到字符边界上
+fn print_bytes(
+    file: Box<dyn BufRead + '_>,
+    out: &mut dyn Write,
+    num_bytes: u64,
+    boundary: Option<Utf8Boundary>,
+) -> Result<()> {
+    let mut handle = file.take(num_bytes);
+    let Some(boundary) = boundary else {
+        io::copy(&mut handle, out)?;
+        return Ok(());
+    };
+    // 分块读取，内存占用与 num_bytes 无关；解码器、管道等一次 read 可能只返回部分数据，
+    // 一直读到足够或遇到 EOF 为止。块末尾不完整的字符留到下一块一起输出
+    let mut chunk = vec![0; 64 * 1024];
+    let mut pending = Vec::new();
+    loop {
+        let n = match handle.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        pending.extend_from_slice(&chunk[..n]);
+        let end = pending.len() - incomplete_utf8_suffix(&pending);
+        out.write_all(&pending[..end])?;
+        pending.drain(..end);
+    }
+    // 剩下的是在字符中间被截断的部分，按 --utf8-boundary 丢掉或者读完整
+    if handle.limit() == 0 && !pending.is_empty() {
+        match boundary {
+            Utf8Boundary::Back => pending.clear(),
+            Utf8Boundary::Forward => {
+                let mut file = handle.into_inner();
+                while pending.len() < utf8_len(pending[0]) {
+                    match file.fill_buf()?.first() {
+                        Some(&byte) if byte & 0xC0 == 0x80 => {
+                            pending.push(byte);
+                            file.consume(1);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+    }
+    out.write_all(&pending)?;
+    Ok(())
+}
+
+// 把 bytes 中的截断位置 end 移到字符边界上
+fn utf8_boundary(bytes: &[u8], end: usize, boundary: Utf8Boundary) -> usize {
+    let cut = incomplete_utf8_suffix(&bytes[..end]);
+    if cut == 0 {
+        return end;
+    }
+    match boundary {
+        Utf8Boundary::Back => end - cut,
+        Utf8Boundary::Forward => {
+            let full = (end - cut + utf8_len(bytes[end - cut])).min(bytes.len());
+            end + bytes[end..full]
+                .iter()
+                .take_while(|&&byte| byte & 0xC0 == 0x80)
+                .count()
+        }
+    }
+}
+
+// 以 lead 开头的 UTF-8 字符的字节数，不是合法的首字节时为 1
+fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    }
+}
+
+// 末尾一个还没读完整的 UTF-8 字符的长度，没有时为 0
+fn incomplete_utf8_suffix(bytes: &[u8]) -> usize {
+    let start = bytes.len().saturating_sub(3);
+    for (i, &byte) in bytes.iter().enumerate().skip(start).rev() {
+        if byte & 0xC0 == 0x80 {
+            continue; // 后续字节，继续向前找首字节
+        }
+        let len = utf8_len(byte);
+        return match bytes.len() - i {
+            found if found < len => found,
+            _ => 0,
+        };
+    }
+    0
+}
+
+// 输出除最后 num_bytes 个字节以外的全部内容
+// 始终只保留末尾 num_bytes 个字节作为滑动窗口，超出的部分立即输出
+fn print_bytes_but_last(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_bytes: u64,
+    max_memory: usize,
+) -> Result<()> {
+    let mut window = SpillQueue::new(max_memory);
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        window.push(chunk)?;
+        let len = chunk.len();
+        file.consume(len);
+        if window.len() > num_bytes {
+            window.pop(window.len() - num_bytes, out)?;
+        }
+    }
+    Ok(())
+}
+
+// 按先进先出的顺序暂存 -n -K / -c -K 末尾的内容
+// 内存中最多保存 max_memory 个字节，超出后新的内容追加到临时文件中；取出时先取内存中的部分
+struct SpillQueue {
+    memory: VecDeque<u8>,
+    spill: Option<SpillFile>,
+    max_memory: usize,
+}
+
+impl SpillQueue {
+    fn new(max_memory: usize) -> Self {
+        SpillQueue {
+            memory: VecDeque::new(),
+            spill: None,
+            max_memory,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        let spilled = self
+            .spill
+            .as_ref()
+            .map_or(0, |spill| spill.write - spill.read);
+        self.memory.len() as u64 + spilled
+    }
+
+    fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.spill {
+            // 已经有内容在临时文件中时，新的内容也要追加到后面才能保持顺序
+            Some(spill) => spill.push(data),
+            None if self.memory.len() + data.len() <= self.max_memory => {
+                self.memory.extend(data);
+                Ok(())
+            }
+            None => self.spill.insert(SpillFile::create()?).push(data),
+        }
+    }
+
+    // 从前面取出 len 个字节写到 out
+    fn pop(&mut self, len: u64, out: &mut dyn Write) -> io::Result<()> {
+        let from_memory = len.min(self.memory.len() as u64) as usize;
+        let (front, back) = self.memory.as_slices();
+        let split = from_memory.min(front.len());
+        out.write_all(&front[..split])?;
+        out.write_all(&back[..from_memory - split])?;
+        self.memory.drain(..from_memory);
+        if let Some(spill) = &mut self.spill {
+            spill.pop(len - from_memory as u64, out)?;
+            // 临时文件中的内容取完后就不再需要
+            if spill.read == spill.write {
+                self.spill = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+// SpillQueue 的临时文件，[read, write) 范围内是还没有取出的内容，删除时一并删除文件
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+    read: u64,
+    write: u64,
+}
+
+// 同一进程中可能同时有多个临时文件（--jobs），用序号区分
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "header.{}.{}.spill",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(SpillFile {
+            file,
+            path,
+            read: 0,
+            write: 0,
+        })
+    }
+
+    fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.write))?;
+        self.file.write_all(data)?;
+        self.write += data.len() as u64;
+        Ok(())
+    }
+
+    // 把 offset 开始的 len 个字节写到 out，不取出，用于 --sample 随机保存的行
+    fn copy_at(&mut self, offset: u64, len: u64, out: &mut dyn Write) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        io::copy(&mut (&self.file).take(len), out)?;
+        Ok(())
+    }
+
+    fn pop(&mut self, len: u64, out: &mut dyn Write) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.read))?;
+        self.read += io::copy(&mut (&self.file).take(len), out)?;
+        // 已经取出的部分比剩下的多时，把剩下的内容移到文件开头，文件大小不会超过窗口的两倍
+        let live = self.write - self.read;
+        if self.read >= live.max(SPILL_COMPACT_MIN) {
+            let mut buffer = vec![0; 64 * 1024];
+            let mut moved = 0;
+            while moved < live {
+                let chunk = buffer.len().min((live - moved) as usize);
+                self.file.seek(SeekFrom::Start(self.read + moved))?;
+                self.file.read_exact(&mut buffer[..chunk])?;
+                self.file.seek(SeekFrom::Start(moved))?;
+                self.file.write_all(&buffer[..chunk])?;
+                moved += chunk as u64;
+            }
+            self.file.set_len(live)?;
+            self.read = 0;
+            self.write = live;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// 临时文件开头已经取出的部分至少有这么大时才整理，避免频繁移动
+const SPILL_COMPACT_MIN: u64 = 1024 * 1024;
+
+// 读取一条以 delimiter 结尾的记录（包含分隔符）追加到 buffer，返回读取的字节数
+// 多字节分隔符先用 read_until 按最后一个字节切分，再检查结尾是否是完整的分隔符
+fn read_record(
+    file: &mut dyn BufRead,
+    delimiter: &[u8],
+    buffer: &mut Vec<u8>,
+) -> io::Result<usize> {
+    let start = buffer.len();
+    let last = match delimiter.last() {
+        Some(&last) => last,
+        None => return file.read_to_end(buffer),
+    };
+    loop {
+        if file.read_until(last, buffer)? == 0 || buffer[start..].ends_with(delimiter) {
+            return Ok(buffer.len() - start);
+        }
+    }
+}
+
+// 与 read_record 相同，但 buffer 中最多暂存 max_len 个字节：记录更长时把前面的部分交给 on_part，
+// 返回时 buffer 中是记录剩下的部分。多字节分隔符的开头可能在已经读到的末尾，这几个字节留在 buffer 中
+// 返回整条记录的字节数，为 0 表示已经读完
+fn read_record_parts(
+    file: &mut dyn BufRead,
+    delimiter: &[u8],
+    buffer: &mut Vec<u8>,
+    max_len: usize,
+    on_part: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<u64> {
+    let mut total = 0;
+    loop {
+        let mut limited = Read::take(
+            &mut *file,
+            max_len.saturating_sub(buffer.len()).max(1) as u64,
+        );
+        let n = match delimiter.last() {
+            Some(&last) => limited.read_until(last, buffer)?,
+            None => limited.read_to_end(buffer)?,
+        };
+        total += n as u64;
+        if n == 0 || (!delimiter.is_empty() && buffer.ends_with(delimiter)) {
+            return Ok(total);
+        }
+        if buffer.len() >= max_len {
+            let split = buffer
+                .len()
+                .saturating_sub(delimiter.len().saturating_sub(1));
+            on_part(&buffer[..split])?;
+            buffer.drain(..split);
+        }
+    }
+}
+
+// 输出开头的 num_lines 行，行以 delimiter 结尾
+// step 大于 1 时每 step 行只输出第一行，共输出 num_lines 行
+fn print_lines(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    offsets: &mut Offsets,
+    num_lines: u64,
+    step: usize,
+    delimiter: &[u8],
+) -> Result<()> {
+    if let (1, true, &[byte]) = (step, offsets.is_plain(), delimiter) {
+        offsets.skip(scan_lines(file, Some(out), num_lines, byte)?);
+        return Ok(());
+    }
+    let max_line = offsets.max_line;
+    let mut line = Vec::new();
+    let mut printed = 0;
+    let mut index = 0;
+    while printed < num_lines {
+        let selected = index % step as u64 == 0;
+        let mut on_part = |part: &[u8]| {
+            if selected {
+                return offsets.write_part(out, part);
+            }
+            offsets.skip(part.len() as u64);
+            Ok(())
+        };
+        let bytes = read_record_parts(file, delimiter, &mut line, max_line, &mut on_part)?;
+        if bytes == 0 {
+            break;
+        }
+        if selected {
+            offsets.write_record(out, &line)?;
+            printed += 1;
+        } else {
+            offsets.skip(line.len() as u64);
+        }
+        index += 1;
+        line.clear();
+    }
+    Ok(())
+}
+
+// 逐行输出，直到已输出的字节数达到 cutoff，最后一行总是完整输出
+fn print_lines_up_to(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    offsets: &mut Offsets,
+    cutoff: u64,
+    delimiter: &[u8],
+) -> Result<()> {
+    let max_line = offsets.max_line;
+    let mut line = Vec::new();
+    let mut printed = 0;
+    while printed < cutoff {
+        let bytes = read_record_parts(file, delimiter, &mut line, max_line, &mut |part| {
+            offsets.write_part(out, part)
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        offsets.write_record(out, &line)?;
+        printed += bytes;
+        line.clear();
+    }
+    Ok(())
+}
+
+// 输出第 start 行到第 end 行（从 1 开始，包含两端）
+fn print_line_range(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    offsets: &mut Offsets,
+    start: u64,
+    end: u64,
+    step: usize,
+    delimiter: &[u8],
+) -> Result<()> {
+    offsets.skip(skip_lines(file, start - 1, delimiter)?);
+    // 范围内按步长能取到的行数
+    let num_lines = (end - start) / step as u64 + 1;
+    print_lines(file, out, offsets, num_lines, step, delimiter)
+}
+
+// 输出除最后 num_lines 行以外的全部内容，step 的含义与 print_lines 相同
+// 只保留最近读到的 num_lines 行，因此对不可 seek 的标准输入同样适用
+// 行的内容超过 --max-memory 时暂存到临时文件中，内存中只保留每行的长度
+fn print_lines_but_last(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    offsets: &mut Offsets,
+    num_lines: u64,
+    config: &Config,
+) -> Result<()> {
+    let max_line = config.max_line_bytes;
+    let mut window = SpillQueue::new(config.max_memory);
+    let mut lengths = VecDeque::new();
+    let mut line = Vec::new();
+    let mut index = 0;
+    loop {
+        line.clear();
+        let bytes = read_record_parts(file, &config.delimiter, &mut line, max_line, &mut |part| {
+            window.push(part)
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        window.push(&line)?;
+        lengths.push_back(bytes);
+        if lengths.len() as u64 > num_lines {
+            if let Some(len) = lengths.pop_front() {
+                if index % config.step == 0 {
+                    // 超长的行分块取出，每块不超过 max_line 个字节
+                    let mut remaining = len;
+                    while remaining > max_line as u64 {
+                        line.clear();
+                        window.pop(max_line as u64, &mut line)?;
+                        offsets.write_part(out, &line)?;
+                        remaining -= max_line as u64;
+                    }
+                    line.clear();
+                    window.pop(remaining, &mut line)?;
+                    offsets.write_record(out, &line)?;
+                } else {
+                    window.pop(len, &mut io::sink())?;
+                    offsets.skip(len);
+                }
+                index += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+// 输出开头的 num_words 个以空白分隔的单词，单词之间原有的空白和换行原样保留，
+// 在第 num_words 个单词结尾处截断
+fn print_words(file: &mut dyn BufRead, out: &mut dyn Write, num_words: usize) -> Result<()> {
+    let mut words = 0;
+    let mut in_word = false;
+    if num_words == 0 {
+        return Ok(());
+    }
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        // 在本块中查找第 num_words 个单词结束的位置
+        let mut end = None;
+        for (i, byte) in chunk.iter().enumerate() {
+            if byte.is_ascii_whitespace() {
+                if in_word && words == num_words {
+                    end = Some(i);
+                    break;
+                }
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                words += 1;
+            }
+        }
+        let len = end.unwrap_or(chunk.len());
+        out.write_all(&chunk[..len])?;
+        file.consume(len);
+        if end.is_some() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// 输出开头的 num_chars 个 UTF-8 字符，按块增量解码，不会截断多字节字符
+// 非法的字节序列替换为 U+FFFD 并计为一个字符，strict 为 true 时直接报错
+fn print_chars(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_chars: usize,
+    strict: bool,
+) -> Result<()> {
+    let mut remaining = num_chars;
+    // 上一块末尾不完整的字符留到下一块再解码
+    let mut pending: Vec<u8> = Vec::new();
+    let mut offset = 0;
+    while remaining > 0 {
+        let chunk = file.fill_buf()?;
+        let eof = chunk.is_empty();
+        pending.extend_from_slice(chunk);
+        let len = chunk.len();
+        file.consume(len);
+
+        let mut pos = 0;
+        while remaining > 0 && pos < pending.len() {
+            let (valid, invalid_len) = match std::str::from_utf8(&pending[pos..]) {
+                Ok(text) => (text, None),
+                Err(e) => {
+                    let valid = std::str::from_utf8(&pending[pos..pos + e.valid_up_to()])?;
+                    match e.error_len() {
+                        Some(n) => (valid, Some(n)),
+                        // 不完整的字符只有在输入结束时才算非法
+                        None if eof => (valid, Some(pending.len() - pos - e.valid_up_to())),
+                        None => (valid, None),
+                    }
+                }
+            };
+            let end = valid
+                .char_indices()
+                .nth(remaining)
+                .map_or(valid.len(), |(i, _)| i);
+            out.write_all(&valid.as_bytes()[..end])?;
+            remaining -= valid[..end].chars().count();
+            pos += end;
+            if remaining == 0 {
+                break;
+            }
+            match invalid_len {
+                Some(_) if strict => {
+                    return Err(invalid!(
+                        "invalid UTF-8 sequence at byte offset {}",
+                        offset + pos
+                    ))
+                }
+                Some(n) => {
+                    out.write_all("\u{FFFD}".as_bytes())?;
+                    remaining -= 1;
+                    pos += n;
+                }
+                // 剩下的是不完整的字符，等待更多数据
+                None => break,
+            }
+        }
+        pending.drain(..pos);
+        offset += pos;
+        if eof {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// 输出开头的 num_graphemes 个字素簇，截断处不会落在字素簇内部
+// 字素簇不会跨越换行符（\r\n 本身是一个字素簇），因此逐行切分即可；超过 max_line 的行分块处理
+fn print_graphemes(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_graphemes: usize,
+    max_line: usize,
+) -> Result<()> {
+    let mut remaining = num_graphemes;
+    let mut line = Vec::new();
+    let mut pending = Vec::new();
+    while remaining > 0 {
+        let bytes = read_record_parts(file, b"\n", &mut line, max_line, &mut |part| {
+            write_graphemes(out, &mut pending, part, false, &mut remaining)
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        write_graphemes(out, &mut pending, &line, true, &mut remaining)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+// 把 bytes 接在 pending 后面，输出其中的字素簇，最多 remaining 个
+// 行还没有结束（complete 为 false）时，末尾不完整的字符和最后一个字素簇可能与后面的内容连在一起，留在 pending 中
+fn write_graphemes(
+    out: &mut dyn Write,
+    pending: &mut Vec<u8>,
+    bytes: &[u8],
+    complete: bool,
+    remaining: &mut usize,
+) -> io::Result<()> {
+    if *remaining == 0 {
+        // 已经输出了足够的字素簇，这一行剩下的部分只需读完
+        pending.clear();
+        return Ok(());
+    }
+    pending.extend_from_slice(bytes);
+    let end = match complete {
+        true => pending.len(),
+        false => pending.len() - incomplete_utf8_suffix(pending),
+    };
+    let text = String::from_utf8_lossy(&pending[..end]).into_owned();
+    let mut graphemes = text.graphemes(true).peekable();
+    let mut kept = "";
+    while let Some(grapheme) = graphemes.next() {
+        if !complete && graphemes.peek().is_none() {
+            kept = grapheme;
+            break;
+        }
+        if *remaining == 0 {
+            break;
+        }
+        out.write_all(grapheme.as_bytes())?;
+        *remaining -= 1;
+    }
+    let mut rest = kept.as_bytes().to_vec();
+    rest.extend_from_slice(&pending[end..]);
+    *pending = rest;
+    Ok(())
+}
+
+// 输出开头的 num_records 条多行记录：匹配 start 的行开始一条新记录，不匹配的行归入上一条记录，
+// 这样日志中的异常堆栈会和它前面带时间戳的那一行保持在一起；第一条匹配行之前的内容单独作为一条记录
+// 逐行输出，不暂存整条记录；超过 max_line 的行只用开头读到的部分判断是否匹配
+fn print_grouped_records(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_records: u64,
+    start: &Regex,
+    delimiter: &[u8],
+    max_line: usize,
+) -> Result<()> {
+    let mut started = 0;
+    // 这一行是否还属于要输出的记录
+    let mut keep = |content: &[u8]| {
+        if started == 0 || start.is_match(content) {
+            if started == num_records {
+                return false;
+            }
+            started += 1;
+        }
+        true
+    };
+    let mut line = Vec::new();
+    loop {
+        let mut kept = None;
+        let bytes = read_record_parts(
+            file,
+            delimiter,
+            &mut line,
+            max_line,
+            &mut |part| match *kept.get_or_insert_with(|| keep(part)) {
+                true => out.write_all(part),
+                false => Ok(()),
+            },
+        )?;
+        if bytes == 0 {
+            break;
+        }
+        let content = line.strip_suffix(delimiter).unwrap_or(&line);
+        if !kept.unwrap_or_else(|| keep(content)) {
+            break;
+        }
+        out.write_all(&line)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+// 输出开头的 num_paragraphs 个段落：段落是以一个或多个空行分隔的文本块，只包含空白字符的行视为空行
+// 段落之间的空行归入后一个段落，最后一个段落之后的空行被丢弃
+// 逐行输出，只暂存还不知道后面是否有段落的空行，超过 --max-memory 时暂存到临时文件中
+fn print_paragraphs(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_paragraphs: usize,
+    config: &Config,
+) -> Result<()> {
+    let delimiter = config.delimiter.as_slice();
+    let mut blanks = SpillQueue::new(config.max_memory);
+    let mut started = 0;
+    let mut separated = true; // 前面是空行或者还没有内容，下一个有内容的行开始新的段落
+    let mut done = false;
+    let mut line = Vec::new();
+    loop {
+        let mut content = false; // 这一行已经出现了非空白字符
+                                 // 输出这一行的一部分（不含分隔符），确定这一行不是空行之前先暂存
+        let mut write = |bytes: &[u8]| -> io::Result<()> {
+            if done {
+                return Ok(());
+            }
+            if content {
+                return out.write_all(bytes);
+            }
+            if bytes.iter().all(u8::is_ascii_whitespace) {
+                return blanks.push(bytes);
+            }
+            if separated {
+                if started == num_paragraphs {
+                    done = true;
+                    return Ok(());
+                }
+                started += 1;
+            }
+            separated = false;
+            content = true;
+            blanks.pop(blanks.len(), out)?;
+            out.write_all(bytes)
+        };
+        let bytes = read_record_parts(
+            file,
+            delimiter,
+            &mut line,
+            config.max_line_bytes,
+            &mut write,
+        )?;
+        if bytes == 0 {
+            break;
+        }
+        let body = line.strip_suffix(delimiter).unwrap_or(&line);
+        write(body)?;
+        let end = &line[body.len()..];
+        if done {
+            break;
+        }
+        if content {
+            out.write_all(end)?;
+        } else if started == num_paragraphs {
+            // 已经输出了足够的段落，后面的空行都会被丢弃
+            break;
+        } else {
+            blanks.push(end)?;
+            separated = true;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+// 记录从内部读取器读到的所有字节，用于原样输出已经解析过的 CSV 记录
+struct Recorder<R> {
+    inner: R,
+    seen: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.seen.extend_from_slice(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+// 输出 CSV 的表头行以及之后的 num_records 条记录
+// 引号内的换行属于同一条记录，输出的是原始字节而不是重新序列化的结果
+fn print_csv(file: Box<dyn BufRead + '_>, out: &mut dyn Write, num_records: u64) -> Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(Recorder {
+            inner: file,
+            seen: Vec::new(),
+        });
+    let mut record = csv::ByteRecord::new();
+    // 已经输出的字节数，也就是 seen 中第一个字节的偏移量
+    let mut emitted = 0;
+    // 表头行加上 num_records 条数据记录
+    for _ in 0..=num_records {
+        if !reader.read_byte_record(&mut record)? {
+            break;
+        }
+        let mut end = reader.position().byte();
+        let recorder = reader.get_mut();
+        let mut len = (end - emitted) as usize;
+        // csv 把 \r 当作记录结尾，\r\n 中的 \n 要一起输出
+        if recorder.seen[..len].ends_with(b"\r") && recorder.seen.get(len) == Some(&b'\n') {
+            len += 1;
+            end += 1;
+        }
+        out.write_all(&recorder.seen[..len])?;
+        recorder.seen.drain(..len);
+        emitted = end;
+    }
+    Ok(())
+}
+
+// 输出开头的 num_records 条 JSON 记录，空行不计数
+// 每条记录先完整校验，遇到不合法的记录时停止并报错，不会输出残缺的内容
+// 记录必须完整读入内存才能校验，超过 max_line 个字节的记录作为错误报告
+fn print_ndjson(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_records: u64,
+    pretty: bool,
+    max_line: usize,
+) -> Result<()> {
+    let mut line = Vec::new();
+    let mut printed = 0;
+    let mut line_num = 0;
+    while printed < num_records {
+        line.clear();
+        line_num += 1;
+        let bytes = read_record_parts(file, b"\n", &mut line, max_line, &mut |_| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "JSON record on line {} is longer than --max-line-bytes ({} bytes)",
+                    line_num, max_line
+                ),
+            ))
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        let content = line.trim_ascii();
+        if content.is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_slice(content)
+            .with_context(|| format!("invalid JSON record on line {}", line_num))?;
+        if pretty {
+            serde_json::to_writer_pretty(&mut *out, &value)?;
+        } else {
+            out.write_all(content)?;
+        }
+        out.write_all(b"\n")?;
+        printed += 1;
+    }
+    Ok(())
+}
+
+// 逐行输出，直到遇到第一行匹配 until 的行或者已输出 num_lines 行
+// exclusive 为 true 时不输出匹配的那一行
+fn print_until(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    offsets: &mut Offsets,
+    num_lines: u64,
+    until: &Regex,
+    exclusive: bool,
+    delimiter: &[u8],
+) -> Result<()> {
+    let max_line = offsets.max_line;
+    let mut line = Vec::new();
+    for _ in 0..num_lines {
+        // 超过 max_line 的行只用开头读到的部分判断是否匹配
+        let mut matched = None;
+        let bytes =
+            read_record_parts(
+                file,
+                delimiter,
+                &mut line,
+                max_line,
+                &mut |part| match *matched.get_or_insert_with(|| until.is_match(part)) && exclusive
+                {
+                    true => Ok(()),
+                    false => offsets.write_part(out, part),
+                },
+            )?;
+        if bytes == 0 {
+            break;
+        }
+        let content = line.strip_suffix(delimiter).unwrap_or(&line);
+        let matched = matched.unwrap_or_else(|| until.is_match(content));
+        if !(matched && exclusive) {
+            offsets.write_record(out, &line)?;
+        }
+        if matched {
+            break;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+// 用蓄水池抽样从所有行中均匀随机抽取 num_lines 行，按原来的顺序输出
+// 内存中最多只保存 num_lines 行，超过 --max-line-bytes 的行保存在临时文件中
+fn print_sample(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_lines: usize,
+    config: &Config,
+) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let mut reservoir: Vec<(usize, Sampled)> = Vec::new();
+    let mut spill: Option<SpillFile> = None;
+    let mut index = 0;
+    loop {
+        // 读这一行之前先决定它放到蓄水池的哪个位置，不放入的行不必保存
+        // 第 index 行以 num_lines / (index + 1) 的概率替换蓄水池中的某一行
+        let slot = match reservoir.len() < num_lines {
+            true => Some(reservoir.len()),
+            false => Some(rng.gen_range(0..=index)).filter(|&slot| slot < num_lines),
+        };
+        let mut line = Vec::new();
+        let mut spilled = None; // 这一行在临时文件中的起始位置
+        let max_line = config.max_line_bytes;
+        let bytes = read_record_parts(file, &config.delimiter, &mut line, max_line, &mut |part| {
+            if slot.is_none() {
+                return Ok(());
+            }
+            spilled.get_or_insert(spill.as_ref().map_or(0, |spill| spill.write));
+            match &mut spill {
+                Some(spill) => spill.push(part),
+                None => spill.insert(SpillFile::create()?).push(part),
+            }
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        if let Some(slot) = slot {
+            let sampled = match (spilled, &mut spill) {
+                (Some(offset), Some(spill)) => {
+                    spill.push(&line)?;
+                    Sampled::Spilled(offset, bytes)
+                }
+                _ => Sampled::Memory(line),
+            };
+            match slot == reservoir.len() {
+                true => reservoir.push((index, sampled)),
+                false => reservoir[slot] = (index, sampled),
+            }
+        }
+        index += 1;
+    }
+    reservoir.sort_by_key(|(index, _)| *index);
+    for (_, sampled) in reservoir {
+        match (sampled, &mut spill) {
+            (Sampled::Memory(line), _) => out.write_all(&line)?,
+            (Sampled::Spilled(offset, len), Some(spill)) => spill.copy_at(offset, len, out)?,
+            (Sampled::Spilled(..), None) => unreachable!("spilled line without a spill file"),
+        }
+    }
+    Ok(())
+}
+
+// 蓄水池中的一行：内存中的内容，或者在临时文件中的起始位置和长度
+enum Sampled {
+    Memory(Vec<u8>),
+    Spilled(u64, u64),
+}
+
+// 丢弃第一行匹配 from 的行之前的所有行，返回从匹配行开始的输入以及丢弃的字节数
+// 没有匹配的行时返回空输入
+// 超过 max_line 的行只读入开头的部分用来判断是否匹配，匹配时其余部分仍然留在输入中
+fn skip_to_match<'a>(
+    mut file: Box<dyn BufRead + 'a>,
+    from: &Regex,
+    delimiter: &[u8],
+    max_line: usize,
+) -> Result<(Box<dyn BufRead + 'a>, u64)> {
+    let mut line = Vec::new();
+    let mut skipped = 0;
+    loop {
+        let n = read_record(
+            &mut Read::take(&mut file, max_line as u64),
+            delimiter,
+            &mut line,
+        )?;
+        if n == 0 {
+            return Ok((Box::new(io::empty()), skipped));
+        }
+        let content = line.strip_suffix(delimiter).unwrap_or(&line);
+        if from.is_match(content) {
+            // 匹配行已经被读出，需要放回输入的最前面
+            return Ok((Box::new(Cursor::new(line).chain(file)), skipped));
+        }
+        skipped += line.len() as u64;
+        if n == max_line && !line.ends_with(delimiter) {
+            // 丢弃这一行剩下的部分，分隔符可能跨在两次读取之间，末尾几个字节一起检查
+            let mut rest = line[line.len() - (delimiter.len() - 1).min(line.len())..].to_vec();
+            skipped +=
+                read_record_parts(&mut file, delimiter, &mut rest, max_line, &mut |_| Ok(()))?;
+        }
+        line.clear();
+    }
+}
+
+// 跳过开头的 num_lines 行，返回跳过的字节数
+fn skip_lines(file: &mut dyn BufRead, num_lines: u64, delimiter: &[u8]) -> Result<u64> {
+    if let &[byte] = delimiter {
+        return Ok(scan_lines(file, None, num_lines, byte)?);
+    }
+    let mut line = Vec::new();
+    let mut skipped = 0;
+    for _ in 0..num_lines {
+        let bytes = read_record_parts(file, delimiter, &mut line, MAX_LINE_BYTES, &mut |_| Ok(()))?;
+        if bytes == 0 {
+            break;
+        }
+        skipped += bytes;
+        line.clear();
+    }
+    Ok(skipped)
+}
+
+// 单字节分隔符时不必逐行读取：在整块数据中用 memchr 找到第 num_lines 个分隔符，
+// 之前的内容一次写到 out（为 None 时丢弃），返回处理过的字节数
+fn scan_lines(
+    file: &mut dyn BufRead,
+    mut out: Option<&mut dyn Write>,
+    num_lines: u64,
+    delimiter: u8,
+) -> io::Result<u64> {
+    let mut remaining = num_lines;
+    let mut total = 0;
+    while remaining > 0 {
+        let buffer = file.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        let mut end = buffer.len();
+        for found in memchr::memchr_iter(delimiter, buffer) {
+            remaining -= 1;
+            if remaining == 0 {
+                end = found + 1;
+                break;
+            }
+        }
+        if let Some(out) = out.as_mut() {
+            out.write_all(&buffer[..end])?;
+        }
+        file.consume(end);
+        total += end as u64;
+    }
+    Ok(total)
+}
+
+// 记录当前行在输入中的起始字节偏移，开启 --offsets 时输出在行首
+// 着色时偏移和行内匹配 highlight 的部分按 palette 输出
+struct Offsets {
+    format: Option<OffsetFormat>,
+    position: u64,
+    palette: Palette,
+    highlight: Option<Regex>,
+    delimiter: Vec<u8>, // 高亮时不匹配行尾的分隔符
+    max_line: usize,    // 逐行读取时一行最多暂存的字节数，见 read_record_parts
+    continued: bool,    // 当前记录已经提前输出了一部分，不再加偏移
+}
+
+// --max-line-bytes 的默认值
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+impl Offsets {
+    fn new(format: Option<OffsetFormat>) -> Self {
+        Offsets {
+            format,
+            position: 0,
+            palette: Palette::default(),
+            highlight: None,
+            delimiter: Vec::new(),
+            max_line: MAX_LINE_BYTES,
+            continued: false,
+        }
+    }
+
+    fn styled(mut self, palette: Palette, highlight: Option<Regex>, delimiter: &[u8]) -> Self {
+        self.palette = palette;
+        self.highlight = highlight;
+        self.delimiter = delimiter.to_vec();
+        self
+    }
+
+    fn max_line(mut self, max_line: usize) -> Self {
+        self.max_line = max_line;
+        self
+    }
+
+    // 不加偏移也不着色时记录可以原样整块输出
+    fn is_plain(&self) -> bool {
+        self.format.is_none() && self.highlight.is_none()
+    }
+
+    // 读过但没有输出的字节
+    fn skip(&mut self, len: u64) {
+        self.position += len;
+    }
+
+    // 输出一条记录，需要时在前面加上它的偏移
+    // 记录按原始字节输出，与 GNU head 一样保留非法的 UTF-8
+    fn write_record(&mut self, out: &mut dyn Write, record: &[u8]) -> io::Result<()> {
+        self.write_part(out, record)?;
+        self.continued = false;
+        Ok(())
+    }
+
+    // 输出超长记录中提前输出的一部分，偏移只加在记录的开头；跨越两部分的匹配不会高亮
+    fn write_part(&mut self, out: &mut dyn Write, record: &[u8]) -> io::Result<()> {
+        let offset = match (self.continued, self.format) {
+            (true, _) => None,
+            (false, Some(OffsetFormat::Dec)) => Some(self.position.to_string()),
+            (false, Some(OffsetFormat::Hex)) => Some(format!("{:#x}", self.position)),
+            (false, None) => None,
+        };
+        if let Some(offset) = offset {
+            write!(out, "{}:", self.palette.paint(Style::Offset, offset))?;
+        }
+        let content = record.strip_suffix(&self.delimiter[..]).unwrap_or(record);
+        let mut last = 0;
+        if let Some(highlight) = &self.highlight {
+            for found in highlight
+                .find_iter(content)
+                .filter(|found| !found.is_empty())
+            {
+                out.write_all(&record[last..found.start()])?;
+                write!(
+                    out,
+                    "{}",
+                    self.palette
+                        .paint(Style::Match, String::from_utf8_lossy(found.as_bytes()))
+                )?;
+                last = found.end();
+            }
+        }
+        out.write_all(&record[last..])?;
+        self.position += record.len() as u64;
+        self.continued = true;
+        Ok(())
+    }
+}
+
+// 输出最后 num_lines 行，适用于标准输入、管道等不可 seek 的输入
+// 与 print_lines_but_last 一样，最近的 num_lines 行暂存在 SpillQueue 中，超长的行分块读取
+fn print_tail_lines(
+    file: &mut dyn BufRead,
+    out: &mut dyn Write,
+    num_lines: u64,
+    config: &Config,
+) -> Result<()> {
+    if num_lines == 0 {
+        return Ok(());
+    }
+    let mut window = SpillQueue::new(config.max_memory);
+    let mut lengths = VecDeque::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let max_line = config.max_line_bytes;
+        let bytes = read_record_parts(file, &config.delimiter, &mut line, max_line, &mut |part| {
+            window.push(part)
+        })?;
+        if bytes == 0 {
+            break;
+        }
+        window.push(&line)?;
+        lengths.push_back(bytes);
+        if lengths.len() as u64 > num_lines {
+            if let Some(len) = lengths.pop_front() {
+                window.pop(len, &mut io::sink())?;
+            }
+        }
+    }
+    window.pop(window.len(), out)?;
+    Ok(())
+}
+
+// 输出普通文件的最后 num_lines 行：从文件末尾按块向前查找分隔符，
+// 找到倒数第 num_lines 行的起始位置后直接复制到结尾
+fn print_tail_seek(
+    file: &mut File,
+    out: &mut dyn Write,
+    num_lines: u64,
+    delimiter: u8,
+) -> Result<()> {
+    const BLOCK_SIZE: usize = 8192;
+
+    let len = file.seek(SeekFrom::End(0))?;
+    // 没找到足够的分隔符时从头输出
+    let mut start = if num_lines == 0 { len } else { 0 };
+    let mut remaining = num_lines;
+    let mut pos = len;
+    let mut buffer = vec![0; BLOCK_SIZE];
+    'search: while pos > 0 && remaining > 0 {
+        let size = BLOCK_SIZE.min(pos as usize);
+        pos -= size as u64;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buffer[..size])?;
+        for (i, &byte) in buffer[..size].iter().enumerate().rev() {
+            let offset = pos + i as u64;
+            // 文件最后一个字节的分隔符属于最后一行，不作为行首的边界
+            if byte == delimiter && offset + 1 < len {
+                remaining -= 1;
+                if remaining == 0 {
+                    start = offset + 1;
+                    break 'search;
+                }
+            }
+        }
+    }
+    file.seek(SeekFrom::Start(start))?;
+    io::copy(file, out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan_lines, skip_lines};
+    use std::io::{BufReader, Cursor};
+
+    // 缓冲区只有 capacity 字节，扫描 num_lines 行，返回输出的内容和处理过的字节数
+    fn scan(input: &[u8], capacity: usize, num_lines: u64, delimiter: u8) -> (Vec<u8>, u64) {
+        let mut file = BufReader::with_capacity(capacity, Cursor::new(input));
+        let mut out = Vec::new();
+        let total = scan_lines(&mut file, Some(&mut out), num_lines, delimiter).unwrap();
+        (out, total)
+    }
+
+    #[test]
+    fn scan_lines_across_buffer_boundaries() {
+        let input = b"one\ntwo\nthree\nfour\n";
+        // 缓冲区大小从 1 到超过整个输入，分隔符落在缓冲区的开头、中间和末尾
+        for capacity in 1..=input.len() + 1 {
+            assert_eq!(
+                scan(input, capacity, 3, b'\n'),
+                (b"one\ntwo\nthree\n".to_vec(), 14)
+            );
+            assert_eq!(scan(input, capacity, 0, b'\n'), (Vec::new(), 0));
+            assert_eq!(
+                scan(input, capacity, 10, b'\n'),
+                (input.to_vec(), input.len() as u64)
+            );
+        }
+    }
+
+    #[test]
+    fn scan_lines_stops_inside_buffer() {
+        // 扫描结束后剩下的内容留在 file 中，继续读取时从下一行开始
+        let mut file = BufReader::with_capacity(64, Cursor::new(&b"a\nb\nc\n"[..]));
+        assert_eq!(scan_lines(&mut file, None, 1, b'\n').unwrap(), 2);
+        let mut rest = String::new();
+        std::io::Read::read_to_string(&mut file, &mut rest).unwrap();
+        assert_eq!(rest, "b\nc\n");
+    }
+
+    #[test]
+    fn scan_lines_custom_delimiter() {
+        let input = b"a\nb\0c\nd\0e";
+        for capacity in [1, 3, 64] {
+            assert_eq!(scan(input, capacity, 1, b'\0'), (b"a\nb\0".to_vec(), 4));
+            assert_eq!(
+                scan(input, capacity, 2, b'\0'),
+                (b"a\nb\0c\nd\0".to_vec(), 8)
+            );
+            assert_eq!(scan(input, capacity, 2, b'\n'), (b"a\nb\0c\n".to_vec(), 6));
+        }
+    }
+
+    #[test]
+    fn scan_lines_last_line_without_delimiter() {
+        for capacity in [1, 2, 64] {
+            assert_eq!(scan(b"x\nyz", capacity, 2, b'\n'), (b"x\nyz".to_vec(), 4));
+            assert_eq!(scan(b"x\nyz", capacity, 5, b'\n'), (b"x\nyz".to_vec(), 4));
+            assert_eq!(scan(b"xyz", capacity, 1, b'\n'), (b"xyz".to_vec(), 3));
+            assert_eq!(scan(b"", capacity, 1, b'\n'), (Vec::new(), 0));
+        }
+    }
+
+    #[test]
+    fn skip_lines_single_byte_delimiter() {
+        let mut file = BufReader::with_capacity(2, Cursor::new(&b"one\ntwo\nthree"[..]));
+        assert_eq!(skip_lines(&mut file, 2, b"\n").unwrap(), 8);
+        assert_eq!(skip_lines(&mut file, 2, b"\n").unwrap(), 5);
+    }
 }
@@ -1,6 +1,71 @@
+use std::path::Path;
+
 fn main() {
-    if let Err(e) = header::get_args().and_then(header::run) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    // 与其他 coreutils 一样，下游关闭管道后由 SIGPIPE 直接结束进程，不报告错误
+    // Rust 默认忽略 SIGPIPE，这里恢复默认的处理方式
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    // GNU 和 BSD 格式的错误信息以程序名开头，通过符号链接以 head 的名字运行时显示为 head
+    let program = std::env::args_os()
+        .next()
+        .and_then(|arg0| Path::new(&arg0).file_name().map(|name| name.to_owned()))
+        .unwrap_or_else(|| "header".into());
+    let program = program.to_string_lossy();
+    let (compat, result) = match header::get_args() {
+        Ok(config) => {
+            // --follow 一直运行到收到 Ctrl-C；信号处理函数由程序而不是库安装。WASI 没有信号
+            #[cfg(not(target_os = "wasi"))]
+            if config.follow() {
+                let stop = config.stop_flag();
+                if let Err(e) = ctrlc::set_handler(move || {
+                    stop.store(true, std::sync::atomic::Ordering::SeqCst)
+                }) {
+                    eprintln!("{}: Failed to install Ctrl-C handler: {}", program, e);
+                    std::process::exit(1);
+                }
+            }
+            let compat = config.compat();
+            // 无法打开的文件在处理到它时立即报告，与 head 一样排在前面文件的输出之后
+            let report_error = |file: &header::FileReport| {
+                if let Some(compat) = compat {
+                    if let Some(message) = file.compat_error(compat) {
+                        eprintln!("{}: {}", program, message);
+                    }
+                } else if let Some(e) = &file.error {
+                    match e {
+                        // 错误信息中已经带有文件名
+                        header::HeaderError::IsDirectory { .. } => {
+                            eprintln!("{}: {}", program, e)
+                        }
+                        header::HeaderError::OpenFile { .. } => {
+                            eprintln!("{}", e.full_message())
+                        }
+                        e => eprintln!("{}: {}", file.path.display(), e.full_message()),
+                    }
+                }
+            };
+            (compat, header::run_with(config, report_error))
+        }
+        // 参数本身有误时还没有 Config，从参数中单独找出 --compat 等选项
+        Err(e) => (header::compat_from_args(std::env::args_os()), Err(e)),
+    };
+    match result {
+        Ok(report) => std::process::exit(report.exit_code()),
+        // 没有 SIGPIPE 的平台上写入已关闭的管道时同样安静地退出
+        Err(e) if e.is_broken_pipe() => std::process::exit(0),
+        Err(e) if compat.is_some() => {
+            let message = match compat {
+                Some(header::Compat::Gnu) => e.gnu_message(),
+                _ => e.bsd_message(),
+            };
+            eprintln!("{}: {}", program, message);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{:#}", anyhow::Error::from(e));
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,451 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufReader,
+    ops::ControlFlow,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use header::{
+    get_args_from, head_bytes, head_lines, head_with, run, run_with, Config, Count, HeadLines,
+    HeaderError,
+};
+
+type HeaderResult<T> = Result<T, Box<dyn Error>>;
+
+const ONE: &str = "./tests/inputs/one.txt";
+const TEN: &str = "./tests/inputs/ten.txt";
+const THREE: &str = "./tests/inputs/three.txt";
+
+// --------------------------------------------------
+#[test]
+fn lines_from_slice() -> HeaderResult<()> {
+    let mut out = Vec::new();
+    head_lines(&b"a\nb\nc\n"[..], &mut out, 2)?;
+    assert_eq!(out, b"a\nb\n");
+    // 行数不足时全部输出，最后一行没有换行符也原样输出
+    let mut out = Vec::new();
+    head_lines(&b"a\nb"[..], &mut out, 5)?;
+    assert_eq!(out, b"a\nb");
+    Ok(())
+}
+
+#[test]
+fn lines_from_file() -> HeaderResult<()> {
+    let mut out = Vec::new();
+    head_lines(BufReader::new(File::open(TEN)?), &mut out, 3)?;
+    let expected: String = std::fs::read_to_string(TEN)?
+        .split_inclusive('\n')
+        .take(3)
+        .collect();
+    assert_eq!(String::from_utf8(out)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_keep_invalid_utf8() -> HeaderResult<()> {
+    let mut out = Vec::new();
+    head_bytes(&b"ab\xe4\xb8\xad"[..], &mut out, 4)?;
+    assert_eq!(out, b"ab\xe4\xb8");
+    let mut out = Vec::new();
+    head_bytes(&b"abc"[..], &mut out, 10)?;
+    assert_eq!(out, b"abc");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lines_with_callback() -> HeaderResult<()> {
+    let mut seen = Vec::new();
+    head_with(&b"a\nb\nc\n"[..], 2, |line| {
+        seen.push(line.to_vec());
+        ControlFlow::Continue(())
+    })?;
+    assert_eq!(seen, [b"a\n".to_vec(), b"b\n".to_vec()]);
+    // 返回 Break 后不再调用，剩下的内容也不读取
+    let mut reader = &b"a\nstop\nc\nd\n"[..];
+    let mut seen = Vec::new();
+    head_with(&mut reader, 10, |line| {
+        seen.push(line.to_vec());
+        match line {
+            b"stop\n" => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        }
+    })?;
+    assert_eq!(seen, [b"a\n".to_vec(), b"stop\n".to_vec()]);
+    assert_eq!(reader, b"c\nd\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+static OUTPUTS: AtomicUsize = AtomicUsize::new(0);
+
+// 输出写到临时文件中，不必启动进程
+fn run_to_string(builder: header::ConfigBuilder) -> HeaderResult<String> {
+    let path = std::env::temp_dir().join(format!(
+        "header-api-{}-{}",
+        std::process::id(),
+        OUTPUTS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let report = run(builder.output_file(&path).build()?)?;
+    let output = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(report.exit_code(), 0);
+    Ok(output)
+}
+
+#[test]
+fn builder_runs() -> HeaderResult<()> {
+    let output = run_to_string(Config::builder().files([TEN]).lines(2))?;
+    assert_eq!(output, "Three\nlines,\n");
+    let output = run_to_string(
+        Config::builder()
+            .files([TEN, ONE])
+            .lines(Count::Range(2, 2)),
+    )?;
+    assert_eq!(
+        output,
+        format!("==> {} <==\nlines,\n\n==> {} <==\n", TEN, ONE)
+    );
+    let output = run_to_string(Config::builder().files([TEN]).bytes(5).verbose(true))?;
+    assert_eq!(output, format!("==> {} <==\nThree", TEN));
+    Ok(())
+}
+
+#[test]
+fn builder_matches_cli() -> HeaderResult<()> {
+    // --until 没有给出 -n 时不限制行数，构造器的结果应当与命令行相同
+    for (builder, args) in [
+        (
+            Config::builder().files([THREE]).until("twelve", false),
+            &["--until", "twelve", THREE][..],
+        ),
+        (
+            Config::builder()
+                .files([THREE])
+                .until("twelve", true)
+                .lines(3),
+            &["--until", "twelve", "--until-exclusive", "-n", "3", THREE],
+        ),
+        (
+            Config::builder()
+                .files([THREE])
+                .from("four")
+                .until("six", false),
+            &["--from", "four", "--until", "six", THREE],
+        ),
+    ] {
+        let path = std::env::temp_dir().join(format!(
+            "header-api-{}-{}",
+            std::process::id(),
+            OUTPUTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let config = get_args_from(["header", "-o", path.to_str().unwrap()].iter().chain(args))?;
+        run(config)?;
+        let expected = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(run_to_string(builder)?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn follow_stops_on_flag() -> HeaderResult<()> {
+    // 库不安装信号处理函数，同一进程中可以多次运行 --follow，由调用者置位停止标志
+    // --follow 不能与 -o 同时使用，-n 0 使它不向标准输出写任何内容
+    for _ in 0..2 {
+        let config = get_args_from(["header", "-f", "-n", "0", ONE])?;
+        let stop = config.stop_flag();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            stop.store(true, Ordering::SeqCst);
+        });
+        assert_eq!(run(config)?.exit_code(), 0);
+        stopper.join().unwrap();
+    }
+    Ok(())
+}
+
+#[test]
+fn builder_validates() {
+    let error = |builder: header::ConfigBuilder| builder.build().unwrap_err().to_string();
+    assert_eq!(
+        error(Config::builder().lines(1).bytes(1)),
+        "lines and bytes cannot be used together"
+    );
+    assert_eq!(
+        error(Config::builder().delimiter("")),
+        "Record delimiter must not be empty"
+    );
+    assert_eq!(
+        error(Config::builder().step(0)),
+        "Failed to parse step: 0: illegal number: 0"
+    );
+    assert_eq!(
+        error(Config::builder().until("(", false)),
+        "Invalid --until pattern: ("
+    );
+    // 与命令行的 -n 2,3 --until 一样拒绝
+    assert_eq!(
+        error(
+            Config::builder()
+                .lines(Count::Range(2, 3))
+                .until("x", false)
+        ),
+        "--until only supports a plain line count"
+    );
+}
+
+#[test]
+fn error_kinds() {
+    match Config::builder().jobs(0).build() {
+        Err(HeaderError::ParseCount { what, value, .. }) => {
+            assert_eq!((what.as_str(), value.as_str()), ("jobs", "0"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let config = Config::builder()
+        .files(["./tests/inputs/missing.txt"])
+        .fail_fast(true)
+        .build()
+        .unwrap();
+    match run(config) {
+        Err(HeaderError::OpenFile { path, source }) => {
+            assert_eq!(path, std::path::Path::new("./tests/inputs/missing.txt"));
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_report() -> HeaderResult<()> {
+    let missing = "./tests/inputs/missing.txt";
+    let path = std::env::temp_dir().join(format!("header-api-report-{}", std::process::id()));
+    let config = Config::builder()
+        .files([TEN, missing, ONE])
+        .lines(2)
+        .quiet(true)
+        .output_file(&path)
+        .build()?;
+    let report = run(config)?;
+    fs::remove_file(&path)?;
+    assert_eq!(report.exit_code(), 1);
+    let outcomes: Vec<_> = report
+        .files
+        .iter()
+        .map(|file| {
+            (
+                file.path.to_str().unwrap(),
+                file.lines,
+                file.bytes,
+                file.error.is_some(),
+            )
+        })
+        .collect();
+    // 最后一行没有换行符时也算一行
+    assert_eq!(
+        outcomes,
+        [
+            (TEN, 2, 13, false),
+            (missing, 0, 0, true),
+            (ONE, 1, 22, false)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn run_reports_errors_as_they_happen() -> HeaderResult<()> {
+    let missing = "./tests/inputs/missing.txt";
+    let path = std::env::temp_dir().join(format!("header-api-errors-{}", std::process::id()));
+    let config = Config::builder()
+        .files([missing, TEN, missing])
+        .output_file(&path)
+        .build()?;
+    let mut reported = Vec::new();
+    let report = run_with(config, |file| {
+        reported.push((file.path.clone(), file.error.is_some()))
+    })?;
+    fs::remove_file(&path)?;
+    assert_eq!(reported, [(missing.into(), true), (missing.into(), true)]);
+    assert_eq!(report.files.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn run_reports_read_errors_per_file() -> HeaderResult<()> {
+    // 解压到一半出错记录在这个文件的结果中，不影响后面的文件
+    let gz = fs::read("./tests/inputs/ten.txt.gz")?;
+    let truncated = std::env::temp_dir().join(format!("header-api-{}.gz", std::process::id()));
+    fs::write(&truncated, &gz[..30])?;
+    let path = std::env::temp_dir().join(format!("header-api-gz-{}", std::process::id()));
+    let config = Config::builder()
+        .files([TEN.into(), truncated.clone(), THREE.into()])
+        .output_file(&path)
+        .build()?;
+    let mut reported = Vec::new();
+    let report = run_with(config, |file| reported.push(file.path.clone()))?;
+    let output = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    fs::remove_file(&truncated)?;
+    assert_eq!(reported, [truncated]);
+    let errors: Vec<bool> = report
+        .files
+        .iter()
+        .map(|file| file.error.is_some())
+        .collect();
+    assert_eq!(errors, [false, true, false]);
+    assert_eq!(report.exit_code(), 1);
+    assert!(output.ends_with("one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n"));
+    Ok(())
+}
+
+#[test]
+fn run_report_counts_copied_bytes() -> HeaderResult<()> {
+    // 写到标准输出的 -c 由内核直接复制，不经过格式化器，同样要计入结果
+    let report = run(Config::builder().files([TEN]).bytes(13).build()?)?;
+    assert_eq!((report.files[0].lines, report.files[0].bytes), (2, 13));
+    let report = run(Config::builder()
+        .files([TEN])
+        .bytes(Count::Range(7, 9))
+        .build()?)?;
+    assert_eq!((report.files[0].lines, report.files[0].bytes), (1, 3));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn args_from_iterator() -> HeaderResult<()> {
+    let path = std::env::temp_dir().join(format!("header-api-args-{}", std::process::id()));
+    // 旧式写法 -NUM 同样适用
+    let config = get_args_from(["header", "-2", "-o", path.to_str().unwrap(), TEN])?;
+    assert_eq!(run(config)?.exit_code(), 0);
+    assert_eq!(fs::read_to_string(&path)?, "Three\nlines,\n");
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn args_from_errors() {
+    assert!(matches!(
+        get_args_from(["header", "-n", "x"]),
+        Err(HeaderError::ParseCount { .. })
+    ));
+    assert!(matches!(
+        get_args_from(["header", "--no-such-option"]),
+        Err(HeaderError::Args(_))
+    ));
+    // --help 也不会退出进程
+    match get_args_from(["header", "--help"]) {
+        Err(HeaderError::Args(e)) => assert!(e.to_string().contains("Usage:")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+// --------------------------------------------------
+#[test]
+fn head_lines_iterator() -> HeaderResult<()> {
+    let records = HeadLines::new(&b"a\nb\nc\n"[..], 2).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records, [b"a\n".to_vec(), b"b\n".to_vec()]);
+    // 记录不足时提前结束，最后一条没有分隔符也照常返回
+    let records = HeadLines::new(&b"a\nb"[..], 5).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records, [b"a\n".to_vec(), b"b".to_vec()]);
+    Ok(())
+}
+
+#[test]
+fn head_lines_iterator_is_lazy() -> HeaderResult<()> {
+    let mut lines = HeadLines::with_delimiter(&b"a\0b\0c\0"[..], 1, b"\0");
+    assert_eq!(lines.next().transpose()?, Some(b"a\0".to_vec()));
+    assert!(lines.next().is_none());
+    // 没有读取的内容留在 reader 中
+    assert_eq!(lines.into_inner(), b"b\0c\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "serde")]
+#[test]
+fn config_round_trip() -> HeaderResult<()> {
+    let config = Config::builder()
+        .files([TEN, ONE])
+        .lines(Count::Range(2, 3))
+        .from("^x")
+        .quiet(true)
+        .build()?;
+    let json = serde_json::to_string(&config)?;
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    assert_eq!(value["lines"], serde_json::json!({ "Range": [2, 3] }));
+    assert_eq!(value["from"], "^x");
+    let restored: Config = serde_json::from_str(&json)?;
+    assert_eq!(serde_json::to_string(&restored)?, json);
+    // 未给出的字段取默认值
+    let partial: Config = serde_json::from_str(&format!(
+        r#"{{"files": ["{}"], "lines": {{"First": 2}}}}"#,
+        TEN
+    ))?;
+    assert_eq!(
+        format!("{:?}", partial),
+        format!("{:?}", Config::builder().files([TEN]).lines(2).build()?)
+    );
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn config_deserialize_validates() {
+    let error = |json: &str| {
+        serde_json::from_str::<Config>(json)
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(error(r#"{"files": []}"#).contains("No input files"));
+    assert!(error(r#"{"jobs": 0}"#).contains("Failed to parse jobs: 0: illegal number: 0"));
+    assert!(error(r#"{"until": "("}"#).contains("regex parse error"));
+    // 与 Config::builder().lines(Count::Range(2, 3)).until("x", false) 一样拒绝
+    assert!(error(r#"{"lines": {"Range": [2, 3]}, "until": "x"}"#)
+        .contains("--until only supports a plain line count"));
+    assert!(error(r#"{"delimiter": []}"#).contains("Record delimiter must not be empty"));
+}
+
+// --------------------------------------------------
+#[cfg(all(feature = "ffi", unix))]
+#[test]
+fn ffi_head_file() -> HeaderResult<()> {
+    use header::ffi::{
+        header_head_file, HeaderLimits, HeaderStatus, HEADER_UNIT_BYTES, HEADER_UNIT_LINES,
+    };
+    use std::{ffi::CString, os::unix::io::AsRawFd};
+
+    let path = std::env::temp_dir().join(format!("header-api-ffi-{}", std::process::id()));
+    let out = File::create(&path)?;
+    let ten = CString::new(TEN)?;
+    let head = |file: &CString, unit, count| unsafe {
+        header_head_file(file.as_ptr(), HeaderLimits { unit, count }, out.as_raw_fd())
+    };
+    assert_eq!(head(&ten, HEADER_UNIT_LINES, 2), HeaderStatus::Ok);
+    assert_eq!(head(&ten, HEADER_UNIT_BYTES, 3), HeaderStatus::Ok);
+    assert_eq!(head(&ten, 7, 1), HeaderStatus::InvalidArgument);
+    let missing = CString::new("./tests/inputs/missing.txt")?;
+    assert_eq!(head(&missing, HEADER_UNIT_LINES, 1), HeaderStatus::NotFound);
+    // fd 不会被关闭，可以继续使用
+    assert_eq!(fs::read_to_string(&path)?, "Three\nlines,\nThr");
+    drop(out);
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "current_thread")]
+async fn lines_async() -> HeaderResult<()> {
+    let mut out = Vec::new();
+    header::head_lines_async(&b"a\nb\nc\n"[..], &mut out, 2).await?;
+    assert_eq!(out, b"a\nb\n");
+    let mut out = Vec::new();
+    header::head_lines_async(&b"a\nb"[..], &mut out, 5).await?;
+    assert_eq!(out, b"a\nb");
+    Ok(())
+}
@@ -56,7 +56,7 @@ fn dies_bad_lines() -> HeaderResult<()> {
     let bad = random_string();
     let expected = format!("Failed to parse lines count: {}", &bad);
     Command::cargo_bin(PRG)?
-        .args(&["-n", &bad, EMPTY])
+        .args(["-n", &bad, EMPTY])
         .assert()
         .failure()
         .stderr(predicate::str::contains(expected));
@@ -67,11 +67,11 @@ fn dies_bad_lines() -> HeaderResult<()> {
 // --------------------------------------------------
 #[test]
 fn dies_bytes_and_lines() -> HeaderResult<()> {
-    let msg = "The argument '--lines <LINES>' cannot be \
+    let msg = "the argument '--lines <LINES>' cannot be \
                used with '--bytes <BYTES>'";
 
     Command::cargo_bin(PRG)?
-        .args(&["-n", "1", "-c", "2"])
+        .args(["-n", "1", "-c", "2"])
         .assert()
         .failure()
         .stderr(predicate::str::contains(msg));
@@ -92,37 +92,90 @@ fn skips_bad_file() -> HeaderResult<()> {
     Ok(())
 }
 
+#[test]
+fn reports_bad_file_in_order() -> HeaderResult<()> {
+    // 标准输出和标准错误写到同一个文件中，错误应当出现在前一个文件的输出之后、后一个文件之前
+    let bad = gen_bad_file();
+    let path = std::env::temp_dir().join(format!("header-order-{}", std::process::id()));
+    let output = File::create(&path)?;
+    let status = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args([ONE, &bad, TWO])
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .status()?;
+    let combined = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert_eq!(status.code(), Some(1));
+    assert_eq!(
+        combined,
+        format!(
+            "==> {} <==\nÖne line, four words.\
+             Failed to open file: {}: No such file or directory (os error 2)\n\
+             \n==> {} <==\nTwo lines.\nFour words.",
+            ONE, bad, TWO
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn reports_truncated_gzip_and_continues() -> HeaderResult<()> {
+    // 解压到一半出错只影响这个文件：已经解压的部分照常输出，错误排在它后面，后面的文件继续处理
+    let gz = fs::read("./tests/inputs/ten.txt.gz")?;
+    let truncated =
+        std::env::temp_dir().join(format!("header-truncated-{}.gz", std::process::id()));
+    fs::write(&truncated, &gz[..30])?;
+    let truncated = truncated.to_str().unwrap().to_string();
+    for jobs in ["1", "2"] {
+        let path = std::env::temp_dir().join(format!("header-order-gz-{}", std::process::id()));
+        let output = File::create(&path)?;
+        let status = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(["-j", jobs, ONE, &truncated, TWO])
+            .stdout(output.try_clone()?)
+            .stderr(output)
+            .status()?;
+        let combined = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(status.code(), Some(1));
+        let banner = combined.find(&format!("==> {} <==", truncated)).unwrap();
+        let error = combined
+            .find(&format!("{}: incomplete deflate stream\n", truncated))
+            .unwrap();
+        assert!(banner < error, "{:?}", combined);
+        assert!(
+            combined[error..].ends_with(&format!("\n==> {} <==\nTwo lines.\nFour words.", TWO)),
+            "{:?}",
+            combined
+        );
+    }
+    fs::remove_file(&truncated)?;
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> HeaderResult<()> {
-    // Extra work here due to lossy UTF
-    let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    // 输出与 GNU head 逐字节比较，截断在多字节字符中间时也不做替换
+    let expected = fs::read(expected_file)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
         .success()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected));
 
     Ok(())
 }
 
 // --------------------------------------------------
 fn run_stdin(args: &[&str], input_file: &str, expected_file: &str) -> HeaderResult<()> {
-    // Extra work here due to lossy UTF
-    let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
-    let input = fs::read_to_string(input_file)?;
+    let expected = fs::read(expected_file)?;
+    let input = fs::read(input_file)?;
 
     Command::cargo_bin(PRG)?
         .write_stdin(input)
         .args(args)
         .assert()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected));
 
     Ok(())
 }
@@ -416,3 +469,3530 @@ fn multiple_files_c4() -> HeaderResult<()> {
         "tests/expected/all.c4.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_quiet() -> HeaderResult<()> {
+    run(
+        &["-q", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.q.out",
+    )
+}
+
+#[test]
+fn multiple_files_quiet_n2() -> HeaderResult<()> {
+    run(
+        &["--quiet", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.q.n2.out",
+    )
+}
+
+#[test]
+fn multiple_files_silent_c4() -> HeaderResult<()> {
+    run(
+        &["--silent", "-c", "4", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.q.c4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_verbose() -> HeaderResult<()> {
+    run(&["-v", EMPTY], "tests/expected/empty.txt.v.out")
+}
+
+#[test]
+fn one_verbose() -> HeaderResult<()> {
+    run(&["-v", ONE], "tests/expected/one.txt.v.out")
+}
+
+#[test]
+fn ten_verbose() -> HeaderResult<()> {
+    run(&["--verbose", TEN], "tests/expected/ten.txt.v.out")
+}
+
+#[test]
+fn multiple_files_quiet_then_verbose() -> HeaderResult<()> {
+    run(
+        &["-q", "-v", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.v.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn one_n_minus2() -> HeaderResult<()> {
+    run(&[ONE, "-n", "-2"], "tests/expected/one.txt.n-2.out")
+}
+
+#[test]
+fn three_n_minus2() -> HeaderResult<()> {
+    run(&[THREE, "-n", "-2"], "tests/expected/three.txt.n-2.out")
+}
+
+#[test]
+fn three_n_minus2_stdin() -> HeaderResult<()> {
+    run_stdin(&["-n", "-2"], THREE, "tests/expected/three.txt.n-2.out")
+}
+
+#[test]
+fn ten_n_minus2_stdin() -> HeaderResult<()> {
+    run_stdin(&["--lines=-2"], TEN, "tests/expected/ten.txt.n-2.out")
+}
+
+#[test]
+fn multiple_files_n_minus2() -> HeaderResult<()> {
+    run(
+        &["-n", "-2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn one_c_minus4() -> HeaderResult<()> {
+    run(&[ONE, "-c", "-4"], "tests/expected/one.txt.c-4.out")
+}
+
+#[test]
+fn one_c_minus4_stdin() -> HeaderResult<()> {
+    run_stdin(&["-c", "-4"], ONE, "tests/expected/one.txt.c-4.out")
+}
+
+#[test]
+fn three_c_minus4() -> HeaderResult<()> {
+    run(&[THREE, "--bytes=-4"], "tests/expected/three.txt.c-4.out")
+}
+
+#[test]
+fn three_c_minus4_stdin() -> HeaderResult<()> {
+    run_stdin(&["-c", "-4"], THREE, "tests/expected/three.txt.c-4.out")
+}
+
+#[test]
+fn multiple_files_c_minus4() -> HeaderResult<()> {
+    run(
+        &["-c", "-4", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.c-4.out",
+    )
+}
+
+#[test]
+fn c_minus_regular_file_edges() -> HeaderResult<()> {
+    // 普通文件按长度直接算出要输出的字节数
+    let expected = fs::read(THREE)?;
+    for (count, len) in [("-0", expected.len()), ("-62", 0), ("-1000", 0), ("-1", 61)] {
+        Command::cargo_bin(PRG)?
+            .args(["-c", count, THREE])
+            .assert()
+            .success()
+            .stdout(expected[..len].to_vec());
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn size_suffix_bytes() -> HeaderResult<()> {
+    let input = "x".repeat(3000);
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1K"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..1024]));
+    Command::cargo_bin(PRG)?
+        .args(["-c", "2kB"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..2000]));
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1KiB"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..1024]));
+    Ok(())
+}
+
+#[test]
+fn size_suffix_lines() -> HeaderResult<()> {
+    let input: String = (1..=1500).map(|i| format!("{}\n", i)).collect();
+    let expected: String = (1..=1024).map(|i| format!("{}\n", i)).collect();
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1k"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn dies_bad_suffix() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "4X", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to parse bytes count: 4X: invalid suffix 'X'",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "-n", "2"])
+        .write_stdin("a\nb\0c\0d\0")
+        .assert()
+        .success()
+        .stdout("a\nb\0c\0");
+    Ok(())
+}
+
+#[test]
+fn zero_terminated_all_but_last() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--zero-terminated", "-n", "-1"])
+        .write_stdin("a\0b\0c\0")
+        .assert()
+        .success()
+        .stdout("a\0b\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn obsolete_num_syntax() -> HeaderResult<()> {
+    run(&["-2", TEN], "tests/expected/ten.txt.n2.out")
+}
+
+#[test]
+fn obsolete_num_syntax_multiple_files() -> HeaderResult<()> {
+    run(
+        &["-4", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n4.out",
+    )
+}
+
+#[test]
+fn obsolete_num_syntax_stdin() -> HeaderResult<()> {
+    run_stdin(&["-2"], THREE, "tests/expected/three.txt.n2.out")
+}
+
+#[test]
+fn obsolete_num_syntax_only_first_argument() -> HeaderResult<()> {
+    // 只改写第一个参数，其他位置的 -NUM 仍然是未知选项
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-5", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unexpected argument '-5'"));
+    // `--` 之后的 -NUM 是文件名
+    let bad = format!("-{}", rand::thread_rng().gen_range(100_000..1_000_000));
+    Command::cargo_bin(PRG)?
+        .args(["--", &bad])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Failed to open file: {}",
+            bad
+        )));
+    Ok(())
+}
+
+#[test]
+fn plus_num_counts() -> HeaderResult<()> {
+    // 带 '+' 的计数与不带时相同
+    run(&["-n", "+2", TEN], "tests/expected/ten.txt.n2.out")?;
+    run(&["-c", "+4", TEN], "tests/expected/ten.txt.c4.out")?;
+    run(&["--lines=+2", TEN], "tests/expected/ten.txt.n2.out")
+}
+
+#[test]
+fn flags_override_env_defaults() -> HeaderResult<()> {
+    let expected = |file: &str| -> HeaderResult<String> { Ok(String::from_utf8(fs::read(file)?)?) };
+    // 旧式 -NUM 和另一种计数方式同样覆盖环境变量给出的默认值
+    for (name, args, file) in [
+        ("HEADER_LINES", &["-4"][..], "tests/expected/ten.txt.n4.out"),
+        (
+            "HEADER_LINES",
+            &["-c", "4"],
+            "tests/expected/ten.txt.c4.out",
+        ),
+        (
+            "HEADER_LINES",
+            &["-n", "-2"],
+            "tests/expected/ten.txt.n-2.out",
+        ),
+        ("HEADER_BYTES", &["-4"], "tests/expected/ten.txt.n4.out"),
+        (
+            "HEADER_BYTES",
+            &["-c", "-4"],
+            "tests/expected/ten.txt.c-4.out",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .env(name, "2")
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .success()
+            .stdout(expected(file)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn dies_conflicting_flags() -> HeaderResult<()> {
+    for (args, expected) in [
+        (
+            &["-n", "1", "-c", "2"][..],
+            "the argument '--lines <LINES>' cannot be used with '--bytes <BYTES>'",
+        ),
+        (
+            &["-2", "-c", "2"],
+            "the argument '--lines <LINES>' cannot be used with '--bytes <BYTES>'",
+        ),
+        (
+            &["-z", "--delimiter", "x"],
+            "the argument '--zero-terminated' cannot be used with '--delimiter <STRING>'",
+        ),
+        (
+            &["--files-from", "-", "-0", "--delimiter", "x", "-c", "1"],
+            "the argument '--delimiter <STRING>' cannot be used with '--bytes <BYTES>'",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .failure()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(expected));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bad_file_exit_status() -> HeaderResult<()> {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args([ONE, &bad])
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("Öne line, four words."));
+    Ok(())
+}
+
+#[test]
+fn fail_fast() -> HeaderResult<()> {
+    let bad = gen_bad_file();
+    let expected = format!("Failed to open file: {}", &bad);
+    Command::cargo_bin(PRG)?
+        .args(["--fail-fast", &bad, ONE])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(expected));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_n0() -> HeaderResult<()> {
+    run(&[TEN, "-n", "0"], "tests/expected/empty.txt.out")
+}
+
+#[test]
+fn ten_c0_stdin() -> HeaderResult<()> {
+    run_stdin(&["-c", "0"], TEN, "tests/expected/empty.txt.out")
+}
+
+#[test]
+fn multiple_files_n0() -> HeaderResult<()> {
+    run(
+        &["-n", "0", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n0.out",
+    )
+}
+
+#[test]
+fn multiple_files_c0() -> HeaderResult<()> {
+    run(
+        &["-c", "0", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.c0.out",
+    )
+}
+
+#[test]
+fn dies_double_negative_lines() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--2", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse lines count: --2"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn one_tail() -> HeaderResult<()> {
+    run(&["--tail", ONE], "tests/expected/one.txt.tail.out")
+}
+
+#[test]
+fn three_tail() -> HeaderResult<()> {
+    run(&["-t", THREE], "tests/expected/three.txt.tail.out")
+}
+
+#[test]
+fn three_tail_n2() -> HeaderResult<()> {
+    run(
+        &["-t", "-n", "2", THREE],
+        "tests/expected/three.txt.tail.n2.out",
+    )
+}
+
+#[test]
+fn three_tail_n2_stdin() -> HeaderResult<()> {
+    run_stdin(
+        &["-t", "-n", "2"],
+        THREE,
+        "tests/expected/three.txt.tail.n2.out",
+    )
+}
+
+#[test]
+fn ten_tail_n2() -> HeaderResult<()> {
+    run(
+        &["-t", "-n", "2", TEN],
+        "tests/expected/ten.txt.tail.n2.out",
+    )
+}
+
+#[test]
+fn ten_tail_n2_stdin() -> HeaderResult<()> {
+    run_stdin(
+        &["-t", "-n", "2"],
+        TEN,
+        "tests/expected/ten.txt.tail.n2.out",
+    )
+}
+
+#[test]
+fn three_tail_n_minus2() -> HeaderResult<()> {
+    run(
+        &["-t", "-n", "-2", THREE],
+        "tests/expected/three.txt.tail.n-2.out",
+    )
+}
+
+#[test]
+fn multiple_files_tail() -> HeaderResult<()> {
+    run(
+        &["-t", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.tail.out",
+    )
+}
+
+#[test]
+fn multiple_files_tail_n2() -> HeaderResult<()> {
+    run(
+        &["-t", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.tail.n2.out",
+    )
+}
+
+#[test]
+fn tail_large_file() -> HeaderResult<()> {
+    // 超过一个查找块的文件，验证跨块向前查找
+    let input: String = (1..=5000).map(|i| format!("line {}\n", i)).collect();
+    let path = std::env::temp_dir().join(format!("header-tail-{}", random_string()));
+    fs::write(&path, &input)?;
+    let result = Command::cargo_bin(PRG)?
+        .args(["-t", "-n", "3"])
+        .arg(&path)
+        .assert()
+        .try_success()
+        .map(|assert| assert.get_output().stdout.clone());
+    fs::remove_file(&path)?;
+    assert_eq!(result?, b"line 4998\nline 4999\nline 5000\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_appended_data() -> HeaderResult<()> {
+    use std::{
+        io::Write,
+        process::{Command as StdCommand, Stdio},
+        thread,
+        time::Duration,
+    };
+
+    // 追加的内容与开头的输出一样经过 --eol 等格式化
+    for (args, expected) in [
+        (&["-f", "-n", "1"][..], "one\nthree\n"),
+        (&["-f", "-n", "1", "--eol", "crlf"], "one\r\nthree\r\n"),
+    ] {
+        let path = std::env::temp_dir().join(format!("header-follow-{}", random_string()));
+        fs::write(&path, "one\ntwo\n")?;
+        let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(args)
+            .arg(&path)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        thread::sleep(Duration::from_millis(500));
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"three\n")?;
+        thread::sleep(Duration::from_millis(800));
+        child.kill()?;
+        let output = child.wait_with_output()?;
+        fs::remove_file(&path)?;
+        assert_eq!(String::from_utf8(output.stdout)?, expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_line_range() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "5:8", THREE])
+        .assert()
+        .success()
+        .stdout("five\nsix\nseven\neight\n");
+    Ok(())
+}
+
+#[test]
+fn three_line_range_open_end_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--lines", "11:"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("eleven\ntwelve");
+    Ok(())
+}
+
+#[test]
+fn three_line_range_open_start() -> HeaderResult<()> {
+    run(&["-n", ":2", THREE], "tests/expected/three.txt.n2.out")
+}
+
+#[test]
+fn dies_bad_line_range() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "8:5", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to parse lines count: 8:5: invalid range: 8:5",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_skip() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "3", "-n", "2", THREE])
+        .assert()
+        .success()
+        .stdout("four\nfive\n");
+    Ok(())
+}
+
+#[test]
+fn three_skip_past_end_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "20"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_skip() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--skip", "x", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse skip count: x"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_step() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--step", "3", "-n", "3", THREE])
+        .assert()
+        .success()
+        .stdout("one\nfour\nseven\n");
+    Ok(())
+}
+
+#[test]
+fn three_step_range_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--step", "2", "-n", "2:7"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("two\nfour\nsix\n");
+    Ok(())
+}
+
+#[test]
+fn dies_zero_step() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--step", "0", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse step: 0"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sample_more_than_available() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--sample", "100", THREE])
+        .assert()
+        .success()
+        .stdout(fs::read_to_string(THREE)?);
+    Ok(())
+}
+
+#[test]
+fn sample_keeps_original_order() -> HeaderResult<()> {
+    let input: String = (1..=1000).map(|i| format!("{}\n", i)).collect();
+    let output = Command::cargo_bin(PRG)?
+        .args(["--sample", "5"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let numbers = String::from_utf8(output)?
+        .lines()
+        .map(|line| line.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(numbers.len(), 5);
+    assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(numbers.iter().all(|n| (1..=1000).contains(n)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_until() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^th", THREE])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\n");
+    Ok(())
+}
+
+#[test]
+fn three_until_exclusive_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^th", "--until-exclusive"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    Ok(())
+}
+
+#[test]
+fn three_until_not_limited_by_default_lines() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^twelve$", THREE])
+        .assert()
+        .success()
+        .stdout(fs::read_to_string(THREE)?);
+    Ok(())
+}
+
+#[test]
+fn three_until_with_lines_limit() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^twelve$", "-n", "2", THREE])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    Ok(())
+}
+
+#[test]
+fn dies_until_with_line_range() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "^twelve$", "-n", "2:3", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--until only supports a plain line count",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_until_pattern() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "(", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --until pattern: ("));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_from() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--from", "^f", "-n", "3", THREE])
+        .assert()
+        .success()
+        .stdout("four\nfive\nsix\n");
+    Ok(())
+}
+
+#[test]
+fn three_from_until_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--from", "^six$", "--until", "^eight$"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("six\nseven\neight\n");
+    Ok(())
+}
+
+#[test]
+fn three_from_no_match() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--from", "^nothing$", THREE])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_words() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-w", "3", TEN])
+        .assert()
+        .success()
+        .stdout("Three\nlines,\nfour");
+    Ok(())
+}
+
+#[test]
+fn words_preserve_whitespace_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--words", "2"])
+        .write_stdin("  alpha \t beta\n\ngamma delta")
+        .assert()
+        .success()
+        .stdout("  alpha \t beta");
+    Ok(())
+}
+
+#[test]
+fn words_more_than_available() -> HeaderResult<()> {
+    run(&["-w", "100", ONE], "tests/expected/one.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn one_chars() -> HeaderResult<()> {
+    // “Ö” 占两个字节，但只算一个字符
+    Command::cargo_bin(PRG)?
+        .args(["-m", "3", ONE])
+        .assert()
+        .success()
+        .stdout("Öne");
+    Ok(())
+}
+
+#[test]
+fn chars_replace_invalid_utf8() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--chars", "3"])
+        .write_stdin(&b"a\xffbc"[..])
+        .assert()
+        .success()
+        .stdout("a\u{FFFD}b");
+    Ok(())
+}
+
+#[test]
+fn chars_strict_utf8() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "3", "--strict-utf8"])
+        .write_stdin(&b"a\xffbc"[..])
+        .assert()
+        .failure()
+        .stdout("a")
+        .stderr(predicate::str::contains(
+            "invalid UTF-8 sequence at byte offset 1",
+        ));
+    Ok(())
+}
+
+#[test]
+fn chars_truncated_sequence_at_eof() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "5"])
+        .write_stdin(&b"ab\xe4\xb8"[..])
+        .assert()
+        .success()
+        .stdout("ab\u{FFFD}");
+    Ok(())
+}
+
+#[test]
+fn chars_across_buffer_boundary() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "9000"])
+        .write_stdin("é".repeat(10000))
+        .assert()
+        .success()
+        .stdout("é".repeat(9000));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn graphemes_keep_clusters_together() -> HeaderResult<()> {
+    // “e\u{301}” 与 “👍🏽” 各是一个字素簇
+    Command::cargo_bin(PRG)?
+        .args(["--graphemes", "3"])
+        .write_stdin("e\u{301}👍🏽x\ny")
+        .assert()
+        .success()
+        .stdout("e\u{301}👍🏽x");
+    Ok(())
+}
+
+#[test]
+fn graphemes_span_lines() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--graphemes", "9", TEN])
+        .assert()
+        .success()
+        .stdout("Three\nlin");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn paragraphs() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--paragraphs", "2"])
+        .write_stdin("\nfirst\nstill first\n\n \nsecond\n\n\nthird\n")
+        .assert()
+        .success()
+        .stdout("\nfirst\nstill first\n\n \nsecond\n");
+    Ok(())
+}
+
+#[test]
+fn paragraphs_more_than_available() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--paragraphs", "5"])
+        .write_stdin("only\n\n\n")
+        .assert()
+        .success()
+        .stdout("only\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn custom_delimiter() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--delimiter", ";", "-n", "2"])
+        .write_stdin("select 1;select 2;\nselect 3;")
+        .assert()
+        .success()
+        .stdout("select 1;select 2;");
+    Ok(())
+}
+
+#[test]
+fn multi_byte_delimiter() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--delimiter", "---\\n", "-n", "2"])
+        .write_stdin("a: 1\n---\nb: -2\n--\n---\nc: 3\n")
+        .assert()
+        .success()
+        .stdout("a: 1\n---\nb: -2\n--\n---\n");
+    Ok(())
+}
+
+#[test]
+fn multi_byte_delimiter_tail() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--delimiter", "::", "-t", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout(fs::read_to_string(TEN)?);
+    Ok(())
+}
+
+#[test]
+fn dies_empty_delimiter() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--delimiter", "", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Record delimiter must not be empty",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_percent_lines() -> HeaderResult<()> {
+    // three.txt 共 62 字节，10% 为 6 字节，对齐到行尾后输出两行
+    Command::cargo_bin(PRG)?
+        .args(["-n", "10%", THREE])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    Ok(())
+}
+
+#[test]
+fn three_percent_bytes() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "10%", THREE])
+        .assert()
+        .success()
+        .stdout("one\ntw");
+    Ok(())
+}
+
+#[test]
+fn three_percent_tail() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-t", "-n", "25%", THREE])
+        .assert()
+        .success()
+        .stdout("eleven\ntwelve");
+    Ok(())
+}
+
+#[test]
+fn dies_percent_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "10%"])
+        .write_stdin("a\nb\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "-: percentage counts require a regular file",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_bad_percent() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "101%", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid percentage: 101%"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn three_byte_range() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "5-11", THREE])
+        .assert()
+        .success()
+        .stdout("two\nthr");
+    Ok(())
+}
+
+#[test]
+fn three_byte_range_stdin() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--bytes", "5-11"])
+        .write_stdin(fs::read_to_string(THREE)?)
+        .assert()
+        .success()
+        .stdout("two\nthr");
+    Ok(())
+}
+
+#[test]
+fn three_byte_range_open_end() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "57-", THREE])
+        .assert()
+        .success()
+        .stdout("twelve");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_byte_range() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "0-4", THREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid range: 0-4"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_keeps_header_and_quoted_newlines() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--csv", "-n", "2"])
+        .write_stdin("id,note\n1,\"multi\nline\"\n2,\"say \"\"hi\"\"\"\n3,plain\n")
+        .assert()
+        .success()
+        .stdout("id,note\n1,\"multi\nline\"\n2,\"say \"\"hi\"\"\"\n");
+    Ok(())
+}
+
+#[test]
+fn csv_header_only() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--csv", "-n", "0"])
+        .write_stdin("a,b\r\n1,2\r\n")
+        .assert()
+        .success()
+        .stdout("a,b\r\n");
+    Ok(())
+}
+
+#[test]
+fn csv_large_input() -> HeaderResult<()> {
+    let input: String = std::iter::once("n,square\n".to_string())
+        .chain((1..=5000).map(|i| format!("{},{}\n", i, i * i)))
+        .collect();
+    Command::cargo_bin(PRG)?
+        .args(["--csv", "-n", "3"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("n,square\n1,1\n2,4\n3,9\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ndjson_records() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ndjson", "-n", "2"])
+        .write_stdin("{\"a\":1}\n\n[1, 2]\n{\"b\":\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":1}\n[1, 2]\n");
+    Ok(())
+}
+
+#[test]
+fn ndjson_stops_at_invalid_record() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ndjson"])
+        .write_stdin("{\"a\":1}\n{\"b\":\n{\"c\":3}\n")
+        .assert()
+        .failure()
+        .stdout("{\"a\":1}\n")
+        .stderr(predicate::str::contains("invalid JSON record on line 2"));
+    Ok(())
+}
+
+#[test]
+fn ndjson_pretty() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ndjson", "--pretty", "-n", "1"])
+        .write_stdin("{\"a\":[1]}")
+        .assert()
+        .success()
+        .stdout("{\n  \"a\": [\n    1\n  ]\n}\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn record_start_keeps_stack_traces() -> HeaderResult<()> {
+    let log = "\
+2024-01-01 10:00:00 INFO start
+2024-01-01 10:00:01 ERROR boom
+java.lang.IllegalStateException: boom
+    at App.main(App.java:3)
+2024-01-01 10:00:02 INFO done
+";
+    Command::cargo_bin(PRG)?
+        .args(["--record-start", r"^\d{4}-\d{2}-\d{2} ", "-n", "2"])
+        .write_stdin(log)
+        .assert()
+        .success()
+        .stdout(
+            "\
+2024-01-01 10:00:00 INFO start
+2024-01-01 10:00:01 ERROR boom
+java.lang.IllegalStateException: boom
+    at App.main(App.java:3)
+",
+        );
+    Ok(())
+}
+
+#[test]
+fn record_start_preamble_is_a_record() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--record-start", "^#", "-n", "2"])
+        .write_stdin("intro\nmore\n# one\nbody\n# two\n")
+        .assert()
+        .success()
+        .stdout("intro\nmore\n# one\nbody\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn gzip_by_extension() -> HeaderResult<()> {
+    run(&["./tests/inputs/ten.txt.gz"], "tests/expected/ten.txt.out")
+}
+
+#[test]
+fn gzip_by_magic() -> HeaderResult<()> {
+    run(
+        &["-n", "2", "./tests/inputs/ten.gzdata"],
+        "tests/expected/ten.txt.n2.out",
+    )
+}
+
+#[test]
+fn gzip_on_stdin() -> HeaderResult<()> {
+    let input = fs::read("./tests/inputs/ten.txt.gz")?;
+    let expected = fs::read("tests/expected/ten.txt.c4.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["-c", "4"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_by_extension() -> HeaderResult<()> {
+    run(
+        &["./tests/inputs/ten.txt.zst"],
+        "tests/expected/ten.txt.out",
+    )
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_by_magic() -> HeaderResult<()> {
+    run(
+        &["-n", "2", "./tests/inputs/ten.zstdata"],
+        "tests/expected/ten.txt.n2.out",
+    )
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn xz_by_extension() -> HeaderResult<()> {
+    run(&["./tests/inputs/ten.txt.xz"], "tests/expected/ten.txt.out")
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn bzip2_by_extension() -> HeaderResult<()> {
+    run(
+        &["-c", "4", "./tests/inputs/ten.txt.bz2"],
+        "tests/expected/ten.txt.c4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_list() -> HeaderResult<()> {
+    let list = format!("{}\n\n{}\n", ONE, TWO);
+    let expected = Command::cargo_bin(PRG)?.args([ONE, TWO]).output()?.stdout;
+    let dir = std::env::temp_dir().join(format!("header-files-from-{}", random_string()));
+    fs::write(&dir, list)?;
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", dir.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(expected);
+    fs::remove_file(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn files_from_stdin_nul() -> HeaderResult<()> {
+    let expected = Command::cargo_bin(PRG)?
+        .args(["-n", "2", ONE, TWO, THREE])
+        .output()?
+        .stdout;
+    Command::cargo_bin(PRG)?
+        .args(["-0", "--files-from", "-", "-n", "2", ONE])
+        .write_stdin(format!("{}\0{}\0", TWO, THREE))
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn files_from_then_obsolete_num() -> HeaderResult<()> {
+    // --files-from 的值之后的 -NUM 仍然是行数，-0 仍然是 --null
+    let expected = Command::cargo_bin(PRG)?
+        .args(["-n", "1", ONE, TWO, THREE])
+        .output()?
+        .stdout;
+    for args in [
+        &["--files-from", "-", "-1", ONE][..],
+        &["--files-from=-", "-1", ONE],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin(format!("{}\n{}\n", TWO, THREE))
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "-0", "-n", "1", ONE])
+        .write_stdin(format!("{}\0{}\0", TWO, THREE))
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn files_from_missing_list() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "no-such-list.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to open file list: no-such-list.txt",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn glob_expansion() -> HeaderResult<()> {
+    let expected = Command::cargo_bin(PRG)?
+        .args([
+            "-n",
+            "3",
+            "tests/inputs/ten.txt",
+            "tests/inputs/three.txt",
+            "tests/inputs/two.txt",
+        ])
+        .output()?
+        .stdout;
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/t*.txt", "-n", "3"])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn glob_without_match() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["./tests/inputs/*.nothing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "No files match pattern: ./tests/inputs/*.nothing",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_directory() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-recursive-{}", random_string()));
+    fs::create_dir_all(dir.join("b/c"))?;
+    fs::write(dir.join("b/c/z.txt"), "zzz\n")?;
+    fs::write(dir.join("b/a.txt"), "aaa\n")?;
+    fs::write(dir.join("top.txt"), "top\n")?;
+    let root = dir.to_str().unwrap();
+    let expected = format!(
+        "==> {root}/b/a.txt <==\naaa\n\n==> {root}/b/c/z.txt <==\nzzz\n\n==> {root}/top.txt <==\ntop\n"
+    );
+    Command::cargo_bin(PRG)?
+        .args(["-R", root])
+        .assert()
+        .success()
+        .stdout(expected);
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+// 启动只处理一个请求的 HTTP 服务器，按 Range 请求头返回内容，并把请求头交给调用者
+#[cfg(feature = "http")]
+fn serve_once(body: &'static [u8]) -> (String, std::thread::JoinHandle<String>) {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+            request.push_str(&line);
+        }
+        let range = request
+            .lines()
+            .find_map(|line| line.strip_prefix("Range: bytes=0-"))
+            .map(|end| end.trim().parse::<usize>().unwrap() + 1);
+        let (status, body) = match range {
+            Some(len) => ("206 Partial Content", &body[..len.min(body.len())]),
+            None => ("200 OK", body),
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        request
+    });
+    (url, handle)
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_bytes_use_range() -> HeaderResult<()> {
+    let (url, server) = serve_once(b"0123456789abcdef\n");
+    Command::cargo_bin(PRG)?
+        .args(["-c", "4", &url])
+        .assert()
+        .success()
+        .stdout("0123");
+    let request = server.join().unwrap();
+    assert!(request.contains("Range: bytes=0-3"));
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_lines_stream() -> HeaderResult<()> {
+    let (url, server) = serve_once(b"one\ntwo\nthree\n");
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", &url])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    let request = server.join().unwrap();
+    assert!(!request.contains("Range"));
+    Ok(())
+}
+
+#[test]
+fn http_unreachable() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["http://127.0.0.1:1/missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to open URL: http://127.0.0.1:1/missing",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "s3")]
+#[test]
+fn s3_ranged_get() -> HeaderResult<()> {
+    let (url, server) = serve_once(b"0123456789abcdef\n");
+    let endpoint = url.trim_end_matches("/file.txt");
+    Command::cargo_bin(PRG)?
+        .env("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE")
+        .env("AWS_SECRET_ACCESS_KEY", "secret")
+        .env("AWS_REGION", "eu-west-1")
+        .env("AWS_ENDPOINT_URL", endpoint)
+        .env_remove("AWS_SESSION_TOKEN")
+        .args(["-c", "4", "s3://bucket/logs/app.log"])
+        .assert()
+        .success()
+        .stdout("0123");
+    let request = server.join().unwrap();
+    assert!(request.starts_with("GET /bucket/logs/app.log HTTP/1.1"));
+    assert!(request.contains("Range: bytes=0-3"));
+    assert!(request.contains("Credential=AKIDEXAMPLE/"));
+    assert!(request.contains("/eu-west-1/s3/aws4_request"));
+    Ok(())
+}
+
+#[cfg(feature = "s3")]
+#[test]
+fn s3_missing_credentials() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .args(["s3://bucket/key"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to open S3 object: s3://bucket/key: AWS_ACCESS_KEY_ID is not set",
+        ));
+    Ok(())
+}
+
+#[test]
+fn s3_invalid_location() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["s3://bucket-only"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to open S3 object: s3://bucket-only",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tar_member() -> HeaderResult<()> {
+    run(
+        &["-n", "2", "tests/inputs/archive.tar::ten.txt"],
+        "tests/expected/ten.txt.n2.out",
+    )
+}
+
+#[test]
+fn tar_gz_member() -> HeaderResult<()> {
+    run(
+        &["tests/inputs/archive.tar.gz::logs/three.txt"],
+        "tests/expected/three.txt.out",
+    )
+}
+
+#[test]
+fn tar_compressed_member() -> HeaderResult<()> {
+    run(
+        &["-c", "4", "tests/inputs/archive.tar::logs/ten.txt.gz"],
+        "tests/expected/ten.txt.c4.out",
+    )
+}
+
+#[test]
+fn archive_member_option() -> HeaderResult<()> {
+    let expected = Command::cargo_bin(PRG)?
+        .args([
+            "tests/inputs/archive.tar::ten.txt",
+            "tests/inputs/archive.tar.gz::ten.txt",
+        ])
+        .output()?
+        .stdout;
+    Command::cargo_bin(PRG)?
+        .args([
+            "--archive-member",
+            "ten.txt",
+            "tests/inputs/archive.tar",
+            "tests/inputs/archive.tar.gz",
+        ])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn tar_missing_member() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/archive.tar::nope.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no member nope.txt in archive tests/inputs/archive.tar",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zip_deflated_member() -> HeaderResult<()> {
+    run(
+        &["-n", "2", "tests/inputs/archive.zip::ten.txt"],
+        "tests/expected/ten.txt.n2.out",
+    )
+}
+
+#[test]
+fn zip_stored_member() -> HeaderResult<()> {
+    run(
+        &["tests/inputs/archive.zip::logs/three.txt"],
+        "tests/expected/three.txt.out",
+    )
+}
+
+#[test]
+fn zip_by_magic() -> HeaderResult<()> {
+    run(
+        &["-c", "4", "tests/inputs/archive.zipdata::ten.txt"],
+        "tests/expected/ten.txt.c4.out",
+    )
+}
+
+#[test]
+fn zip_missing_member() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/archive.zip::nope.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no member nope.txt in archive tests/inputs/archive.zip",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+// 文件名不是合法的 UTF-8 时按原样的字节打开
+#[cfg(target_os = "linux")]
+#[test]
+fn non_utf8_file_name() -> HeaderResult<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = std::env::temp_dir().join(format!("header-non-utf8-{}", random_string()));
+    fs::create_dir_all(&dir)?;
+    let file = dir.join(OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::write(&file, "a\nb\n")?;
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("a\n");
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-R"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+    let mut list = file.as_os_str().as_bytes().to_vec();
+    list.push(0);
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-0", "--files-from", "-"])
+        .write_stdin(list)
+        .assert()
+        .success()
+        .stdout("a\n");
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+// 建立含有各种符号链接的目录树：文件链接、目录链接、指向自身的循环链接和悬空链接
+#[cfg(unix)]
+fn symlink_tree() -> HeaderResult<std::path::PathBuf> {
+    use std::os::unix::fs::symlink;
+
+    let dir = std::env::temp_dir().join(format!("header-symlinks-{}", random_string()));
+    fs::create_dir_all(dir.join("sub"))?;
+    fs::write(dir.join("a.txt"), "a\n")?;
+    fs::write(dir.join("sub/b.txt"), "b\n")?;
+    symlink(dir.join("a.txt"), dir.join("link_file"))?;
+    symlink(dir.join("sub"), dir.join("link_dir"))?;
+    symlink(&dir, dir.join("loop"))?;
+    symlink(dir.join("missing"), dir.join("dangling"))?;
+    Ok(dir)
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_symlinks_default() -> HeaderResult<()> {
+    let dir = symlink_tree()?;
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-R", dir.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stdout("a\na\nb\n")
+        .stderr(predicate::str::contains("dangling"));
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_follow_symlinks() -> HeaderResult<()> {
+    let dir = symlink_tree()?;
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-R", "--follow-symlinks", "--skip-dangling"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout("a\nb\na\nb\n");
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_no_follow() -> HeaderResult<()> {
+    let dir = symlink_tree()?;
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-R", "--no-follow", "--skip-dangling"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+    // 不跟随链接时悬空链接同样被忽略，不必再给出 --skip-dangling
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-R", "--no-follow"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout("a\nb\n")
+        .stderr("");
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_binary() -> HeaderResult<()> {
+    let bin = std::env::temp_dir().join(format!("header-binary-{}", random_string()));
+    fs::write(&bin, b"\x7fELF\x02\x01\x01\x00\x00\x00garbage\n")?;
+    let bin = bin.to_str().unwrap().to_string();
+    Command::cargo_bin(PRG)?
+        .args(["--skip-binary", ONE, &bin])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\nÖne line, four words.\n==> {} <==\n<binary file skipped>\n",
+            ONE, bin
+        ));
+    Command::cargo_bin(PRG)?
+        .args(["--skip-binary", "--binary", "-c", "4", &bin])
+        .assert()
+        .success()
+        .stdout(&b"\x7fELF"[..]);
+    fs::remove_file(&bin)?;
+    Ok(())
+}
+
+#[test]
+fn skip_binary_invalid_utf8() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--skip-binary"])
+        .write_stdin(&b"\xff\xfe\xfd\xfc text"[..])
+        .assert()
+        .success()
+        .stdout("<binary file skipped>\n");
+    Ok(())
+}
+
+#[test]
+fn skip_binary_allows_nul_records() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--skip-binary", "-z", "-n", "1"])
+        .write_stdin("one\0two\0")
+        .assert()
+        .success()
+        .stdout("one\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_banner() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-v"])
+        .write_stdin("data\n")
+        .assert()
+        .success()
+        .stdout("==> standard input <==\ndata\n");
+    Ok(())
+}
+
+#[test]
+fn stdin_label() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--label", "my-stream", "-", ONE])
+        .write_stdin("data\n")
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> my-stream <==\ndata\n\n==> {} <==\nÖne line, four words.",
+            ONE
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+fn make_fifo() -> HeaderResult<std::path::PathBuf> {
+    let fifo = std::env::temp_dir().join(format!("header-fifo-{}", random_string()));
+    let status = std::process::Command::new("mkfifo").arg(&fifo).status()?;
+    assert!(status.success());
+    Ok(fifo)
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_open_timeout() -> HeaderResult<()> {
+    let fifo = make_fifo()?;
+    Command::cargo_bin(PRG)?
+        .args(["--open-timeout", "0.2"])
+        .arg(&fifo)
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "timed out after 200ms waiting for a writer",
+        ));
+    fs::remove_file(&fifo)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_with_writer() -> HeaderResult<()> {
+    let fifo = make_fifo()?;
+    let path = fifo.clone();
+    let writer = std::thread::spawn(move || fs::write(path, "one\ntwo\n"));
+    Command::cargo_bin(PRG)?
+        .args(["--open-timeout", "10", "-n", "1"])
+        .arg(&fifo)
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("one\n");
+    let _ = writer.join();
+    fs::remove_file(&fifo)?;
+    Ok(())
+}
+
+#[test]
+fn bad_open_timeout() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--open-timeout", "soon", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to parse open timeout: soon",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tcp_input() -> HeaderResult<()> {
+    use std::io::Write;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("tcp://{}", listener.local_addr()?);
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(b"220 ready\n250 ok\n221 bye\n");
+    });
+    Command::cargo_bin(PRG)?
+        .args(["-n", "2", &address])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("220 ready\n250 ok\n");
+    server.join().unwrap();
+    Ok(())
+}
+
+#[test]
+fn tcp_read_timeout() -> HeaderResult<()> {
+    use std::io::Write;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("tcp://{}", listener.local_addr()?);
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(b"hello\n");
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    });
+    Command::cargo_bin(PRG)?
+        .args(["--read-timeout", "0.3", &address])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .failure()
+        .stdout("hello\n")
+        .stderr(predicate::str::contains("no data received for 300ms"));
+    server.join().unwrap();
+    Ok(())
+}
+
+#[test]
+fn tcp_connection_refused() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["tcp://127.0.0.1:1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed to connect: tcp://127.0.0.1:1",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn encoding_gbk() -> HeaderResult<()> {
+    let gbk: &[u8] = b"\xd6\xd0\xce\xc4\n\xb5\xda\xb6\xfe\xd0\xd0\n\xb5\xda\xc8\xfd\xd0\xd0\n";
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "gbk", "-n", "2"])
+        .write_stdin(gbk)
+        .assert()
+        .success()
+        .stdout("中文\n第二行\n");
+    Ok(())
+}
+
+#[test]
+fn encoding_utf16le_bytes() -> HeaderResult<()> {
+    let utf16: Vec<u8> = "héllo\nworld\n"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "utf-16le", "-c", "3"])
+        .write_stdin(utf16)
+        .assert()
+        .success()
+        .stdout("h\u{e9}");
+    Ok(())
+}
+
+#[test]
+fn encoding_latin1() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "latin1"])
+        .write_stdin(&b"caf\xe9\n"[..])
+        .assert()
+        .success()
+        .stdout("café\n");
+    Ok(())
+}
+
+#[test]
+fn unknown_encoding() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "klingon", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown encoding: klingon"));
+    Ok(())
+}
+
+#[test]
+fn list_encodings() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--list-encodings"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\nGBK\n"))
+        .stdout(predicate::str::contains("\nUTF-16LE\n"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn generate_completions() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "bash"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("_header() {"))
+        .stdout(predicate::str::contains("complete -F _header"));
+    // FILE 补全文件名，-n 的值不补全文件名
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "zsh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("'*::files -- Input file(s)"))
+        .stdout(predicate::str::contains(
+            "\\[env\\: HEADER_LINES\\]]:LINES:'",
+        ));
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "fish"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-s o -l output-file"));
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "powershell"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Register-ArgumentCompleter"));
+    Ok(())
+}
+
+#[test]
+fn dies_generate_completions_with_other_args() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "bash", "-n", "3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Command::cargo_bin(PRG)?
+        .args(["--generate-completions", "tcsh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'tcsh'"));
+    Ok(())
+}
+
+#[test]
+fn help_hides_generate_completions() -> HeaderResult<()> {
+    let output = Command::cargo_bin(PRG)?.arg("--help").output()?;
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stdout)?.contains("--generate-completions"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn keeps_bom_by_default() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1"])
+        .write_stdin("\u{feff}id,name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("\u{feff}id,name\n");
+    Ok(())
+}
+
+#[test]
+fn strip_utf8_bom() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "-n", "1"])
+        .write_stdin("\u{feff}id,name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("id,name\n");
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "--keep-bom", "-c", "3"])
+        .write_stdin("\u{feff}id,name\n")
+        .assert()
+        .success()
+        .stdout("\u{feff}");
+    Ok(())
+}
+
+#[test]
+fn strip_utf16_bom_with_encoding() -> HeaderResult<()> {
+    let utf16: Vec<u8> = "\u{feff}a\nb\n"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    Command::cargo_bin(PRG)?
+        .args(["--strip-bom", "--encoding", "utf-16le", "-n", "1"])
+        .write_stdin(utf16)
+        .assert()
+        .success()
+        .stdout("a\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn number_lines() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-N", "-n", "3"])
+        .write_stdin("a\n\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("     1\ta\n     2\t\n     3\tb\n");
+    Ok(())
+}
+
+#[test]
+fn number_nonblank() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--number-nonblank", "--number-width", "2"])
+        .write_stdin("a\n\nb\n")
+        .assert()
+        .success()
+        .stdout(" 1\ta\n\n 2\tb\n");
+    Ok(())
+}
+
+#[test]
+fn number_resets_per_file() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-N", "--number-width", "1", "-n", "2", TWO, THREE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\n1\tTwo lines.\n2\tFour words.\n==> {} <==\n1\tone\n2\ttwo\n",
+            TWO, THREE
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn offsets_decimal() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--offsets", "-n", "2"])
+        .write_stdin("ab\ncd\nef\n")
+        .assert()
+        .success()
+        .stdout("0:ab\n3:cd\n");
+    Ok(())
+}
+
+#[test]
+fn offsets_hex_after_skip_and_step() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--offsets=hex", "--skip", "1", "--step", "2"])
+        .write_stdin("line0\nline1\nline2\nline3\n")
+        .assert()
+        .success()
+        .stdout("0x6:line1\n0x12:line3\n");
+    Ok(())
+}
+
+#[test]
+fn offsets_from_match() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--offsets", "--from", "^start", "-n", "2"])
+        .write_stdin("noise\nstart\nnext\n")
+        .assert()
+        .success()
+        .stdout("6:start\n12:next\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hexdump_bytes() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-x", "-c", "25"])
+        .write_stdin(&b"Hello\n\x00\x01abcdefghijklmnopqrstuvwxyz"[..])
+        .assert()
+        .success()
+        .stdout(
+            "\
+00000000: 4865 6c6c 6f0a 0001 6162 6364 6566 6768  Hello...abcdefgh
+00000010: 696a 6b6c 6d6e 6f70 71                   ijklmnopq
+",
+        );
+    Ok(())
+}
+
+#[test]
+fn hexdump_per_file() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--hex", "-n", "1", TWO, THREE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\n\
+00000000: 5477 6f20 6c69 6e65 732e 0a              Two lines..\n\
+\n==> {} <==\n\
+00000000: 6f6e 650a                                one.\n",
+            TWO, THREE
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_headers_and_numbers() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args([
+            "--color=always",
+            "-N",
+            "--number-width",
+            "1",
+            "-n",
+            "1",
+            ONE,
+            TWO,
+        ])
+        .assert()
+        .success()
+        .stdout(format!(
+            "\x1b[1;35m==> {} <==\x1b[0m\n\x1b[32m1\x1b[0m\tÖne line, four words.\n\
+             \x1b[1;35m==> {} <==\x1b[0m\n\x1b[32m1\x1b[0m\tTwo lines.\n",
+            ONE, TWO
+        ));
+    Ok(())
+}
+
+#[test]
+fn color_highlights_matches() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--color", "--offsets", "--until", "b+"])
+        .write_stdin("a\nabbc\nd\n")
+        .assert()
+        .success()
+        .stdout("\x1b[32m0\x1b[0m:a\n\x1b[32m2\x1b[0m:a\x1b[1;31mbb\x1b[0mc\n");
+    Ok(())
+}
+
+#[test]
+fn color_never_and_auto_off_when_piped() -> HeaderResult<()> {
+    for args in [
+        &["--color=never", "-v"][..],
+        &["-v"],
+        &["--color=auto", "-v"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("==> standard input <==\na\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_json() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "json", "-n", "2", ONE, THREE, EMPTY])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{{\"file\":\"{}\",\"lines\":[\"Öne line, four words.\"],\"truncated\":false}}\n\
+             {{\"file\":\"{}\",\"lines\":[\"one\",\"two\"],\"truncated\":true}}\n\
+             {{\"file\":\"{}\",\"lines\":[],\"truncated\":false}}\n",
+            ONE, THREE, EMPTY
+        ));
+    Ok(())
+}
+
+#[test]
+fn output_json_stdin_label() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "json", "--label", "in", "-z", "-n", "1"])
+        .write_stdin("a\"b\0c\0")
+        .assert()
+        .success()
+        .stdout("{\"file\":\"in\",\"lines\":[\"a\\\"b\"],\"truncated\":true}\n");
+    Ok(())
+}
+
+#[test]
+fn output_json_conflicts_with_tail() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "json", "-t", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+#[test]
+fn output_tsv() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "tsv", "-n", "2", ONE, THREE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\t1\tÖne line, four words.\n{}\t1\tone\n{}\t2\ttwo\n",
+            ONE, THREE, THREE
+        ));
+    Ok(())
+}
+
+#[test]
+fn output_tsv_escapes_fields() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "tsv", "--label", "a\tb"])
+        .write_stdin("x\ty\\z\n")
+        .assert()
+        .success()
+        .stdout("a\\tb\t1\tx\\ty\\\\z\n");
+    Ok(())
+}
+
+#[test]
+fn output_csv() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output", "csv", "--label", "in, out"])
+        .write_stdin("plain\n\"quoted\",x\n")
+        .assert()
+        .success()
+        .stdout("\"in, out\",1,plain\n\"in, out\",2,\"\"\"quoted\"\",x\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_dir_keeps_structure() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-output-dir-{}", random_string()));
+    fs::create_dir_all(dir.join("logs/sub"))?;
+    fs::write(dir.join("logs/a.log"), "1\n2\n3\n")?;
+    fs::write(dir.join("logs/sub/b.log"), "x\ny\n")?;
+    Command::cargo_bin(PRG)?
+        .current_dir(&dir)
+        .args(["-n", "2", "-R", "logs/", "--output-dir", "samples"])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        fs::read_to_string(dir.join("samples/logs/a.log"))?,
+        "1\n2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("samples/logs/sub/b.log"))?,
+        "x\ny\n"
+    );
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn output_dir_rejects_parent() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-output-dir-{}", random_string()));
+    Command::cargo_bin(PRG)?
+        .args([
+            "--output-dir",
+            dir.to_str().unwrap(),
+            "tests/../tests/inputs/one.txt",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "refusing to write outside the output directory",
+        ));
+    assert!(!dir.exists());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_file() -> HeaderResult<()> {
+    let path = std::env::temp_dir().join(format!("header-output-{}", random_string()));
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-o", path.to_str().unwrap(), ONE, TWO])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        fs::read_to_string(&path)?,
+        format!(
+            "==> {} <==\nÖne line, four words.\n==> {} <==\nTwo lines.\n",
+            ONE, TWO
+        )
+    );
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn output_file_untouched_on_error() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-output-{}", random_string()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("out.txt");
+    fs::write(&path, "old\n")?;
+    Command::cargo_bin(PRG)?
+        .args([
+            "--fail-fast",
+            "-o",
+            path.to_str().unwrap(),
+            ONE,
+            &gen_bad_file(),
+        ])
+        .assert()
+        .failure();
+    assert_eq!(fs::read_to_string(&path)?, "old\n");
+    assert_eq!(fs::read_dir(&dir)?.count(), 1);
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn output_file_refuses_input() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--output-file", "tests/../tests/inputs/ten.txt", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "refusing to overwrite an input file",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_format_placeholders() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-n",
+            "1",
+            "--header-format",
+            "### {path} ({size} bytes) #{index} {basename} {other}",
+            THREE,
+            "-",
+        ])
+        .write_stdin("in\n")
+        .assert()
+        .success()
+        .stdout(format!(
+            "### {} (62 bytes) #1 three.txt {{other}}\none\n\n\
+             ### standard input (? bytes) #2 standard input {{other}}\nin\n",
+            THREE
+        ));
+    Ok(())
+}
+
+#[test]
+fn header_format_with_verbose() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-v", "-n", "1", "--header-format", "--- {basename}", TWO])
+        .assert()
+        .success()
+        .stdout("--- two.txt\nTwo lines.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn ensure_newline() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ensure-newline", "-n", "1", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\nÖne line, four words.\n\n==> {} <==\nTwo lines.\n",
+            ONE, TWO
+        ));
+    Ok(())
+}
+
+#[test]
+fn ensure_newline_skips_empty_output() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ensure-newline", "-z"])
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout("");
+    Command::cargo_bin(PRG)?
+        .args(["--ensure-newline", "-z"])
+        .write_stdin("a\0b")
+        .assert()
+        .success()
+        .stdout("a\0b\0");
+    Ok(())
+}
+
+#[test]
+fn no_trailing_newline() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--no-trailing-newline", "-n", "2"])
+        .write_stdin("a\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("a\nb");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn squeeze_blank() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-s", "-n", "7"])
+        .write_stdin("\n\na\n\n\n\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("\na\n\nb\n");
+    Ok(())
+}
+
+#[test]
+fn squeeze_blank_before_numbering() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--squeeze-blank", "-N", "--number-width", "1"])
+        .write_stdin("a\n\n\nb\n")
+        .assert()
+        .success()
+        .stdout("1\ta\n2\t\n3\tb\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn truncate_lines_to_columns() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--truncate-lines=5"])
+        .write_stdin("abcdef\nabcde\n中文字符串\n\tx\n")
+        .assert()
+        .success()
+        .stdout("abcd…\nabcde\n中文…\n…\n");
+    Ok(())
+}
+
+#[test]
+fn truncate_lines_defaults_to_columns_env() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .env("COLUMNS", "3")
+        .args(["--truncate-lines", "-n", "1"])
+        .write_stdin("abcdef\nnext\n")
+        .assert()
+        .success()
+        .stdout("ab…\n");
+    Ok(())
+}
+
+#[test]
+fn truncate_lines_bad_width() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--truncate-lines=0", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse column count: 0"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn show_nonprinting() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--show-nonprinting", "-n", "1"])
+        .write_stdin(&b"a\x01\x1b[31m\tb\x7f\xc3\xa9\xff\nnext\n"[..])
+        .assert()
+        .success()
+        .stdout("a^A^[[31m\tb^?M-CM-)M-^?\n");
+    Ok(())
+}
+
+#[test]
+fn show_nonprinting_keeps_nul_delimiter() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--show-nonprinting", "-z"])
+        .write_stdin("a\nb\0c\0")
+        .assert()
+        .success()
+        .stdout("a\nb\0c\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "highlight")]
+#[test]
+fn highlight_by_extension() -> HeaderResult<()> {
+    let path = std::env::temp_dir().join(format!("header-highlight-{}.rs", random_string()));
+    fs::write(&path, "fn main() {}\n")?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["--color=always", "--highlight", path.to_str().unwrap()])
+        .output()?;
+    fs::remove_file(&path)?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("\x1b[38;2;"));
+    assert!(stdout.ends_with("\x1b[0m\n"));
+    Ok(())
+}
+
+#[cfg(feature = "highlight")]
+#[test]
+fn highlight_degrades_gracefully() -> HeaderResult<()> {
+    // 无法识别的类型原样输出
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "--highlight", TWO])
+        .assert()
+        .success()
+        .stdout("Two lines.\nFour words.");
+    // 没有开启颜色时不高亮
+    Command::cargo_bin(PRG)?
+        .args(["--highlight", "--label", "main.rs"])
+        .write_stdin("fn main() {}\n")
+        .assert()
+        .success()
+        .stdout("fn main() {}\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pager_not_a_terminal() -> HeaderResult<()> {
+    // 标准输出不是终端时不启动分页程序
+    Command::cargo_bin(PRG)?
+        .env("PAGER", "false")
+        .args(["--pager", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "==> {} <==\nÖne line, four words.\n==> {} <==\nTwo lines.\nFour words.",
+            ONE, TWO
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_pager_with_output_file() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--pager", "-o", "out.txt", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn print0() -> HeaderResult<()> {
+    // 最后一条没有分隔符的记录也以 NUL 结尾
+    Command::cargo_bin(PRG)?
+        .args(["--print0", TWO])
+        .assert()
+        .success()
+        .stdout("Two lines.\0Four words.\0");
+    Command::cargo_bin(PRG)?
+        .args(["--print0", "--delimiter", "\\r\\n", "-n", "2"])
+        .write_stdin("a\r\nb\rc\r\nd\r\n")
+        .assert()
+        .success()
+        .stdout("a\0b\rc\0");
+    // 分隔符的前缀与自身重叠时，失配后从仍然可能匹配的位置继续
+    for (delimiter, input, expected) in [("aab", "aaab", "a\0"), ("ababc", "abababcx", "ab\0x\0")] {
+        Command::cargo_bin(PRG)?
+            .args(["--print0", "--delimiter", delimiter])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn print0_zero_terminated() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "--print0", "-n", "1", "--number"])
+        .write_stdin("a\nb\0c\0")
+        .assert()
+        .success()
+        .stdout("     1\ta\nb\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "-n", "1", ONE, TWO])
+        .assert()
+        .success()
+        .stderr(format!(
+            "      1      22 {}\n      1      11 {}\n      2      33 total\n",
+            ONE, TWO
+        ));
+    // 统计的是格式化之后实际输出的内容
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "--number", TWO])
+        .assert()
+        .success()
+        .stderr(format!("      2      36 {}\n", TWO));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn mmap_matches_buffered() -> HeaderResult<()> {
+    for count in ["1", "4", "-2", "2-5", "50%", "1000"] {
+        let expected = Command::cargo_bin(PRG)?
+            .args(["-c", count, ONE])
+            .output()?
+            .stdout;
+        Command::cargo_bin(PRG)?
+            .args(["--mmap", "-c", count, ONE])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    // 空文件和标准输入退回到按缓冲读取
+    Command::cargo_bin(PRG)?
+        .args(["--mmap", "-c", "3", EMPTY])
+        .assert()
+        .success()
+        .stdout("");
+    Command::cargo_bin(PRG)?
+        .args(["--mmap", "-c", "3"])
+        .write_stdin("abcdef")
+        .assert()
+        .success()
+        .stdout("abc");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_keeps_argument_order() -> HeaderResult<()> {
+    let args = [ONE, TWO, THREE, EMPTY, ONE, THREE, TWO];
+    let expected = Command::cargo_bin(PRG)?.args(args).output()?.stdout;
+    Command::cargo_bin(PRG)?
+        .args(["--jobs", "4"])
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[test]
+fn jobs_reports_errors_in_order() -> HeaderResult<()> {
+    let bad = random_string();
+    Command::cargo_bin(PRG)?
+        .args(["-j", "3", ONE, &bad, TWO])
+        .assert()
+        .failure()
+        .stderr(format!(
+            "Failed to open file: {}: No such file or directory (os error 2)\n",
+            bad
+        ))
+        .stdout(format!(
+            "==> {} <==\nÖne line, four words.\n==> {} <==\nTwo lines.\nFour words.",
+            ONE, TWO
+        ));
+    Command::cargo_bin(PRG)?
+        .args(["--jobs", "0", ONE])
+        .assert()
+        .failure()
+        .stderr("Failed to parse jobs: 0: illegal number: 0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lines_keep_invalid_utf8() -> HeaderResult<()> {
+    // 与 GNU head 一样原样输出非法的 UTF-8
+    for args in [
+        &["-n", "2"][..],
+        &["--offsets", "-n", "2"],
+        &["--sample", "3"],
+    ] {
+        let expected: &[u8] = match args[0] {
+            "--offsets" => b"0:a\xffb\n4:c\xfe\n",
+            "--sample" => b"a\xffb\nc\xfe\nd\n",
+            _ => b"a\xffb\nc\xfe\n",
+        };
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin(&b"a\xffb\nc\xfe\nd\n"[..])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_range_redirected_to_file() -> HeaderResult<()> {
+    // 标准输出是普通文件时内容可能由内核直接复制，标题仍然排在前面
+    let path = std::env::temp_dir().join(format!("header-copy-{}", random_string()));
+    let status = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-v", "-c", "-5", TEN])
+        .stdout(File::create(&path)?)
+        .status()?;
+    let output = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+    assert!(status.success());
+    let content = fs::read_to_string(TEN)?;
+    assert_eq!(
+        output,
+        format!("==> {} <==\n{}", TEN, &content[..content.len() - 5])
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_byte_count_streams() -> HeaderResult<()> {
+    // 不会按计数一次分配缓冲区
+    Command::cargo_bin(PRG)?
+        .args(["-c", "10000000000", TWO])
+        .assert()
+        .success()
+        .stdout("Two lines.\nFour words.");
+    // 分块读取时跨越两块的字符仍然完整输出
+    let input = format!("{}{}", "a".repeat(65535), "中文".repeat(10));
+    Command::cargo_bin(PRG)?
+        .args(["-c", "65541"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(input[..65541].to_string());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[test]
+fn io_backend_uring() -> HeaderResult<()> {
+    // 结果与默认的读取方式相同，压缩文件和标准输入也照常处理
+    let args = [ONE, "-", TEN, "./tests/inputs/ten.txt.gz", THREE];
+    let expected = Command::cargo_bin(PRG)?
+        .args(args)
+        .write_stdin("stdin\n")
+        .output()?
+        .stdout;
+    Command::cargo_bin(PRG)?
+        .args(["--io-backend", "uring"])
+        .args(args)
+        .write_stdin("stdin\n")
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[test]
+fn io_backend_uring_falls_back() -> HeaderResult<()> {
+    // 文件描述符不够预先打开整批文件时，其余的文件按原来的方式逐个打开，结果不变
+    let args: Vec<&str> = [ONE, TEN, THREE].repeat(40);
+    let expected = Command::cargo_bin(PRG)?.args(&args).output()?.stdout;
+    let output = std::process::Command::new("sh")
+        .args(["-c", r#"ulimit -n 32 && exec "$0" --io-backend uring "$@""#])
+        .arg(assert_cmd::cargo::cargo_bin(PRG))
+        .args(&args)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_memory_spills_to_disk() -> HeaderResult<()> {
+    // 暂存的内容超过 --max-memory 时写到临时文件中，结果不变
+    let input: String = (1..=5000).map(|i| format!("{}\n", i)).collect();
+    let lines: Vec<&str> = input.split_inclusive('\n').collect();
+    Command::cargo_bin(PRG)?
+        .args(["-n", "-1000", "--max-memory", "100"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(lines[..4000].concat());
+    Command::cargo_bin(PRG)?
+        .args(["-c", "-2000", "--max-memory", "1K"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(input[..input.len() - 2000].to_string());
+    Ok(())
+}
+
+#[test]
+fn dies_bad_max_memory() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "lots", ONE])
+        .assert()
+        .failure()
+        .stderr("Failed to parse memory limit: lots: illegal number: lots\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn utf8_boundary() -> HeaderResult<()> {
+    // 在 "€" 的中间截断
+    let input = "aé€b";
+    for (flag, expected) in [
+        ("--utf8-boundary", "aé"),
+        ("--utf8-boundary=back", "aé"),
+        ("--utf8-boundary=forward", "aé€"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["-c", "4", flag])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    // 截断位置本来就在字符边界上时不变
+    Command::cargo_bin(PRG)?
+        .args(["-c", "3", "--utf8-boundary=forward"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("aé");
+    // 普通文件通过内存映射读取时结果相同
+    for (flag, expected) in [("--utf8-boundary", ""), ("--utf8-boundary=forward", "Ö")] {
+        Command::cargo_bin(PRG)?
+            .args(["--mmap", "-c", "1", flag, ONE])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn dies_utf8_boundary_without_bytes() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--utf8-boundary", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--bytes <BYTES>"));
+    Ok(())
+}
+
+// --------------------------------------------------
+// 下游提前关闭管道时像其他 coreutils 一样被 SIGPIPE 结束，不输出错误
+#[cfg(unix)]
+#[test]
+fn broken_pipe_is_quiet() -> HeaderResult<()> {
+    use std::{
+        io::Write,
+        os::unix::process::ExitStatusExt,
+        process::{Command as StdCommand, Stdio},
+    };
+
+    for args in [["-n", "10000000"], ["-c", "100000000"]] {
+        let mut child = StdCommand::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || {
+            let line = "y\n".repeat(4096);
+            while stdin.write_all(line.as_bytes()).is_ok() {}
+        });
+        let mut first = [0; 2];
+        child.stdout.take().unwrap().read_exact(&mut first)?;
+        let output = child.wait_with_output()?;
+        writer.join().unwrap();
+        assert_eq!(output.status.signal(), Some(libc::SIGPIPE));
+        assert!(output.stderr.is_empty());
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn gnu_errors() -> HeaderResult<()> {
+    let bad = gen_bad_file();
+    let expected = format!(
+        "header: cannot open '{}' for reading: No such file or directory\n",
+        &bad
+    );
+    Command::cargo_bin(PRG)?
+        .args(["--gnu-errors", &bad, ONE])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("==> ./tests/inputs/one.txt <=="))
+        .stderr(expected.clone());
+    Command::cargo_bin(PRG)?
+        .args(["--gnu-errors", "--fail-fast", &bad, ONE])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(expected);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn gnu_errors_reading_directory() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--gnu-errors", "tests/inputs/", ONE])
+        .assert()
+        .code(1)
+        .stderr("header: error reading 'tests/inputs/': Is a directory\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn compat_bsd_errors() -> HeaderResult<()> {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--compat", "bsd", &bad, ONE])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("==> ./tests/inputs/one.txt <=="))
+        .stderr(format!("header: {}: No such file or directory\n", &bad));
+    Command::cargo_bin(PRG)?
+        .args(["--compat", "gnu", &bad])
+        .assert()
+        .code(1)
+        .stderr(format!(
+            "header: cannot open '{}' for reading: No such file or directory\n",
+            &bad
+        ));
+    Ok(())
+}
+
+#[test]
+fn compat_counts() -> HeaderResult<()> {
+    run(
+        &["--compat", "bsd", "-n", "2", TEN],
+        "tests/expected/ten.txt.n2.out",
+    )?;
+    for (args, expected) in [
+        (
+            ["--compat", "bsd", "-n", "-3"],
+            "header: illegal line count -- -3\n",
+        ),
+        (
+            ["--compat", "posix", "-n", "0"],
+            "header: illegal line count -- 0\n",
+        ),
+        (
+            ["--compat", "bsd", "-c", "2-4"],
+            "header: illegal byte count -- 2-4\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .failure()
+            .stderr(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn compat_usage_errors() -> HeaderResult<()> {
+    let cases: [(&[&str], &str); 5] = [
+        (
+            &["--compat", "gnu", "-n", "abc"],
+            "header: invalid number of lines: 'abc'\n",
+        ),
+        (
+            &["--compat", "gnu", "-c", "1x"],
+            "header: invalid number of bytes: '1x'\n",
+        ),
+        (
+            &["-n", "abc", "--gnu-errors"],
+            "header: invalid number of lines: 'abc'\n",
+        ),
+        (
+            &["--compat", "bsd", "-n", "abc"],
+            "header: illegal line count -- abc\n",
+        ),
+        (
+            &["--compat", "posix", "-c", "abc"],
+            "header: illegal byte count -- abc\n",
+        ),
+    ];
+    for (args, expected) in cases {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .code(1)
+            .stdout("")
+            .stderr(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn compat_posix() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--compat", "posix", "-q", ONE, TWO])
+        .assert()
+        .failure()
+        .stderr("header: -q is not available with --compat posix\n");
+    Command::cargo_bin(PRG)?
+        .args(["--compat", "posix", "-v", ONE])
+        .env("POSIXLY_CORRECT", "1")
+        .assert()
+        .failure()
+        .stderr("header: -v is not available with --compat posix\n");
+    Ok(())
+}
+
+#[test]
+fn posixly_correct() -> HeaderResult<()> {
+    // 与 GNU head 一样，POSIXLY_CORRECT 不影响 -q、-v、-n 0 和 -n -K
+    Command::cargo_bin(PRG)?
+        .args(["-v", "-n", "-2", ONE])
+        .env("POSIXLY_CORRECT", "1")
+        .assert()
+        .success()
+        .stdout("==> ./tests/inputs/one.txt <==\n");
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-n", "0", ONE, TWO])
+        .env("POSIXLY_CORRECT", "1")
+        .assert()
+        .success()
+        .stdout("");
+    // 错误信息按 GNU head 的格式
+    Command::cargo_bin(PRG)?
+        .args(["-n", "abc", ONE])
+        .env("POSIXLY_CORRECT", "1")
+        .assert()
+        .code(1)
+        .stderr("header: invalid number of lines: 'abc'\n");
+    // --compat 优先于环境变量
+    Command::cargo_bin(PRG)?
+        .args(["--compat", "bsd", "-n", "abc", ONE])
+        .env("POSIXLY_CORRECT", "1")
+        .assert()
+        .code(1)
+        .stderr("header: illegal line count -- abc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn counts_beyond_32_bits() -> HeaderResult<()> {
+    run(&[TEN, "-n", "4294967296"], TEN)?;
+    run(&[TEN, "-c", "18446744073709551615"], TEN)?;
+    run(&[TEN, "-c", "5G"], TEN)
+}
+
+#[test]
+fn dies_count_overflow() -> HeaderResult<()> {
+    for (flag, value) in [
+        ("-c", "99999999999999999999"),
+        ("-n", "16E"),
+        ("-c", "2-99999999999999999999"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args([flag, value, TEN])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("number too large"));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn crlf() -> HeaderResult<()> {
+    let input = "a\r\nb\rc\r\nd\r";
+    for (mode, args, expected) in [
+        ("keep", ["-n", "2"], "a\r\nb\rc\r\n"),
+        ("strip", ["-n", "2"], "a\nb\rc\n"),
+        ("strip", ["-c", "5"], "a\nb\rc"),
+        ("convert", ["-n", "3"], "a\nb\nc\n"),
+        ("convert", ["-n", "9"], "a\nb\nc\nd\n"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--crlf", mode])
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn dies_bad_crlf() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--crlf", "unix", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "invalid value 'unix' for '--crlf <MODE>'",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_are_raw() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "4"])
+        .write_stdin(&b"a\xffb\xc3\xa9"[..])
+        .assert()
+        .success()
+        .stdout(&b"a\xffb\xc3"[..]);
+    for args in [["-c", "1"], ["--mmap", "-c1"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(ONE)
+            .assert()
+            .success()
+            .stdout(&b"\xc3"[..]);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn directory_argument() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", ONE])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("Öne line, four words."))
+        .stderr("header: tests/inputs: Is a directory\n");
+    Command::cargo_bin(PRG)?
+        .args(["--fail-fast", "tests/inputs", ONE])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr("tests/inputs: Is a directory\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_line_bytes() -> HeaderResult<()> {
+    for (args, input, expected) in [
+        (
+            &["--offsets", "-n", "2"][..],
+            "abcdefghij\nklm\nnop\n",
+            "0:abcdefghij\n11:klm\n",
+        ),
+        (
+            &["--step", "2", "-n", "2"],
+            "abcdefghij\nklm\nnop\n",
+            "abcdefghij\nnop\n",
+        ),
+        (
+            &["--offsets", "-n", "-1"],
+            "abcdefghij\nklm\nnop\n",
+            "0:abcdefghij\n11:klm\n",
+        ),
+        (
+            &["--delimiter", "XY", "-n", "2"],
+            "abcXYdefXYghiXY",
+            "abcXYdefXY",
+        ),
+        (
+            &["--delimiter", "XY", "--skip", "1", "-n", "1"],
+            "abcXYdefXYghiXY",
+            "defXY",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--max-line-bytes", "3"])
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn max_line_bytes_other_modes() -> HeaderResult<()> {
+    // 这些模式同样分块读取超长的行，结果与整行读取时相同
+    for (args, input, expected) in [
+        (
+            &["--graphemes", "5"][..],
+            "e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}\n",
+            "e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}",
+        ),
+        (&["--graphemes", "3"], "👍🏽ab\ny", "👍🏽ab"),
+        (
+            &["--paragraphs", "2"],
+            "\nfirst line\n\n      \nsecond line\n\n\nthird\n",
+            "\nfirst line\n\n      \nsecond line\n",
+        ),
+        (
+            &["--record-start", "^#", "-n", "2"],
+            "preamble\n#1 abcdef\ncontinued\n#2 x\n",
+            "preamble\n#1 abcdef\ncontinued\n",
+        ),
+        (
+            &["--tail", "-n", "2", "--max-memory", "4"],
+            "abcdefghij\nklmnop\nqrs\n",
+            "klmnop\nqrs\n",
+        ),
+        (
+            &["--sample", "5"],
+            "abcdefghij\nklmnop\nqrs\n",
+            "abcdefghij\nklmnop\nqrs\n",
+        ),
+        (
+            &["--until", "^kl"],
+            "abcdefghij\nklmnop\nqrs\n",
+            "abcdefghij\nklmnop\n",
+        ),
+        (
+            &["--until", "^kl", "--until-exclusive"],
+            "abcdefghij\nklmnop\nqrs\n",
+            "abcdefghij\n",
+        ),
+        (
+            &["--from", "^kl"],
+            "abcdefghij\nklmnop\nqrs\n",
+            "klmnop\nqrs\n",
+        ),
+        // 分隔符跨在两次读取之间
+        (&["--delimiter", "XY", "--from", "^d"], "abXYdefXY", "defXY"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--max-line-bytes", "3"])
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn ndjson_record_longer_than_max_line_bytes() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--ndjson", "--max-line-bytes", "10"])
+        .write_stdin("{\"a\":1}\n{\"bbbbbbbbbb\":2}\n")
+        .assert()
+        .failure()
+        .stdout("{\"a\":1}\n")
+        .stderr(predicate::str::contains(
+            "JSON record on line 2 is longer than --max-line-bytes (10 bytes)",
+        ));
+    Ok(())
+}
+
+#[test]
+fn dies_max_line_bytes_zero() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--max-line-bytes", "0", TEN])
+        .assert()
+        .failure()
+        .stderr("Failed to parse line limit: 0: illegal number: 0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn eol() -> HeaderResult<()> {
+    let input = "a\r\nb\nc\rd\n";
+    for (eol, expected) in [
+        ("lf", "a\nb\nc\rd\n"),
+        ("crlf", "a\r\nb\r\nc\rd\r\n"),
+        ("cr", "a\rb\rc\rd\r"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["--eol", eol])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Command::cargo_bin(PRG)?
+        .args(["--eol", "crlf", "-n", "2", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(
+            "==> ./tests/inputs/one.txt <==\r\nÖne line, four words.\r\n\
+             ==> ./tests/inputs/two.txt <==\r\nTwo lines.\r\nFour words.",
+        );
+    // 结尾单独的 \r 在处理完时原样写出
+    Command::cargo_bin(PRG)?
+        .args(["--eol", "crlf"])
+        .write_stdin("a\nb\r")
+        .assert()
+        .success()
+        .stdout("a\r\nb\r");
+    Ok(())
+}
+
+#[test]
+fn dies_eol_with_print0() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .args(["--eol", "crlf", "--print0", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_tty_warning_for_piped_stdin() -> HeaderResult<()> {
+    // 测试中的标准输入是管道，不是终端，因此不会输出提示
+    for args in [&[][..], &["--no-tty-warning"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin("a\n")
+            .assert()
+            .success()
+            .stdout("a\n")
+            .stderr("");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn env_defaults() -> HeaderResult<()> {
+    let expected = |file: &str| -> HeaderResult<String> { Ok(String::from_utf8(fs::read(file)?)?) };
+    // 环境变量提供默认值，命令行上的选项优先
+    for (name, value, args, file) in [
+        (
+            "HEADER_LINES",
+            "2",
+            &[][..],
+            "tests/expected/ten.txt.n2.out",
+        ),
+        (
+            "HEADER_LINES",
+            "2",
+            &["-n", "4"],
+            "tests/expected/ten.txt.n4.out",
+        ),
+        ("HEADER_BYTES", "2", &[], "tests/expected/ten.txt.c2.out"),
+        (
+            "HEADER_BYTES",
+            "2",
+            &["-c", "4"],
+            "tests/expected/ten.txt.c4.out",
+        ),
+        (
+            "HEADER_BYTES",
+            "2",
+            &["-n", "4"],
+            "tests/expected/ten.txt.n4.out",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .env(name, value)
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .success()
+            .stdout(expected(file)?);
+    }
+    // 命令行上选择了其他计数方式时 HEADER_BYTES 不起作用
+    for (args, expected) in [
+        (&["-w", "1"][..], "Three"),
+        (&["--tail", "-n", "1"], "four words."),
+        (&["--skip", "2"], "four words."),
+        (&["--chars", "3"], "Thr"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .env("HEADER_BYTES", "2")
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .success()
+            .stderr("")
+            .stdout(expected);
+    }
+    // 命令行上的其他错误不会因为 HEADER_BYTES 而被掩盖
+    Command::cargo_bin(PRG)?
+        .env("HEADER_BYTES", "2")
+        .args(["-n", "1", "-c", "4", "--tail", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Command::cargo_bin(PRG)?
+        .env("HEADER_COLOR", "always")
+        .args(["-v", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("\x1b["));
+    Command::cargo_bin(PRG)?
+        .env("HEADER_COLOR", "always")
+        .args(["--color=never", "-v", "-n", "1", TEN])
+        .assert()
+        .success()
+        .stdout("==> ./tests/inputs/ten.txt <==\nThree\n");
+    Ok(())
+}
+
+#[test]
+fn dies_bad_env_defaults() -> HeaderResult<()> {
+    // 值有误的环境变量与有误的选项一样报错，不论命令行上是否给出了对应的选项
+    for (name, value, args, stderr) in [
+        (
+            "HEADER_LINES",
+            "x",
+            &[][..],
+            "Failed to parse HEADER_LINES: x: illegal number: x\n",
+        ),
+        (
+            "HEADER_BYTES",
+            "4X",
+            &[],
+            "Failed to parse HEADER_BYTES: 4X: invalid suffix 'X'\n",
+        ),
+        (
+            "HEADER_BYTES",
+            "abc",
+            &["-n", "2"],
+            "Failed to parse HEADER_BYTES: abc: illegal number: abc\n",
+        ),
+        (
+            "HEADER_COLOR",
+            "purple",
+            &["--color=never"],
+            "Failed to parse HEADER_COLOR: purple: expected auto, always or never\n",
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .env(name, value)
+            .args(args)
+            .arg(TEN)
+            .assert()
+            .failure()
+            .code(1)
+            .stdout("")
+            .stderr(stderr);
+    }
+    // 命令行上的值有误时报告的是选项
+    Command::cargo_bin(PRG)?
+        .env("HEADER_LINES", "2")
+        .args(["-n", "x", TEN])
+        .assert()
+        .failure()
+        .stderr("Failed to parse lines count: x: illegal number: x\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn profile() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-profile-{}", random_string()));
+    fs::create_dir_all(&dir)?;
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        "[profile.logs]\nlines = 2\nverbose = true\nnumber = false\n\n\
+         [profile.bytes]\nbytes = \"-4\"\n",
+    )?;
+    Command::cargo_bin(PRG)?
+        .env("HEADER_CONFIG", &config)
+        .args(["--profile", "logs", TEN])
+        .assert()
+        .success()
+        .stdout("==> ./tests/inputs/ten.txt <==\nThree\nlines,\n");
+    // 命令行上的选项覆盖预设
+    Command::cargo_bin(PRG)?
+        .env("HEADER_CONFIG", &config)
+        .args(["--profile", "logs", "-n", "1", "-q", TEN])
+        .assert()
+        .success()
+        .stdout("Three\n");
+    Command::cargo_bin(PRG)?
+        .env("HEADER_CONFIG", &config)
+        .args(["--profile", "bytes", TEN])
+        .assert()
+        .success()
+        .stdout(fs::read("tests/expected/ten.txt.c-4.out")?);
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn dies_bad_profile() -> HeaderResult<()> {
+    let dir = std::env::temp_dir().join(format!("header-bad-profile-{}", random_string()));
+    fs::create_dir_all(&dir)?;
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        "[profile.typo]\nlinez = 2\n\n[profile.list]\nlines = [1, 2]\n",
+    )?;
+    let path = config.display();
+    for (name, expected) in [
+        ("nope", format!("No profile 'nope' in {}\n", path)),
+        (
+            "typo",
+            format!("Unknown option 'linez' in profile 'typo' ({})\n", path),
+        ),
+        (
+            "list",
+            format!(
+                "Unsupported value for 'lines' in profile 'list' ({})\n",
+                path
+            ),
+        ),
+    ] {
+        Command::cargo_bin(PRG)?
+            .env("HEADER_CONFIG", &config)
+            .args(["--profile", name, TEN])
+            .assert()
+            .failure()
+            .stderr(expected);
+    }
+    fs::write(&config, "[profile\n")?;
+    Command::cargo_bin(PRG)?
+        .env("HEADER_CONFIG", &config)
+        .args(["--profile", "logs", TEN])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(format!(
+            "Invalid config file: {}",
+            path
+        )));
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn version() -> HeaderResult<()> {
+    Command::cargo_bin(PRG)?
+        .arg("-V")
+        .assert()
+        .success()
+        .stdout(format!("header {}\n", env!("CARGO_PKG_VERSION")));
+    // --version 还输出构建信息，便于在问题报告中确定是哪个二进制文件
+    let output = Command::cargo_bin(PRG)?.arg("--version").output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], format!("header {}", env!("CARGO_PKG_VERSION")));
+    // 构建日期只在设置了 SOURCE_DATE_EPOCH 时输出
+    let labels: Vec<&str> = lines[1..]
+        .iter()
+        .map(|line| line.split_once(": ").map_or(*line, |(label, _)| label))
+        .collect();
+    match option_env!("SOURCE_DATE_EPOCH") {
+        Some(_) => assert_eq!(labels, ["commit", "built", "features", "target"]),
+        None => assert_eq!(labels, ["commit", "features", "target"]),
+    }
+    // 帮助中说明两者的区别
+    Command::cargo_bin(PRG)?
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Print version (see build details with '--version')",
+        ));
+    Ok(())
+}